@@ -0,0 +1,126 @@
+//! SGR styling of text for a terminal
+
+use crate::Error;
+
+const COLORS: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+/// Whether to style the output of a stream, `is_tty` unless `NO_COLOR` is set
+/// or `CLICOLOR` is 0, `env` reads a variable
+pub fn enabled(is_tty: bool, env: impl Fn(&str) -> Option<String>) -> bool {
+    let no_color = env("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let clicolor_off = env("CLICOLOR").is_some_and(|value| value == "0");
+    is_tty && !no_color && !clicolor_off
+}
+
+/// Parameters of a color, `base` is 30 for the foreground and 40 for the background
+fn color(name: &str, base: u8) -> Result<String, Error> {
+    let index = |name| COLORS.iter().position(|known| *known == name);
+    if let Some(hex) = name.strip_prefix('#')
+        && hex.len() == 6
+        && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        && let Ok(rgb) = u32::from_str_radix(hex, 16)
+    {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        return Ok(format!("{};2;{r};{g};{b}", base + 8));
+    }
+    if let Some(i) = name.strip_prefix("bright_").and_then(index) {
+        return Ok((base + 60 + i as u8).to_string());
+    }
+    match index(name) {
+        Some(i) => Ok((base + i as u8).to_string()),
+        None => Err(Error::InvalidArgument(format!(
+            "unknown color {name:?}, expected one of {}, bright_<color> or #rrggbb",
+            COLORS.join(", "),
+        ))),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Style<'a> {
+    pub fg: Option<&'a str>,
+    pub bg: Option<&'a str>,
+    pub bold: bool,
+    pub underline: bool,
+}
+
+impl Style<'_> {
+    /// The SGR sequence, empty without any attribute
+    pub fn sequence(&self) -> Result<String, Error> {
+        let mut params = vec![];
+        if self.bold {
+            params.push("1".to_owned());
+        }
+        if self.underline {
+            params.push("4".to_owned());
+        }
+        if let Some(fg) = self.fg {
+            params.push(color(fg, 30)?);
+        }
+        if let Some(bg) = self.bg {
+            params.push(color(bg, 40)?);
+        }
+        if params.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(format!("\x1b[{}m", params.join(";")))
+    }
+
+    /// `text` styled and reset, as is when not `enabled`, an invalid color is an error either way
+    pub fn apply(&self, text: &str, enabled: bool) -> Result<String, Error> {
+        let sequence = self.sequence()?;
+        if !enabled || sequence.is_empty() {
+            return Ok(text.into());
+        }
+        Ok(format!("{sequence}{text}\x1b[0m"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(fg: Option<&str>, bg: Option<&str>, bold: bool) -> String {
+        Style { fg, bg, bold, underline: false }.apply("hi", true).unwrap()
+    }
+
+    #[test]
+    fn colors_when_forced() {
+        assert_eq!(styled(Some("red"), None, false), "\x1b[31mhi\x1b[0m");
+        assert_eq!(styled(None, Some("white"), false), "\x1b[47mhi\x1b[0m");
+        assert_eq!(styled(Some("bright_cyan"), Some("black"), true), "\x1b[1;96;40mhi\x1b[0m");
+        assert_eq!(styled(Some("#ff8000"), Some("#00A0ff"), false), "\x1b[38;2;255;128;0;48;2;0;160;255mhi\x1b[0m");
+        let all = Style { fg: Some("green"), bg: None, bold: true, underline: true };
+        assert_eq!(all.sequence().unwrap(), "\x1b[1;4;32m");
+        // nothing to apply
+        assert_eq!(Style::default().apply("hi", true).unwrap(), "hi");
+
+        let style = |force| {
+            let line = format!(r##"{{"style": {{"text": "x", "fg": "#010203", "force": {force}}}}}"##);
+            crate::Command::parse(&line).unwrap().run(&mut crate::Context::default()).unwrap()
+        };
+        assert_eq!(style(true), "\x1b[38;2;1;2;3mx\x1b[0m");
+        assert_eq!(style(false), "x");
+
+        for invalid in ["purple", "#12345", "#gggggg", "bright_", "Red"] {
+            let e = Style { fg: Some(invalid), ..Default::default() }.apply("hi", false).unwrap_err();
+            assert!(matches!(e, Error::InvalidArgument(_)), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn plain_without_color() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        };
+        assert!(enabled(true, env(&[])));
+        assert!(!enabled(false, env(&[])));
+        assert!(!enabled(true, env(&[("NO_COLOR", "1")])));
+        // an empty NO_COLOR does not count
+        assert!(enabled(true, env(&[("NO_COLOR", "")])));
+        assert!(!enabled(true, env(&[("CLICOLOR", "0")])));
+        assert!(enabled(true, env(&[("CLICOLOR", "1")])));
+
+        let style = Style { fg: Some("red"), bold: true, ..Default::default() };
+        assert_eq!(style.apply("plain", enabled(true, env(&[("NO_COLOR", "1")]))).unwrap(), "plain");
+    }
+}
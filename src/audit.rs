@@ -0,0 +1,247 @@
+//! Log of the commands and their answers, `--log`
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{lock, Error};
+
+/// Results of `commands` longer than this are omitted
+pub const MAX_RESULT: usize = 1024;
+
+/// Parts of field names whose values are not logged
+const SECRET_KEYS: &[&str] = &[
+    "password", "passwd", "token", "secret", "api_key", "apikey", "authorization", "cookie",
+];
+
+/// Commands whose results are environment values
+const ENV_COMMANDS: &[&str] = &["get_env", "env_vars", "set_env", "load_dotenv"];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Full,
+    /// Results over [`MAX_RESULT`] bytes are replaced by their length
+    Commands,
+    Errors,
+}
+
+impl Level {
+    pub fn by_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "full" => Ok(Level::Full),
+            "commands" => Ok(Level::Commands),
+            "errors" => Ok(Level::Errors),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown log level {name:?}, expected full, commands or errors",
+            ))),
+        }
+    }
+}
+
+/// JSON lines appended to a file, a failed write is reported once and ignored
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+    level: Level,
+    failed: AtomicBool,
+}
+
+impl AuditLog {
+    pub fn open(path: impl AsRef<Path>, level: Level) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file: Mutex::new(file), level, failed: AtomicBool::new(false) })
+    }
+
+    /// Log the command line `raw` and its `answer`
+    pub fn record(&self, raw: &str, answer: &Value, elapsed: Duration) {
+        let failed = answer.get("err").is_some();
+        if self.level == Level::Errors && !failed {
+            return;
+        }
+        let mut command = serde_json::from_str(raw).unwrap_or_else(|_| Value::from(raw));
+        let name = command_name(&command).map(str::to_owned);
+        let is_env = name.as_deref().is_some_and(|name| ENV_COMMANDS.contains(&name));
+        redact_command(&mut command);
+
+        let mut result = answer.get("ok").cloned().unwrap_or(Value::Null);
+        if is_env {
+            result = REDACTED.into();
+        } else {
+            redact(&mut result, false);
+        }
+        let mut entry = json!({
+            "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "command": command,
+            "elapsed_ms": elapsed.as_secs_f64() * 1000.0,
+        });
+        if let Some(err) = answer.get("err") {
+            entry["err"] = err.clone();
+        } else {
+            let len = result.to_string().len();
+            entry["ok"] = if self.level == Level::Commands && len > MAX_RESULT {
+                json!({"omitted_bytes": len})
+            } else {
+                result
+            };
+        }
+        let mut line = entry.to_string();
+        line.push('\n');
+        if let Err(e) = lock(&self.file).write_all(line.as_bytes())
+            && !self.failed.swap(true, Ordering::Relaxed)
+        {
+            eprintln!("jq-bridge: cannot write the log, no longer logging: {e}");
+        }
+    }
+}
+
+/// Name of a bare or tagged command
+fn command_name(command: &Value) -> Option<&str> {
+    match command {
+        Value::String(name) => Some(name),
+        Value::Object(map) => match map.get("cmd") {
+            Some(cmd) if map.contains_key("id") => command_name(cmd),
+            _ => map.keys().next().map(String::as_str),
+        },
+        _ => None,
+    }
+}
+
+/// Replace the secrets of a bare or tagged command, also the value of `set_env`
+pub fn redact_command(command: &mut Value) {
+    redact(command, false);
+    if command_name(command) == Some("set_env") {
+        let cmd = if command.get("id").is_some() { &mut command["cmd"] } else { command };
+        if let Some(value) = cmd["set_env"].get_mut(1) {
+            *value = REDACTED.into();
+        }
+    }
+}
+
+/// Replace the values of secret fields, or all the strings when `all`,
+/// as of the `envs` of a process
+fn redact(value: &mut Value, all: bool) {
+    match value {
+        Value::String(s) if all => *s = REDACTED.into(),
+        Value::Array(values) => values.iter_mut().for_each(|value| redact(value, all)),
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.to_lowercase();
+                if SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+                    *value = REDACTED.into();
+                } else {
+                    redact(value, all || key == "envs");
+                }
+            }
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bridge::{run_bridge, BridgeOptions},
+        Context,
+    };
+
+    /// Entries logged at `level` of a session of `lines`
+    fn logged(name: &str, level: Level, lines: &[Value]) -> Vec<Value> {
+        let path = std::env::temp_dir().join(format!("jq-bridge-audit-{name}-{}", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let opts = BridgeOptions { log: Some(AuditLog::open(&path, level).unwrap()), ..Default::default() };
+        let input: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        run_bridge(input.as_bytes(), io::sink(), &mut Context::default(), &opts).unwrap();
+        drop(opts);
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut entries: Vec<Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        // tagged commands are answered by their threads, put last
+        entries.sort_by_key(|entry| entry["command"].get("id").is_some());
+        entries
+    }
+
+    fn session() -> Vec<Value> {
+        vec![
+            json!({"echo": {"user": "me", "password": "hunter2", "nested": [{"Api_Key": "k"}]}}),
+            json!({"echo": {"envs": {"PATH": "/bin", "N": 1}, "args": ["kept"]}}),
+            json!({"get_env": "HOME"}),
+            json!({"id": 7, "cmd": {"env_vars": {"prefix": "PATH"}}}),
+            json!({"read": "/nonexistent/jq-bridge-audit"}),
+            json!({"echo": "x".repeat(2 * MAX_RESULT)}),
+        ]
+    }
+
+    #[test]
+    fn full_session() {
+        let entries = logged("full", Level::Full, &session());
+        assert_eq!(entries.len(), 6);
+        for entry in &entries {
+            assert!(OffsetDateTime::parse(entry["time"].as_str().unwrap(), &Rfc3339).is_ok(), "{entry}");
+            assert!(entry["elapsed_ms"].as_f64().unwrap() >= 0.0);
+        }
+        let redacted = json!({"user": "me", "password": REDACTED, "nested": [{"Api_Key": REDACTED}]});
+        assert_eq!(entries[0]["command"], json!({"echo": redacted}));
+        assert_eq!(entries[0]["ok"], redacted);
+        let envs = json!({"envs": {"PATH": REDACTED, "N": 1}, "args": ["kept"]});
+        assert_eq!(entries[1]["ok"], envs);
+        assert_eq!(entries[2]["command"], json!({"get_env": "HOME"}));
+        assert_eq!(entries[2]["ok"], REDACTED);
+        assert_eq!(entries[3]["err"]["kind"], "not_found");
+        assert!(entries[3].get("ok").is_none());
+        assert_eq!(entries[4]["ok"].as_str().map(str::len), Some(2 * MAX_RESULT));
+        assert_eq!(entries[5]["command"], json!({"id": 7, "cmd": {"env_vars": {"prefix": "PATH"}}}));
+        assert_eq!(entries[5]["ok"], REDACTED);
+    }
+
+    #[test]
+    fn levels() {
+        let entries = logged("commands", Level::Commands, &session());
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[4]["ok"], json!({"omitted_bytes": 2 * MAX_RESULT + 2}));
+        assert_eq!(entries[0]["ok"]["user"], "me");
+
+        let entries = logged("errors", Level::Errors, &session());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["command"], json!({"read": "/nonexistent/jq-bridge-audit"}));
+        assert_eq!(entries[0]["err"]["kind"], "not_found");
+        assert!(Level::by_name("verbose").is_err());
+    }
+
+    #[cfg(feature = "env-write")]
+    #[test]
+    fn set_env_value() {
+        let name = format!("JQ_BRIDGE_AUDIT_{}", std::process::id());
+        let entries = logged("set-env", Level::Full, &[
+            json!({"set_env": [name, "s3cret"]}),
+            json!({"id": 1, "cmd": {"set_env": [name, "s3cret"]}}),
+        ]);
+        assert_eq!(entries[0]["command"], json!({"set_env": [name, REDACTED]}));
+        assert_eq!(entries[1]["command"], json!({"id": 1, "cmd": {"set_env": [name, REDACTED]}}));
+        assert!(!entries.iter().any(|entry| entry.to_string().contains("s3cret")));
+        unsafe { std::env::remove_var(&name) };
+    }
+
+    #[test]
+    fn dotenv_values() {
+        let path = std::env::temp_dir().join(format!("jq-bridge-audit-{}.env", std::process::id()));
+        std::fs::write(&path, "API=s3cret\n").unwrap();
+        let entries = logged("dotenv", Level::Full, &[json!({"load_dotenv": {"path": path}})]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries[0]["command"], json!({"load_dotenv": {"path": path}}));
+        assert_eq!(entries[0]["ok"], REDACTED);
+        assert!(!entries[0].to_string().contains("s3cret"));
+    }
+}
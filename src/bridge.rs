@@ -0,0 +1,671 @@
+//! The protocol loop, reading commands and writing answers
+
+use std::{
+    io::{self, BufRead, BufWriter, Write},
+    mem,
+    sync::Mutex,
+    thread,
+    time::Instant,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    audit::AuditLog,
+    framing::{self, Framing},
+    lock, Command, Context, Error, InvalidRequest, Request,
+};
+
+/// How the channel is served,
+/// the error format and the policy are options of the [`Context`]
+pub struct BridgeOptions {
+    /// `exit` only ends the loop, e.g. for a connection of a server
+    pub exit_closes: bool,
+    pub commands: Box<dyn Framing>,
+    pub answers: Box<dyn Framing>,
+    /// Prefix of the command lines, other lines are forwarded to the output of print
+    pub marker: Option<String>,
+    /// Run each command `with_timeout` when possible
+    pub default_timeout: Option<u64>,
+    /// Answers carry `"cmd": name`
+    pub verbose_responses: bool,
+    /// Exit code of a session ended by the first err answer not `allow_error`
+    pub strict: Option<i32>,
+    /// Largest serialized ok answer, a larger one is answered with `response_too_large`
+    pub max_output_size: Option<usize>,
+    pub log: Option<AuditLog>,
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        Self {
+            exit_closes: false,
+            commands: Box::new(framing::Line),
+            answers: Box::new(framing::Line),
+            marker: None,
+            default_timeout: None,
+            verbose_responses: false,
+            strict: None,
+            max_output_size: None,
+            log: None,
+        }
+    }
+}
+
+/// Write one answer, answers of concurrent commands never interleave
+fn write_answer<W: Write>(to: &Mutex<W>, framing: &dyn Framing, message: &[u8]) -> io::Result<()> {
+    let mut to = lock(to);
+    framing.write(&mut *to, message)?;
+    to.flush()
+}
+
+/// Serialization buffer given up once past `limit` bytes
+struct Bounded<'a> {
+    buf: &'a mut Vec<u8>,
+    limit: usize,
+    /// Bytes serialized when given up
+    exceeded: Option<usize>,
+}
+
+impl Write for Bounded<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let size = self.buf.len() + data.len();
+        if size > self.limit {
+            self.exceeded = Some(size);
+            return Err(io::Error::other("response too large"));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An answer or a part, the keys are in the sorted order of a JSON object [`Value`]
+#[derive(Debug, Default, Serialize)]
+struct Answer<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    err: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ok: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a Value>,
+}
+
+/// Serialize `answer` into `to`, the bytes serialized so far once past `limit`
+fn encode(answer: &Answer<'_>, limit: Option<usize>, to: &mut Vec<u8>) -> Result<(), usize> {
+    to.clear();
+    let Some(limit) = limit else {
+        serde_json::to_writer(to, answer).expect("an answer serializes");
+        return Ok(());
+    };
+    let mut to = Bounded { buf: to, limit, exceeded: None };
+    match serde_json::to_writer(&mut to, answer) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(to.exceeded.expect("only the limit fails")),
+    }
+}
+
+/// What the answers and parts of a request echo
+#[derive(Debug, Default)]
+struct Echo {
+    id: Option<Value>,
+    tag: Option<Value>,
+    /// With `verbose_responses`, that of the command and not of its timeout wrapper
+    name: Option<&'static str>,
+}
+
+impl Echo {
+    fn answer(&self) -> Answer<'_> {
+        Answer {
+            cmd: self.name,
+            id: self.id.as_ref(),
+            tag: self.tag.as_ref(),
+            ..Default::default()
+        }
+    }
+}
+
+/// `{"ok": value}` or `{"err": error}`, with the echo of the request
+/// and `"dry_run": true` when the command was not run
+fn answer<'a>(
+    ctx: &Mutex<Context>,
+    echo: &'a Echo,
+    cmd: Option<&Command>,
+    result: Result<Value, Error>,
+) -> Answer<'a> {
+    let mut answer = echo.answer();
+    match result {
+        Ok(value) => answer.ok = Some(value),
+        Err(err) => answer.err = Some(lock(ctx).error_it(cmd, &err)),
+    }
+    if cmd.is_some_and(Command::mutates) && lock(ctx).dry_run() {
+        answer.dry_run = Some(true);
+    }
+    answer
+}
+
+/// [`answer`] serialized into `to`, an ok answer over `limit` bytes is replaced by an error
+fn sealed_answer<'a>(
+    ctx: &Mutex<Context>,
+    echo: &'a Echo,
+    cmd: Option<&Command>,
+    result: Result<Value, Error>,
+    limit: Option<usize>,
+    to: &mut Vec<u8>,
+) -> Answer<'a> {
+    let sealed = answer(ctx, echo, cmd, result);
+    let limit = limit.filter(|_| sealed.ok.is_some());
+    let Err(size) = encode(&sealed, limit, to) else {
+        return sealed;
+    };
+    let limit = limit.unwrap_or_default();
+    let sealed = answer(ctx, echo, cmd, Err(Error::ResponseTooLarge { size, limit }));
+    _ = encode(&sealed, None, to);
+    sealed
+}
+
+/// Answer the commands of `input` on `output` until EOF,
+/// tagged requests run concurrently and are all answered before returning
+pub fn run_bridge<R: BufRead, W: Write + Send>(
+    input: R,
+    output: W,
+    ctx: &mut Context,
+    opts: &BridgeOptions,
+) -> Result<(), Error> {
+    let shared = Mutex::new(mem::take(ctx));
+    let result = run_bridge_shared(input, output, &shared, opts);
+    *ctx = shared.into_inner().unwrap_or_else(|e| e.into_inner());
+    result
+}
+
+/// Like [`run_bridge`], with a context shared by several loops
+pub fn run_bridge_shared<R: BufRead, W: Write + Send>(
+    mut input: R,
+    output: W,
+    ctx: &Mutex<Context>,
+    opts: &BridgeOptions,
+) -> Result<(), Error> {
+    let (policy, out) = {
+        let ctx = lock(ctx);
+        (ctx.policy(), ctx.output())
+    };
+    let to = Mutex::new(BufWriter::new(output));
+    let write = |message: &[u8]| write_answer(&to, &*opts.answers, message);
+    let send = |echo: &Echo, part: Value| {
+        write(&serde_json::to_vec(&Answer { part: Some(part), ..echo.answer() })?)
+    };
+    let fails = |answer: &Answer<'_>, allow_error: bool| {
+        let code = opts.strict.filter(|_| !allow_error && answer.err.is_some());
+        if let Some(code) = code {
+            lock(ctx).fail(code);
+        }
+        code.is_some()
+    };
+    let limit = opts.max_output_size;
+    // every answer is counted for `stats` and logged
+    let done = |line: &str, name: Option<&'static str>, answer: &Answer<'_>, started: Instant| {
+        lock(ctx).count(name, answer.err.is_some());
+        if let Some(log) = &opts.log {
+            let value = serde_json::to_value(answer).unwrap_or_default();
+            log.record(line, &value, started.elapsed());
+        }
+    };
+
+    thread::scope(|scope| {
+        let mut workers = vec![];
+        // reused by the commands answered inline
+        let mut buf = String::new();
+        let mut message = vec![];
+        while opts.commands.read(&mut input, &mut buf)? {
+            if let Some(marker) = &opts.marker {
+                if !buf.starts_with(&**marker) {
+                    // same output as print, ordered with the answered commands
+                    let mut out = out.lock();
+                    writeln!(out, "{buf}").and_then(|()| out.print_done())?;
+                    continue;
+                }
+                buf.drain(..marker.len());
+            }
+            if buf.trim().is_empty() {
+                continue;
+            }
+            workers.retain(|worker: &thread::ScopedJoinHandle<'_, ()>| !worker.is_finished());
+            // a tagged command failed under --strict
+            if opts.strict.is_some() && lock(ctx).exit_code().is_some() {
+                break;
+            }
+            // next time when a command holds the context
+            if let Ok(mut ctx) = ctx.try_lock() {
+                ctx.reap_children();
+            }
+
+            let started = Instant::now();
+            let Request { id, tag, cmd, allow_error } = match Request::parse(&buf) {
+                Ok(request) => request,
+                Err(invalid) => {
+                    let InvalidRequest { id, tag, error } = *invalid;
+                    let echo = Echo { id, tag, name: None };
+                    let answer = sealed_answer(ctx, &echo, None, Err(error), limit, &mut message);
+                    done(&buf, None, &answer, started);
+                    write(&message)?;
+                    if fails(&answer, false) {
+                        break;
+                    }
+                    continue;
+                },
+            };
+            if opts.exit_closes && matches!(cmd, Command::exit(_)) {
+                break;
+            }
+            // counted as the command, not as its timeout wrapper
+            let name = cmd.name();
+            let cmd = match opts.default_timeout {
+                Some(timeout_ms) => cmd.timed(timeout_ms),
+                None => cmd,
+            };
+            let is_exit = matches!(cmd, Command::exit(_));
+            let concurrent = id.is_some() && !is_exit;
+            let echo = Echo { id, tag, name: opts.verbose_responses.then_some(name) };
+            let result = match policy.check(&cmd) {
+                Err(err) => Err(err),
+                // exit is never concurrent, the loop ends after its answer
+                Ok(()) if concurrent => {
+                    let line = mem::take(&mut buf);
+                    workers.push(scope.spawn(move || {
+                        let result = cmd.run_shared(ctx, &mut |part| Ok(send(&echo, part)?));
+                        let mut message = vec![];
+                        let answer = sealed_answer(
+                            ctx, &echo, Some(&cmd), result, limit, &mut message,
+                        );
+                        done(&line, Some(name), &answer, started);
+                        // a broken pipe is noticed by the main loop
+                        _ = write(&message);
+                        fails(&answer, allow_error);
+                    }));
+                    continue;
+                },
+                Ok(()) => cmd.run_shared(ctx, &mut |part| Ok(send(&echo, part)?)),
+            };
+            let answer = sealed_answer(ctx, &echo, Some(&cmd), result, limit, &mut message);
+            done(&buf, Some(name), &answer, started);
+            write(&message)?;
+            if fails(&answer, allow_error) || is_exit && lock(ctx).exit_code().is_some() {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Answers of the command lines `input`
+    fn bridge(input: &str, ctx: &mut Context, opts: &BridgeOptions) -> Vec<Value> {
+        let mut output = vec![];
+        run_bridge(input.as_bytes(), &mut output, ctx, opts).unwrap();
+        output.split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ok() {
+        let answers = bridge(
+            "{\"echo\": [1, 2]}\n\"ping\"\n",
+            &mut Context::default(),
+            &Default::default(),
+        );
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0], json!({"ok": [1, 2]}));
+        assert!(answers[1]["ok"].is_object());
+    }
+
+    #[test]
+    fn err() {
+        let answers = bridge(
+            "{\"read\": \"/nonexistent/jq-bridge\"}\n",
+            &mut Context::default(),
+            &Default::default(),
+        );
+        assert_eq!(answers[0]["err"]["kind"], "not_found");
+        assert_eq!(answers[0]["err"]["command"], "read");
+        assert_eq!(answers[0]["err"]["path"], "/nonexistent/jq-bridge");
+    }
+
+    #[test]
+    fn invalid_command() {
+        let answers = bridge(
+            "{\"nope\": 1}\nnot json\n\n{\"echo\": 3}\n",
+            &mut Context::default(),
+            &Default::default(),
+        );
+        // the blank line is skipped and the loop goes on
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0]["err"]["kind"], "invalid_command");
+        assert_eq!(answers[1]["err"]["kind"], "invalid_command");
+        assert_eq!(answers[2]["ok"], 3);
+    }
+
+    #[test]
+    fn tagged() {
+        let input = "\
+{\"id\": 1, \"cmd\": {\"sleep\": 100}}
+{\"id\": \"b\", \"cmd\": {\"echo\": \"b\"}}
+{\"echo\": \"inline\"}
+";
+        let answers = bridge(input, &mut Context::default(), &Default::default());
+        assert_eq!(answers.len(), 3);
+        // the sleep is answered last, after the commands sent later
+        assert_eq!(answers[2], json!({"id": 1, "ok": null}));
+        assert!(answers.contains(&json!({"id": "b", "ok": "b"})));
+        assert!(answers.contains(&json!({"ok": "inline"})));
+    }
+
+    #[test]
+    fn strict() {
+        let opts = BridgeOptions { strict: Some(3), ..Default::default() };
+        let mut ctx = Context::default();
+        let input = "\
+{\"cmd\": {\"read\": \"/nonexistent/jq-bridge\"}, \"allow_error\": true}
+{\"read\": \"/nonexistent/jq-bridge\"}
+{\"echo\": \"never\"}
+";
+        let answers = bridge(input, &mut ctx, &opts);
+        // the allowed err goes on, the next one ends the session
+        assert_eq!(answers.len(), 2);
+        assert!(answers.iter().all(|answer| answer["err"].is_object()));
+        assert_eq!(ctx.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn max_output_size() {
+        let limited = |line: &str, limit| {
+            let opts = BridgeOptions { max_output_size: Some(limit), ..Default::default() };
+            bridge(line, &mut Context::default(), &opts).remove(0)
+        };
+        let path = std::env::temp_dir().join(format!("jq-bridge-max-output-{}", std::process::id()));
+        std::fs::write(&path, "x".repeat(1000)).unwrap();
+        let line = format!("{}\n", json!({"read": path}));
+        // {"ok":"x.."}
+        let size = 1000 + 9;
+        assert_eq!(limited(&line, size)["ok"].as_str().map(str::len), Some(1000));
+        let answer = limited(&line, size - 1);
+        assert_eq!(answer["err"]["kind"], "response_too_large");
+        assert_eq!(answer["err"]["size"], size);
+        assert_eq!(answer["err"]["limit"], size - 1);
+        // where it was given up, past the limit and before the end
+        let given_up = limited(&line, 10)["err"]["size"].as_u64().unwrap();
+        assert!(10 < given_up && given_up < size as u64, "{given_up}");
+
+        // the session goes on, errors are not limited
+        let opts = BridgeOptions { max_output_size: Some(10), ..Default::default() };
+        let next = format!("{line}{{\"echo\": 1}}\n{{\"read\": \"/nonexistent/jq-bridge/{}\"}}\n", "y".repeat(20));
+        let answers = bridge(&next, &mut Context::default(), &opts);
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0]["err"]["kind"], "response_too_large");
+        assert_eq!(answers[1], json!({"ok": 1}));
+        assert_eq!(answers[2]["err"]["kind"], "not_found");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_line_then_read() {
+        let path = std::env::temp_dir().join(format!("jq-bridge-bad-line-{}", std::process::id()));
+        std::fs::write(&path, "still here").unwrap();
+        let long = format!("{{\"raed\": \"{}\"}}", "y".repeat(500));
+        let input = format!("{{\"read\": \n{long}\n\n{}\n", json!({"read": path}));
+        let answers = bridge(&input, &mut Context::default(), &Default::default());
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0]["err"]["kind"], "invalid_command");
+        let message = answers[1]["err"]["message"].as_str().unwrap();
+        assert!(message.contains("raed") && message.ends_with(".."), "{message}");
+        assert!(message.len() < long.len(), "{message}");
+        assert_eq!(answers[2], json!({"ok": "still here"}));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn byte_identical_script() {
+        let script = r#"{"echo": {"b": 1, "a": [true, null, 1.5, -0, 1e3, "é\n\"\u0001"]}}
+"ping_x"
+{"echo": "line"}
+
+{"echo": 12345678901234567890}
+{"timer_elapsed": "none"}
+{"id": "t", "tag": [1], "cmd": {"echo": {}}}
+"#;
+        let expected = concat!(
+            r#"{"ok":{"a":[true,null,1.5,-0.0,1000.0,"é\n\"\u0001"],"b":1}}"#, "\n",
+            r#"{"err":{"command":null,"kind":"invalid_command","message":"invalid command: unknown command \"ping_x\", did you mean \"ping\"?, in \"\\\"ping_x\\\"\"","os_code":null,"path":null}}"#, "\n",
+            r#"{"ok":"line"}"#, "\n",
+            r#"{"ok":12345678901234567890}"#, "\n",
+            r#"{"err":{"command":"timer_elapsed","kind":"timer_not_found","message":"timer not found: \"none\"","os_code":null,"path":null}}"#, "\n",
+            r#"{"id":"t","ok":{},"tag":[1]}"#, "\n",
+        );
+        let mut output = vec![];
+        run_bridge(script.as_bytes(), &mut output, &mut Context::default(), &Default::default()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    /// 500k small commands, `cargo test --release -- --ignored throughput --nocapture`
+    #[test]
+    #[ignore]
+    fn throughput() {
+        const COMMANDS: usize = 500_000;
+        let input = "{\"echo\": [1, \"two\"]}\n".repeat(COMMANDS);
+        let mut output = Vec::with_capacity(COMMANDS * 20);
+        let started = std::time::Instant::now();
+        run_bridge(input.as_bytes(), &mut output, &mut Context::default(), &Default::default()).unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(output.len(), COMMANDS * "{\"ok\":[1,\"two\"]}\n".len());
+        eprintln!("{COMMANDS} commands in {elapsed:?}, {:.0}/s", COMMANDS as f64 / elapsed.as_secs_f64());
+    }
+
+    #[test]
+    fn tag_and_cmd_echoed() {
+        let path = std::env::temp_dir().join(format!("jq-bridge-tag-{}", std::process::id()));
+        std::fs::write(&path, "tagged").unwrap();
+        let tag = json!({"step": [1, "a"]});
+        let input = [
+            json!({"tag": tag, "cmd": {"read": path}}),
+            json!({"tag": tag, "cmd": {"read": "/nonexistent/jq-bridge"}}),
+            json!({"id": 1, "tag": "t", "cmd": {"read": path}}),
+            json!({"id": 2, "tag": "t", "cmd": {"read": "/nonexistent/jq-bridge"}}),
+            json!({"tag": tag, "cmd": {"raed": path}}),
+        ].map(|line| format!("{line}\n")).concat();
+        let opts = BridgeOptions { verbose_responses: true, ..Default::default() };
+        let mut answers = bridge(&input, &mut Context::default(), &opts);
+        assert_eq!(answers.len(), 5);
+        assert_eq!(answers[0], json!({"tag": tag, "cmd": "read", "ok": "tagged"}));
+        let err = answers[1].as_object_mut().unwrap().remove("err").unwrap();
+        assert_eq!(err["kind"], "not_found");
+        assert_eq!(answers[1], json!({"tag": tag, "cmd": "read"}));
+
+        // the tagged ones by their threads
+        let by_id = |id| answers.iter().find(|answer| answer["id"] == id).unwrap().clone();
+        assert_eq!(by_id(1), json!({"id": 1, "tag": "t", "cmd": "read", "ok": "tagged"}));
+        let mut failed = by_id(2);
+        assert_eq!(failed.as_object_mut().unwrap().remove("err").unwrap()["kind"], "not_found");
+        assert_eq!(failed, json!({"id": 2, "tag": "t", "cmd": "read"}));
+
+        // not parsed, no name of the command
+        let invalid = answers.iter().find(|answer| answer["err"]["kind"] == "invalid_command").unwrap();
+        assert_eq!(invalid["tag"], tag);
+        assert!(invalid.get("cmd").is_none(), "{invalid}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        let input = [
+            r#"{"echo": 1}"#,
+            r#"{"echo": 2}"#,
+            r#""ping""#,
+            r#"{"read": "/nonexistent/jq-bridge"}"#,
+            r#"{"echo": 3}"#,
+            r#"{"nonsense": true}"#,
+            r#""stats""#,
+            r#""stats_reset""#,
+            r#""stats""#,
+        ].join("\n");
+        let answers = bridge(&input, &mut Context::default(), &Default::default());
+        let stats = &answers[6]["ok"];
+        // counted once answered, without the stats command itself
+        assert_eq!(stats["commands"], 6);
+        assert_eq!(stats["errors"], 2);
+        assert_eq!(stats["by_command"], json!({"echo": 3, "ping": 1, "read": 1}));
+
+        let reset = &answers[8]["ok"];
+        assert_eq!(reset["commands"], 1);
+        assert_eq!(reset["errors"], 0);
+        assert_eq!(reset["by_command"], json!({"stats_reset": 1}));
+    }
+
+    #[test]
+    fn marker() {
+        // the forwarded lines and the answers in one stream, to see the order
+        let captured = crate::output::Captured::default();
+        let mut ctx = Context::default();
+        ctx.set_output(crate::Output::new(captured.clone()));
+        let opts = BridgeOptions { marker: Some("@@".into()), ..Default::default() };
+        let input = [
+            "plain text",
+            "@@{\"echo\": 1}",
+            "{\"echo\": \"looks like a command\"}",
+            "",
+            "@@\"ping\" ",
+            " @@{\"echo\": 2}",
+            "@@{\"println\": \"printed\"}",
+            // an empty command is skipped like an empty line
+            "@@",
+            "last",
+        ].join("\n");
+        run_bridge(input.as_bytes(), captured.clone(), &mut ctx, &opts).unwrap();
+        let text = captured.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 9, "{text}");
+        assert_eq!(lines[..3], ["plain text", "{\"ok\":1}", "{\"echo\": \"looks like a command\"}"]);
+        assert_eq!(lines[3], "");
+        assert!(lines[4].starts_with("{\"ok\":{\"pong\":true"), "{text}");
+        assert_eq!(lines[5..], [" @@{\"echo\": 2}", "printed", "{\"ok\":null}", "last"]);
+    }
+
+    #[test]
+    fn dry_run() {
+        let stderr = crate::output::Captured::default();
+        let mut ctx = Context::default();
+        ctx.set_dry_run(true);
+        ctx.set_error_output(crate::Output::new(stderr.clone()));
+        let dir = std::env::temp_dir().join(format!("jq-bridge-dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let existing = dir.join("existing");
+        std::fs::write(&existing, "kept").unwrap();
+
+        let lines: Vec<_> = [
+            json!({"read": existing}),
+            json!({"id": 1, "cmd": {"set_umask": 0o777}}),
+            json!({"echo": {"password": "not a command"}}),
+        ].into_iter()
+            .chain(cfg!(feature = "fs-write").then(|| json!({"write": {"path": dir.join("new"), "text": "never"}})))
+            .chain(cfg!(feature = "env-write").then(|| json!({"set_env": ["JQ_BRIDGE_DRY_RUN", "s3cret"]})))
+            .collect();
+        let input: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        let mut answers = bridge(&input, &mut ctx, &Default::default());
+        assert_eq!(answers.len(), lines.len());
+        // the tagged one is answered by its thread
+        let tagged = answers.iter().position(|answer| answer.get("id").is_some()).unwrap();
+        assert_eq!(answers.remove(tagged), json!({"id": 1, "ok": 0o022, "dry_run": true}));
+        assert_eq!(answers[0], json!({"ok": "kept"}));
+        assert_eq!(answers[1], json!({"ok": {"password": "not a command"}}));
+
+        let stderr = stderr.text();
+        assert!(stderr.contains("dry run: {\"set_umask\":511}\n"), "{stderr}");
+        #[cfg(feature = "fs-write")]
+        {
+            assert_eq!(answers[2], json!({"ok": null, "dry_run": true}));
+            assert!(!dir.join("new").exists());
+            assert!(stderr.contains("\"write\""), "{stderr}");
+        }
+        #[cfg(feature = "env-write")]
+        {
+            assert_eq!(answers.last().unwrap(), &json!({"ok": null, "dry_run": true}));
+            assert!(std::env::var_os("JQ_BRIDGE_DRY_RUN").is_none());
+            assert!(stderr.contains("JQ_BRIDGE_DRY_RUN") && !stderr.contains("s3cret"), "{stderr}");
+        }
+        assert_eq!(stderr.lines().count(), 1 + lines.len() - 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(target_os = "linux", feature = "proc"))]
+    #[test]
+    fn exited_children_reaped() {
+        use std::io::{BufRead, BufReader};
+
+        let (commands, to_bridge) = io::pipe().unwrap();
+        let (from_bridge, answers) = io::pipe().unwrap();
+        let worker = thread::spawn(move || {
+            run_bridge(BufReader::new(commands), answers, &mut Context::default(), &Default::default())
+        });
+        let mut from_bridge = BufReader::new(from_bridge);
+        let mut ask = |line: Value| {
+            writeln!(&to_bridge, "{line}").unwrap();
+            let mut answer = String::new();
+            from_bridge.read_line(&mut answer).unwrap();
+            serde_json::from_str::<Value>(&answer).unwrap()
+        };
+
+        let pid = ask(json!({"spawn": ["sh", {"args": ["-c", "sleep 0.05; exit 3"]}]}))["ok"].as_u64().unwrap();
+        let state = || {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+            stat.rsplit_once(") ").and_then(|(_, rest)| rest.chars().next())
+        };
+        thread::sleep(std::time::Duration::from_millis(300));
+        assert_eq!(state(), Some('Z'));
+        // the unrelated commands reap it
+        assert_eq!(ask(json!({"echo": 1})), json!({"ok": 1}));
+        assert_eq!(ask(json!({"echo": 2})), json!({"ok": 2}));
+        assert_eq!(state(), None);
+        assert_eq!(ask(json!({"wait_id": {"id": pid}})), json!({"ok": {"status": 3}}));
+
+        drop(to_bridge);
+        worker.join().unwrap().unwrap();
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn tagged_wait_any_unlocks() {
+        let mut ctx = Context::default();
+        let spawn = Command::parse(r#"{"spawn": ["sleep", {"args": ["5"]}]}"#).unwrap();
+        let pid = spawn.run(&mut ctx).unwrap();
+        // the wait is polling by the time of the kill
+        let input = format!(
+            "{}\n{}\n{}\n",
+            json!({"id": "w", "cmd": {"wait_any": {}}}),
+            json!({"sleep": 200}),
+            json!({"kill_tree_id": {"id": pid}}),
+        );
+        let started = Instant::now();
+        let answers = bridge(&input, &mut ctx, &Default::default());
+        // the kill is run while the wait polls
+        assert!(started.elapsed() < std::time::Duration::from_secs(3), "{:?}", started.elapsed());
+        assert_eq!(answers[1]["ok"]["signaled"], json!([pid]), "{answers:?}");
+        assert_eq!(answers[2], json!({"id": "w", "ok": {"id": pid, "status": null, "signal": 15, "core_dumped": false}}));
+    }
+}
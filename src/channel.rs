@@ -0,0 +1,178 @@
+//! Transports of the command channel with jq
+
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+use crate::Error;
+
+/// Where jq writes commands and reads answers
+#[derive(Debug, Clone)]
+pub enum Channel {
+    /// The stdin and stdout of jq
+    Stdio,
+    /// Commands on the inherited fd `n`, answers on `n + 1`, stdio untouched
+    Fd(i32),
+    /// Commands on the FIFO `$JQ_BRIDGE_IN`, answers on `$JQ_BRIDGE_OUT`,
+    /// both created in the directory, stdio untouched
+    Fifo(PathBuf),
+}
+
+pub struct Transport {
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send>,
+    /// FIFOs to remove once done
+    pub fifos: Vec<PathBuf>,
+}
+
+impl Channel {
+    /// Spawn jq connected by the channel,
+    /// no transport when jq exited before opening the FIFOs
+    pub fn spawn(&self, mut command: Command) -> Result<(Child, Option<Transport>), Error> {
+        match self {
+            Channel::Stdio => {
+                let mut child = command
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                let transport = Transport {
+                    reader: Box::new(child.stdout.take().unwrap()),
+                    writer: Box::new(child.stdin.take().unwrap()),
+                    fifos: vec![],
+                };
+                Ok((child, Some(transport)))
+            },
+            #[cfg(unix)]
+            Channel::Fd(fd) => unix::spawn_fd(command, *fd),
+            #[cfg(unix)]
+            Channel::Fifo(dir) => unix::spawn_fifo(command, dir),
+            #[cfg(not(unix))]
+            Channel::Fd(_) | Channel::Fifo(_) => Err(Error::Unsupported("side channel")),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        ffi::CString,
+        fs::{self, File, OpenOptions},
+        io,
+        os::{fd::AsRawFd, unix::{ffi::OsStrExt, process::CommandExt}},
+        path::{Path, PathBuf},
+        process::{Child, Command},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use super::Transport;
+    use crate::Error;
+
+    pub fn spawn_fd(mut command: Command, fd: i32) -> Result<(Child, Option<Transport>), Error> {
+        if fd < 3 {
+            return Err(Error::InvalidArgument(format!("channel fd {fd} would replace stdio")));
+        }
+        let (reader, cmd_writer) = io::pipe()?;
+        let (answer_reader, writer) = io::pipe()?;
+        let targets = [(cmd_writer.as_raw_fd(), fd), (answer_reader.as_raw_fd(), fd + 1)];
+        unsafe {
+            command.pre_exec(move || {
+                for (from, to) in targets {
+                    if from == to {
+                        // dup2 keeps close-on-exec of the same fd
+                        let flags = libc::fcntl(to, libc::F_GETFD);
+                        libc::fcntl(to, libc::F_SETFD, flags & !libc::FD_CLOEXEC);
+                    } else if libc::dup2(from, to) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // only jq keeps its ends, so that its exit is our EOF
+        drop((cmd_writer, answer_reader));
+        let transport = Transport {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            fifos: vec![],
+        };
+        Ok((child, Some(transport)))
+    }
+
+    fn mkfifo(path: &Path) -> Result<(), Error> {
+        let path_io = |source| Error::PathIo { path: path.to_string_lossy().into(), source };
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::InvalidString(path.to_string_lossy().into()))?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(path_io(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    pub fn spawn_fifo(mut command: Command, dir: &Path) -> Result<(Child, Option<Transport>), Error> {
+        let path_io = |path: &Path| {
+            let path = path.to_string_lossy().into_owned();
+            move |source| Error::PathIo { path, source }
+        };
+        fs::create_dir_all(dir).map_err(path_io(dir))?;
+        let dir = dir.canonicalize().map_err(path_io(dir))?;
+        let (cmd_path, answer_path) = (dir.join("in"), dir.join("out"));
+        mkfifo(&cmd_path)?;
+        if let Err(e) = mkfifo(&answer_path) {
+            _ = fs::remove_file(&cmd_path);
+            return Err(e);
+        }
+        let fifos = vec![cmd_path.clone(), answer_path.clone()];
+        let remove = |fifos: &[PathBuf]| fifos.iter().for_each(|fifo| _ = fs::remove_file(fifo));
+
+        let mut child = match command
+            .env("JQ_BRIDGE_IN", &cmd_path)
+            .env("JQ_BRIDGE_OUT", &answer_path)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                remove(&fifos);
+                return Err(e.into());
+            },
+        };
+
+        // opening a FIFO blocks until the other end is opened,
+        // which jq may do in any order, or never when it fails early
+        let (send, recv) = mpsc::channel();
+        let open = |path: PathBuf, write: bool| {
+            let send = send.clone();
+            thread::spawn(move || {
+                let file = OpenOptions::new().read(!write).write(write).open(&path);
+                _ = send.send((write, file.map_err(path_io(&path))));
+            });
+        };
+        open(cmd_path, false);
+        open(answer_path, true);
+        drop(send);
+        let (mut reader, mut writer): (Option<File>, Option<File>) = (None, None);
+        while reader.is_none() || writer.is_none() {
+            match recv.recv_timeout(Duration::from_millis(10)) {
+                Ok((true, file)) => writer = Some(file?),
+                Ok((false, file)) => reader = Some(file?),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if child.try_wait()?.is_some() {
+                        remove(&fifos);
+                        return Ok((child, None));
+                    }
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => unreachable!("both opened"),
+            }
+        }
+        let transport = Transport {
+            reader: Box::new(reader.unwrap()),
+            writer: Box::new(writer.unwrap()),
+            fifos,
+        };
+        Ok((child, Some(transport)))
+    }
+}
@@ -0,0 +1,72 @@
+//! Text of the system clipboard
+
+use std::sync::Mutex;
+
+use arboard::Clipboard;
+
+use crate::Error;
+
+/// Kept alive so that the bridge serves the copied text until it exits,
+/// which X11 and Wayland need
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+fn unavailable(e: arboard::Error) -> Error {
+    Error::ClipboardUnavailable(e.to_string())
+}
+
+fn with<T>(f: impl FnOnce(&mut Clipboard) -> Result<T, arboard::Error>) -> Result<T, Error> {
+    let mut clipboard = CLIPBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let clipboard = match &mut *clipboard {
+        Some(clipboard) => clipboard,
+        None => clipboard.insert(Clipboard::new().map_err(unavailable)?),
+    };
+    f(clipboard).map_err(unavailable)
+}
+
+/// The text, `None` when empty or not text
+pub fn get() -> Result<Option<String>, Error> {
+    let text = with(|clipboard| match clipboard.get_text() {
+        Err(arboard::Error::ContentNotAvailable) => Ok(None),
+        text => text.map(Some),
+    })?;
+    Ok(text.filter(|text| !text.is_empty()))
+}
+
+/// Copy `text`, with `wait` on Linux block until another program replaces it,
+/// so that it outlives the bridge without a clipboard manager
+pub fn set(text: String, wait: bool) -> Result<(), Error> {
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+    if wait {
+        use arboard::SetExtLinux;
+        let mut clipboard = Clipboard::new().map_err(unavailable)?;
+        return clipboard.set().wait().text(text).map_err(unavailable);
+    }
+    _ = wait;
+    with(|clipboard| clipboard.set_text(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a clipboard can be expected, only Linux may run without a display
+    fn display() -> bool {
+        cfg!(not(target_os = "linux"))
+            || ["DISPLAY", "WAYLAND_DISPLAY"].iter().any(|name| std::env::var_os(name).is_some())
+    }
+
+    #[test]
+    fn round_trip() {
+        if !display() {
+            return;
+        }
+        let saved = get().unwrap();
+        set("jq-bridge clipboard test".into(), false).unwrap();
+        assert_eq!(get().unwrap().as_deref(), Some("jq-bridge clipboard test"));
+        set(String::new(), false).unwrap();
+        assert_eq!(get().unwrap(), None);
+        if let Some(saved) = saved {
+            set(saved, false).unwrap();
+        }
+    }
+}
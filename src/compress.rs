@@ -0,0 +1,121 @@
+//! Gzip, zlib and raw deflate of strings
+
+use std::io::{self, Read, Write};
+
+use flate2::{
+    read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder},
+    write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::Error;
+
+/// Default limit of decompressed size, against zip bombs
+pub const DEFAULT_MAX_SIZE: u64 = 64 << 20;
+
+fn unknown(format: &str) -> Error {
+    Error::InvalidArgument(format!(
+        "unknown format {format:?}, expected gzip, zlib or deflate",
+    ))
+}
+
+pub fn compress(data: &[u8], format: &str, level: Option<u32>) -> Result<Vec<u8>, Error> {
+    let level = match level {
+        None => Compression::default(),
+        Some(level @ 0..=9) => Compression::new(level),
+        Some(level) => return Err(Error::InvalidArgument(format!(
+            "compression level {level} is not in 0..=9",
+        ))),
+    };
+    Ok(match format {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(vec![], level);
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        "zlib" => {
+            let mut encoder = ZlibEncoder::new(vec![], level);
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        "deflate" => {
+            let mut encoder = DeflateEncoder::new(vec![], level);
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        _ => return Err(unknown(format)),
+    })
+}
+
+/// Decompress at most `max_size` bytes, error if the output is larger
+pub fn decompress(data: &[u8], format: &str, max_size: u64) -> Result<Vec<u8>, Error> {
+    let decoder: Box<dyn Read + '_> = match format {
+        "gzip" => Box::new(MultiGzDecoder::new(data)),
+        "zlib" => Box::new(ZlibDecoder::new(data)),
+        "deflate" => Box::new(DeflateDecoder::new(data)),
+        _ => return Err(unknown(format)),
+    };
+    let mut buf = vec![];
+    decoder.take(max_size.saturating_add(1))
+        .read_to_end(&mut buf)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::InvalidArgument(format!("truncated {format} stream")),
+            _ => Error::InvalidArgument(format!("invalid {format} stream: {e}")),
+        })?;
+    if buf.len() as u64 > max_size {
+        return Err(Error::InvalidArgument(format!(
+            "decompressed size exceeds max_size {max_size}",
+        )));
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [&str; 3] = ["gzip", "zlib", "deflate"];
+
+    fn data() -> Vec<u8> {
+        (0..100_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = data();
+        for format in FORMATS {
+            for level in [None, Some(0), Some(9)] {
+                let compressed = compress(&data, format, level).unwrap();
+                assert_eq!(decompress(&compressed, format, DEFAULT_MAX_SIZE).unwrap(), data, "{format}");
+            }
+            let empty = compress(b"", format, None).unwrap();
+            assert_eq!(decompress(&empty, format, 0).unwrap(), b"");
+        }
+        assert!(compress(b"", "gzip", Some(10)).is_err());
+        assert!(compress(b"", "zstd", None).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = data();
+        for format in FORMATS {
+            let compressed = compress(&data, format, None).unwrap();
+            let e = decompress(&compressed[..compressed.len() / 2], format, DEFAULT_MAX_SIZE).unwrap_err();
+            assert!(matches!(&e, Error::InvalidArgument(msg) if msg == &format!("truncated {format} stream")), "{e}");
+            assert!(decompress(b"not compressed", format, DEFAULT_MAX_SIZE).is_err());
+        }
+    }
+
+    #[test]
+    fn max_size() {
+        let data = data();
+        for format in FORMATS {
+            let compressed = compress(&data, format, None).unwrap();
+            let len = data.len() as u64;
+            assert_eq!(decompress(&compressed, format, len).unwrap().len() as u64, len);
+            let e = decompress(&compressed, format, len - 1).unwrap_err();
+            let expected = format!("decompressed size exceeds max_size {}", len - 1);
+            assert!(matches!(&e, Error::InvalidArgument(msg) if *msg == expected), "{e}");
+        }
+    }
+}
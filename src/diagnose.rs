@@ -0,0 +1,266 @@
+//! Readable errors of the lines that are not a valid command
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{
+    de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, VariantAccess, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+use serde_json::Value;
+
+use crate::{policy::command_names, Command, Error, Shape};
+
+static RAW_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Answer the plain serde message instead, e.g. to debug the bridge
+pub fn set_raw_errors(enabled: bool) {
+    RAW_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Error of the command `text`, naming the command and what it expects,
+/// with the closest name of an unknown one
+pub(crate) fn invalid_command(e: serde_json::Error, text: &str) -> Error {
+    let described = match serde_json::from_str(text) {
+        Ok(value) if !RAW_ERRORS.load(Ordering::Relaxed) => describe(&e, &value),
+        _ => None,
+    };
+    quote_command(described.unwrap_or_else(|| e.to_string()), text)
+}
+
+/// The error of a JSON line, `None` when it is better left to serde
+fn describe(e: &serde_json::Error, value: &Value) -> Option<String> {
+    let name = match value {
+        Value::String(name) => name,
+        Value::Object(map) if map.len() == 1 => map.keys().next()?,
+        _ => return Some("expected a command name or an object of one command".into()),
+    };
+    let Some(&name) = command_names().iter().find(|known| *known == name) else {
+        return Some(match closest(name, command_names()) {
+            Some(known) => format!("unknown command {name:?}, did you mean {known:?}?"),
+            None => format!("unknown command {name:?}"),
+        });
+    };
+    // the message without its position, which is that of the whole line
+    let mut message = e.to_string();
+    if let Some(at) = message.rfind(" at line ") {
+        message.truncate(at);
+    }
+    let field = match field_of(name, value) {
+        Some(field) => format!(" in field {field:?}"),
+        None => String::new(),
+    };
+    Some(format!("{name}: {}{field}, usage: {}", suggest(&message), usage(name)))
+}
+
+/// Path of the payload field that fails, e.g. `cmd.path` of a `retry`
+fn field_of(name: &str, value: &Value) -> Option<String> {
+    let e = serde_path_to_error::deserialize::<_, Command>(value).err()?;
+    let path = e.path().to_string();
+    let field = path.strip_prefix(name)?.strip_prefix('.')?;
+    (!field.is_empty()).then(|| field.into())
+}
+
+/// Replace the listed expected names of serde by the closest one
+fn suggest(message: &str) -> String {
+    let Some((head, list)) = message.split_once(", expected ") else {
+        return message.into();
+    };
+    let unknown = head.strip_prefix("unknown variant ")
+        .or_else(|| head.strip_prefix("unknown field "));
+    let Some(unknown) = unknown else {
+        return message.into();
+    };
+    let unknown = unknown.trim_matches('`');
+    let kind = if head.starts_with("unknown variant") { "variant" } else { "field" };
+    let names: Vec<&str> = list.split('`').skip(1).step_by(2).collect();
+    match closest(unknown, &names) {
+        Some(known) => format!("unknown {kind} {unknown:?}, did you mean {known:?}?"),
+        None => format!("unknown {kind} {unknown:?}"),
+    }
+}
+
+/// How the command `name` is written, e.g. `{"write": {"path", "text", "must_new"}}`
+fn usage(name: &'static str) -> String {
+    match Command::shape_of(name) {
+        Some(Shape::Unit) => format!("{name:?}"),
+        Some(Shape::Fields) => match fields_of(name) {
+            Some(fields) => {
+                let fields = fields.iter().map(|field| format!("{field:?}")).collect::<Vec<_>>();
+                format!("{{{name:?}: {{{}}}}}", fields.join(", "))
+            },
+            None => format!("{{{name:?}: {{..}}}}"),
+        },
+        Some(Shape::Value) | None => format!("{{{name:?}: value}}"),
+    }
+}
+
+/// Name of `names` at the smallest edit distance, when close enough to be a typo
+fn closest<'a>(name: &str, names: &[&'a str]) -> Option<&'a str> {
+    let limit = name.chars().count() / 3 + 1;
+    names.iter()
+        .map(|known| (distance(name, known), *known))
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, known)| known)
+}
+
+/// Edit distance counting a swap of adjacent characters as one edit
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn quote_command(message: String, text: &str) -> Error {
+    const MAX_QUOTE: usize = 200;
+    let mut end = text.len().min(MAX_QUOTE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let ellipsis = if end < text.len() { ".." } else { "" };
+    Error::InvalidCommand(format!("{message}, in {:?}{ellipsis}", &text[..end]))
+}
+
+/// Field names of the struct command `name`, taken from the derived deserializer
+fn fields_of(name: &'static str) -> Option<&'static [&'static str]> {
+    #[derive(Debug)]
+    struct Fields(Option<&'static [&'static str]>);
+
+    impl std::fmt::Display for Fields {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("fields probe")
+        }
+    }
+
+    impl std::error::Error for Fields {}
+
+    impl de::Error for Fields {
+        fn custom<T: std::fmt::Display>(_msg: T) -> Self {
+            Fields(None)
+        }
+    }
+
+    struct Probe(&'static str);
+
+    impl<'de> Deserializer<'de> for Probe {
+        type Error = Fields;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Fields> {
+            Err(Fields(None))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            _variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, Fields> {
+            visitor.visit_enum(self)
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    impl<'de> EnumAccess<'de> for Probe {
+        type Error = Fields;
+        type Variant = Self;
+
+        fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Fields> {
+            let variant = seed.deserialize(IntoDeserializer::<Fields>::into_deserializer(self.0))?;
+            Ok((variant, self))
+        }
+    }
+
+    impl<'de> VariantAccess<'de> for Probe {
+        type Error = Fields;
+
+        fn unit_variant(self) -> Result<(), Fields> {
+            Err(Fields(None))
+        }
+
+        fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, Fields> {
+            Err(Fields(None))
+        }
+
+        fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Fields> {
+            Err(Fields(None))
+        }
+
+        fn struct_variant<V: Visitor<'de>>(
+            self,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Fields> {
+            Err(Fields(Some(fields)))
+        }
+    }
+
+    Command::deserialize(Probe(name)).map_or_else(|Fields(fields)| fields, |_| None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(line: &str) -> String {
+        match Command::parse(line) {
+            Err(Error::InvalidCommand(message)) => message,
+            other => panic!("{line}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn typo_suggested() {
+        assert!(message(r#"{"raed": "/tmp/x"}"#).starts_with(r#"unknown command "raed", did you mean "read"?"#));
+        assert!(message(r#""pnig""#).starts_with(r#"unknown command "pnig", did you mean "ping"?"#));
+        assert!(message(r#"{"zzzzzzzz": 1}"#).starts_with(r#"unknown command "zzzzzzzz", in"#));
+        assert_eq!(distance("raed", "read"), 1);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(closest("timer_strat", command_names()), Some("timer_start"));
+    }
+
+    #[test]
+    fn payload_field_named() {
+        let wrong = message(r#"{"hash": {"text": "a", "algo": 1}}"#);
+        assert!(wrong.starts_with("hash: invalid type: integer `1`, expected a string in field \"algo\""), "{wrong}");
+        assert!(wrong.contains(r#"usage: {"hash": {"text", "algo"}}"#), "{wrong}");
+        let nested = message(r#"{"retry": {"cmd": {"read": 5}, "attempts": 1, "delay_ms": 0}}"#);
+        assert!(nested.contains("in field \"cmd.read\""), "{nested}");
+        let missing = message(r#"{"history_add": {"lien": "x"}}"#);
+        assert!(missing.starts_with("history_add: missing field `line`, usage:"), "{missing}");
+        let shape = message("[1]");
+        assert!(shape.starts_with("expected a command name or an object of one command"), "{shape}");
+        // the line is quoted, a long one cut
+        let long = message(&format!(r#"{{"raed": "{}"}}"#, "x".repeat(500)));
+        assert!(long.ends_with(".."), "{long}");
+    }
+
+    #[test]
+    fn valid_commands_unaffected() {
+        for line in [
+            r#""ping""#,
+            r#"{"echo": {"raed": 1}}"#,
+            r#"{"read": "/tmp/x"}"#,
+            r#"{"hash": {"text": "a", "algo": "sha256"}}"#,
+            r#"{"retry": {"cmd": {"read": "x"}, "attempts": 2, "delay_ms": 0}}"#,
+            r#"{"history_add": {"line": "x"}}"#,
+        ] {
+            assert!(Command::parse(line).is_ok(), "{line}");
+        }
+    }
+}
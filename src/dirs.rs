@@ -0,0 +1,144 @@
+//! Platform directories
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct AppDirs {
+    pub config: Option<PathBuf>,
+    pub cache: Option<PathBuf>,
+    pub data: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub runtime: Option<PathBuf>,
+}
+
+impl AppDirs {
+    pub fn join(self, app: &str) -> Self {
+        let join = |dir: Option<PathBuf>| dir.map(|dir| dir.join(app));
+        AppDirs {
+            config: join(self.config),
+            cache: join(self.cache),
+            data: join(self.data),
+            state: join(self.state),
+            runtime: join(self.runtime),
+        }
+    }
+}
+
+/// Resolve per the XDG base directory spec, relative paths are ignored
+pub fn xdg<F>(get: F) -> AppDirs
+where F: Fn(&str) -> Option<String>,
+{
+    let absolute = |name| get(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute());
+    let home = absolute("HOME");
+    let or_home = |name, default: &str| absolute(name)
+        .or_else(|| home.as_deref().map(|home| home.join(default)));
+    AppDirs {
+        config: or_home("XDG_CONFIG_HOME", ".config"),
+        cache: or_home("XDG_CACHE_HOME", ".cache"),
+        data: or_home("XDG_DATA_HOME", ".local/share"),
+        state: or_home("XDG_STATE_HOME", ".local/state"),
+        runtime: absolute("XDG_RUNTIME_DIR"),
+    }
+}
+
+pub fn macos<F>(get: F) -> AppDirs
+where F: Fn(&str) -> Option<String>,
+{
+    let Some(home) = get("HOME") else { return AppDirs::default() };
+    let library = Path::new(&home).join("Library");
+    AppDirs {
+        config: Some(library.join("Application Support")),
+        cache: Some(library.join("Caches")),
+        data: Some(library.join("Application Support")),
+        state: Some(library.join("Application Support")),
+        runtime: None,
+    }
+}
+
+pub fn windows<F>(get: F) -> AppDirs
+where F: Fn(&str) -> Option<String>,
+{
+    let roaming = get("APPDATA").map(PathBuf::from);
+    let local = get("LOCALAPPDATA").map(PathBuf::from);
+    AppDirs {
+        config: roaming.clone(),
+        cache: local.clone(),
+        data: roaming,
+        state: local,
+        runtime: None,
+    }
+}
+
+pub fn current<F>(get: F) -> AppDirs
+where F: Fn(&str) -> Option<String>,
+{
+    if cfg!(windows) {
+        windows(get)
+    } else if cfg!(target_os = "macos") {
+        macos(get)
+    } else {
+        xdg(get)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn xdg_defaults() {
+        let dirs = xdg(env(&[("HOME", "/home/u")]));
+        assert_eq!(dirs, AppDirs {
+            config: Some("/home/u/.config".into()),
+            cache: Some("/home/u/.cache".into()),
+            data: Some("/home/u/.local/share".into()),
+            state: Some("/home/u/.local/state".into()),
+            runtime: None,
+        });
+    }
+
+    #[test]
+    fn xdg_overrides() {
+        let dirs = xdg(env(&[
+            ("HOME", "/home/u"),
+            ("XDG_CONFIG_HOME", "/cfg"),
+            ("XDG_CACHE_HOME", "relative/cache"),
+            ("XDG_RUNTIME_DIR", "/run/user/1000"),
+        ]));
+        assert_eq!(dirs.config, Some("/cfg".into()));
+        assert_eq!(dirs.cache, Some("/home/u/.cache".into()));
+        assert_eq!(dirs.runtime, Some("/run/user/1000".into()));
+    }
+
+    #[test]
+    fn xdg_without_home() {
+        assert_eq!(xdg(env(&[])), AppDirs::default());
+    }
+
+    #[test]
+    fn app_suffix() {
+        let dirs = xdg(env(&[("HOME", "/home/u"), ("XDG_CONFIG_HOME", "/cfg")])).join("app");
+        assert_eq!(dirs.config, Some("/cfg/app".into()));
+        assert_eq!(dirs.data, Some("/home/u/.local/share/app".into()));
+        assert_eq!(dirs.runtime, None);
+    }
+
+    #[test]
+    fn other_platforms() {
+        let dirs = macos(env(&[("HOME", "/Users/u")]));
+        assert_eq!(dirs.cache, Some("/Users/u/Library/Caches".into()));
+        assert_eq!(dirs.runtime, None);
+        let dirs = windows(env(&[("APPDATA", "/roaming"), ("LOCALAPPDATA", "/local")]));
+        assert_eq!(dirs.config, Some("/roaming".into()));
+        assert_eq!(dirs.state, Some("/local".into()));
+        assert_eq!(dirs.runtime, None);
+    }
+}
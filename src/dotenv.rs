@@ -0,0 +1,156 @@
+//! Parsing of `.env` files
+
+/// Variables of a `.env` file in order and the warnings of its malformed lines
+///
+/// A line is `KEY=value`, optionally prefixed by `export `, blank lines and `#` comments
+/// are skipped, an unquoted value ends at a ` #` comment, a single quoted value is literal
+/// and a double quoted value has the escapes `\n`, `\r`, `\t`, `\"`, `\\` and `\$`,
+/// both quotes may span lines, `${VAR}` is not expanded
+pub fn parse(text: &str) -> (Vec<(String, String)>, Vec<String>) {
+    let lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+    let (mut vars, mut warnings) = (vec![], vec![]);
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        i += 1;
+        let line = lines[start].trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export").filter(|rest| rest.starts_with([' ', '\t']))
+            .map_or(line, str::trim_start);
+        let Some((key, value)) = line.split_once('=') else {
+            warnings.push(format!("line {}: expected KEY=value", start + 1));
+            continue;
+        };
+        let key = key.trim_end();
+        if !is_key(key) {
+            warnings.push(format!("line {}: invalid key {key:?}", start + 1));
+            continue;
+        }
+        match parse_value(value, &lines[i..]) {
+            Ok((value, continued)) => {
+                vars.push((key.into(), value));
+                i += continued;
+            },
+            Err(message) => warnings.push(format!("line {}: {message}", start + 1)),
+        }
+    }
+    (vars, warnings)
+}
+
+fn is_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// The value after `=` of a line, and how many of the `next` lines a quote spans
+fn parse_value(raw: &str, next: &[&str]) -> Result<(String, usize), String> {
+    let first = raw.trim_start();
+    let Some(quote) = first.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+        let end = raw.char_indices()
+            .find(|&(i, c)| c == '#' && raw[..i].ends_with([' ', '\t']))
+            .map_or(raw.len(), |(i, _)| i);
+        return Ok((raw[..end].trim().into(), 0));
+    };
+    let mut value = String::new();
+    let mut rest = &first[1..];
+    let mut continued = 0;
+    loop {
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' if quote == '"' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c @ ('"' | '\\' | '$'))) => value.push(c),
+                    Some((_, c)) => value.extend(['\\', c]),
+                    None => value.push('\\'),
+                },
+                c if c == quote => {
+                    let after = rest[i + 1..].trim_start();
+                    if !after.is_empty() && !after.starts_with('#') {
+                        return Err(format!("unexpected {after:?} after the closing quote"));
+                    }
+                    return Ok((value, continued));
+                },
+                c => value.push(c),
+            }
+        }
+        let Some(line) = next.get(continued) else {
+            return Err(format!("unterminated {quote} quote"));
+        };
+        value.push('\n');
+        rest = line;
+        continued += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(text: &str) -> Vec<(String, String)> {
+        let (vars, warnings) = parse(text);
+        assert_eq!(warnings, Vec::<String>::new());
+        vars
+    }
+
+    fn var(key: &str, value: &str) -> (String, String) {
+        (key.into(), value.into())
+    }
+
+    #[test]
+    fn comments() {
+        let text = "# comment\nA=1 # comment\nB=x#y\n  C=\"a # b\"\nD='#' # comment\n\n";
+        assert_eq!(vars(text), [var("A", "1"), var("B", "x#y"), var("C", "a # b"), var("D", "#")]);
+    }
+
+    #[test]
+    fn empty_values() {
+        assert_eq!(vars("A=\nB=''\nC=\"\"\nD= # comment\n"), [
+            var("A", ""),
+            var("B", ""),
+            var("C", ""),
+            var("D", ""),
+        ]);
+    }
+
+    #[test]
+    fn crlf() {
+        assert_eq!(vars("A=1\r\nexport B = two words \r\nC=\"x\r\ny\"\r\n"), [
+            var("A", "1"),
+            var("B", "two words"),
+            var("C", "x\ny"),
+        ]);
+    }
+
+    #[test]
+    fn quotes() {
+        let text = r#"A="a\tb\n\"c\" \\ \$HOME ${X} \q"
+B='a\tb $X'
+C="first
+second"
+"#;
+        assert_eq!(vars(text), [
+            var("A", "a\tb\n\"c\" \\ $HOME ${X} \\q"),
+            var("B", "a\\tb $X"),
+            var("C", "first\nsecond"),
+        ]);
+    }
+
+    #[test]
+    fn warnings() {
+        let (vars, warnings) = parse("A=1\nnot a pair\n1A=2\nB=\"x\" y\nC='open\nD=4\n");
+        // the lines after an unterminated quote are still read
+        assert_eq!(vars, [var("A", "1"), var("D", "4")]);
+        assert_eq!(warnings, [
+            "line 2: expected KEY=value",
+            "line 3: invalid key \"1A\"",
+            "line 4: unexpected \"y\" after the closing quote",
+            "line 5: unterminated ' quote",
+        ]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Binary to string encodings
+
+#[cfg(any(feature = "fs-write", feature = "env-write"))]
+use std::borrow::Cow;
+
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+#[cfg(any(feature = "fs-write", feature = "env-write"))]
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::Error;
+
+pub fn hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        s.push(DIGITS[usize::from(b >> 4)].into());
+        s.push(DIGITS[usize::from(b & 0xf)].into());
+    }
+    s
+}
+
+/// Encode as `base64`, `base64url` (without padding) or `hex`
+pub fn encode(bytes: &[u8], encoding: &str) -> Result<String, Error> {
+    Ok(match encoding {
+        "base64" => base64(bytes),
+        "base64url" => URL_SAFE_NO_PAD.encode(bytes),
+        "hex" => hex(bytes),
+        _ => return Err(Error::InvalidArgument(format!(
+            "unknown encoding {encoding:?}, expected base64, base64url or hex",
+        ))),
+    })
+}
+
+pub fn base64_encode(bytes: &[u8], url_safe: bool, no_pad: bool) -> String {
+    match (url_safe, no_pad) {
+        (false, false) => STANDARD.encode(bytes),
+        (false, true) => STANDARD_NO_PAD.encode(bytes),
+        (true, false) => URL_SAFE.encode(bytes),
+        (true, true) => URL_SAFE_NO_PAD.encode(bytes),
+    }
+}
+
+/// Decode standard or url-safe base64, padded or not, whitespace is ignored
+pub fn base64_decode(data: &str) -> Result<Vec<u8>, Error> {
+    let data: String = data.chars().filter(|ch| !ch.is_ascii_whitespace()).collect();
+    let data = data.trim_end_matches('=');
+    let engine = if data.contains(['-', '_']) { URL_SAFE_NO_PAD } else { STANDARD_NO_PAD };
+    engine.decode(data)
+        .map_err(|e| Error::InvalidArgument(format!("invalid base64: {e}")))
+}
+
+/// Decode hex of any case
+pub fn hex_decode(data: &str) -> Result<Vec<u8>, Error> {
+    hex_decode_with(data, false)
+}
+
+/// Decode hex, `lenient` allows a `0x` prefix, whitespace and colons,
+/// errors report the byte offset in `data`
+pub fn hex_decode_with(data: &str, lenient: bool) -> Result<Vec<u8>, Error> {
+    let (start, rest) = match data.get(..2) {
+        Some("0x" | "0X") if lenient => (2, &data[2..]),
+        _ => (0, data),
+    };
+    let nibbles = rest.bytes()
+        .enumerate()
+        .filter(|&(_, b)| !(lenient && (b.is_ascii_whitespace() || b == b':')))
+        .map(|(i, b)| {
+            char::from(b).to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| Error::InvalidArgument(format!("invalid hex at offset {}", start + i)))
+        })
+        .collect::<Result<Vec<u8>, Error>>()?;
+    if !nibbles.len().is_multiple_of(2) {
+        return Err(Error::InvalidArgument("odd length hex".into()));
+    }
+    Ok(nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+pub fn hex_upper(bytes: &[u8]) -> String {
+    hex(bytes).to_ascii_uppercase()
+}
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE32_HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+/// RFC 4648 base32, the extended hex alphabet with `extended_hex`
+pub fn base32_encode(bytes: &[u8], extended_hex: bool, no_pad: bool) -> String {
+    let alphabet = if extended_hex { BASE32_HEX } else { BASE32 };
+    let mut s = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    for group in bytes.chunks(5) {
+        let mut buf = [0; 5];
+        buf[..group.len()].copy_from_slice(group);
+        let bits = buf.iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b));
+        let chars = (group.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            s.push(alphabet[(bits >> (35 - i * 5)) as usize & 0x1f].into());
+        }
+        if !no_pad {
+            s.extend(std::iter::repeat_n('=', 8 - chars));
+        }
+    }
+    s
+}
+
+/// Decode base32 of any case, padded or not, whitespace is ignored,
+/// errors report the byte offset of an invalid character
+pub fn base32_decode(data: &str, extended_hex: bool) -> Result<Vec<u8>, Error> {
+    let alphabet = if extended_hex { BASE32_HEX } else { BASE32 };
+    let data = data.trim_end_matches(|ch: char| ch == '=' || ch.is_ascii_whitespace());
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let (mut buf, mut bits, mut len) = (0u32, 0, 0);
+    for (i, b) in data.bytes().enumerate() {
+        if b.is_ascii_whitespace() {
+            continue;
+        }
+        let value = alphabet.iter()
+            .position(|&ch| ch == b.to_ascii_uppercase())
+            .ok_or_else(|| Error::InvalidArgument(format!("invalid base32 at offset {i}")))?;
+        buf = buf << 5 | value as u32;
+        bits += 5;
+        len += 1;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+    if matches!(len % 8, 1 | 3 | 6) {
+        return Err(Error::InvalidArgument("invalid base32 length".into()));
+    }
+    Ok(out)
+}
+
+/// Bytes of exactly one of `text` and the encoded `data`
+pub fn input(
+    text: &Option<String>,
+    data: &Option<String>,
+    data_name: &str,
+    decode: fn(&str) -> Result<Vec<u8>, Error>,
+) -> Result<Vec<u8>, Error> {
+    match (text, data) {
+        (Some(text), None) => Ok(text.as_bytes().to_vec()),
+        (None, Some(data)) => decode(data),
+        _ => Err(Error::InvalidArgument(format!(
+            "expected exactly one of text and {data_name}",
+        ))),
+    }
+}
+
+/// UTF-8 text with `as_text`, otherwise `encode`d, so that binary survives JSON
+pub fn output(
+    bytes: Vec<u8>,
+    as_text: bool,
+    encode: fn(&[u8]) -> String,
+) -> Result<String, Error> {
+    if as_text {
+        String::from_utf8(bytes)
+            .map_err(|e| Error::InvalidArgument(format!("decoded data is not UTF-8: {e}")))
+    } else {
+        Ok(encode(&bytes))
+    }
+}
+
+pub fn base64(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Bytes of an argument, a string or the `{"text": ..}` / `{"base64": ..}` of `--binary-safe`
+#[cfg(any(feature = "fs-write", feature = "env-write"))]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged, expecting = "expected a string, {\"text\": string} or {\"base64\": string}")]
+pub enum Data {
+    Plain(String),
+    Text { text: String },
+    Base64 { base64: String },
+}
+
+#[cfg(any(feature = "fs-write", feature = "env-write"))]
+impl Data {
+    pub fn bytes(&self) -> Result<Cow<'_, [u8]>, Error> {
+        match self {
+            Data::Plain(text) | Data::Text { text } => Ok(text.as_bytes().into()),
+            Data::Base64 { base64 } => Ok(base64_decode(base64)?.into()),
+        }
+    }
+}
+
+/// `{"text": ..}` of UTF-8 bytes, `{"base64": ..}` of any other
+pub fn data_it(bytes: Vec<u8>) -> Value {
+    match String::from_utf8(bytes) {
+        Ok(text) => json!({"text": text}),
+        Err(e) => json!({"base64": base64(e.as_bytes())}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_alphabets() {
+        let bytes = [0xfb, 0xff, 0xbf, 0x01];
+        assert_eq!(base64_encode(&bytes, false, false), "+/+/AQ==");
+        assert_eq!(base64_encode(&bytes, false, true), "+/+/AQ");
+        assert_eq!(base64_encode(&bytes, true, false), "-_-_AQ==");
+        assert_eq!(base64_encode(&bytes, true, true), "-_-_AQ");
+        for data in ["+/+/AQ==", "+/+/AQ", "-_-_AQ==", "-_-_AQ", "+/+/\nAQ = ="] {
+            assert_eq!(base64_decode(data).unwrap(), bytes, "{data:?}");
+        }
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(base64_decode("Zm9v").unwrap(), b"foo");
+    }
+
+    #[test]
+    fn base64_invalid() {
+        for data in ["Z", "Zm9v!", "Zm9v+-", "Zm=9v", "ä"] {
+            let e = base64_decode(data).unwrap_err();
+            assert!(matches!(&e, Error::InvalidArgument(msg) if msg.starts_with("invalid base64")), "{data:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(hex_decode(&hex(&bytes)).unwrap(), bytes);
+        assert_eq!(hex_decode(&hex_upper(&bytes)).unwrap(), bytes);
+        assert_eq!(hex(b"\x00\xab"), "00ab");
+        assert_eq!(hex_decode("").unwrap(), b"");
+    }
+
+    #[test]
+    fn hex_lenient() {
+        assert_eq!(hex_decode_with("aa:bb:cc", true).unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(hex_decode_with("0xDE AD\nbe ef", true).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        assert!(hex_decode_with("aa:bb", false).is_err());
+        assert!(hex_decode_with("0xaa", false).is_err());
+        let offset = |data, lenient| match hex_decode_with(data, lenient) {
+            Err(Error::InvalidArgument(msg)) => msg,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(offset("0g", false), "invalid hex at offset 1");
+        assert_eq!(offset("0x0g", true), "invalid hex at offset 3");
+        assert_eq!(offset("aa:0g", true), "invalid hex at offset 4");
+        assert_eq!(offset("abc", false), "odd length hex");
+    }
+
+    #[test]
+    fn base32_rfc4648() {
+        let cases = [
+            ("", "", ""),
+            ("f", "MY======", "CO======"),
+            ("fo", "MZXQ====", "CPNG===="),
+            ("foo", "MZXW6===", "CPNMU==="),
+            ("foob", "MZXW6YQ=", "CPNMUOG="),
+            ("fooba", "MZXW6YTB", "CPNMUOJ1"),
+            ("foobar", "MZXW6YTBOI======", "CPNMUOJ1E8======"),
+        ];
+        for (text, base32, base32_hex) in cases {
+            assert_eq!(base32_encode(text.as_bytes(), false, false), base32);
+            assert_eq!(base32_encode(text.as_bytes(), true, false), base32_hex);
+            assert_eq!(base32_decode(base32, false).unwrap(), text.as_bytes());
+            assert_eq!(base32_decode(base32_hex, true).unwrap(), text.as_bytes());
+            assert_eq!(base32_decode(&base32.to_lowercase(), false).unwrap(), text.as_bytes());
+            assert_eq!(base32_decode(&base32_hex.to_lowercase(), true).unwrap(), text.as_bytes());
+            let unpadded = base32_encode(text.as_bytes(), false, true);
+            assert_eq!(unpadded, base32.trim_end_matches('='));
+            assert_eq!(base32_decode(&unpadded, false).unwrap(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn base32_invalid() {
+        assert!(matches!(base32_decode("MZ1W", false), Err(Error::InvalidArgument(msg))
+            if msg == "invalid base32 at offset 2"));
+        assert!(base32_decode("MZXW6YTW", true).is_err());
+        assert!(matches!(base32_decode("MZXW6Y", false), Err(Error::InvalidArgument(msg))
+            if msg == "invalid base32 length"));
+        assert_eq!(base32_decode("MZXW 6YQ=\n", false).unwrap(), b"foob");
+    }
+
+    #[test]
+    fn data_of_bytes() {
+        assert_eq!(data_it(vec![]), json!({"text": ""}));
+        assert_eq!(data_it(b"plain ascii".to_vec()), json!({"text": "plain ascii"}));
+        assert_eq!(data_it("caf\u{e9}".into()), json!({"text": "caf\u{e9}"}));
+        // invalid in the middle, and a multi-byte character cut short
+        assert_eq!(data_it(b"ab\xffcd".to_vec()), json!({"base64": "YWL/Y2Q="}));
+        assert_eq!(data_it(b"x\xc3".to_vec()), json!({"base64": "eMM="}));
+    }
+
+    #[cfg(any(feature = "fs-write", feature = "env-write"))]
+    #[test]
+    fn data_argument() {
+        let data = |value: Value| serde_json::from_value::<Data>(value).unwrap().bytes().unwrap().into_owned();
+        assert_eq!(data(json!("plain")), b"plain");
+        assert_eq!(data(json!({"text": ""})), b"");
+        assert_eq!(data(json!({"base64": "YWL/Y2Q="})), b"ab\xffcd");
+        // what data_it answers is taken back
+        for bytes in [&b""[..], b"ascii", b"ab\xffcd"] {
+            assert_eq!(data(data_it(bytes.to_vec())), bytes);
+        }
+        assert!(serde_json::from_value::<Data>(json!(1)).is_err());
+        let invalid = serde_json::from_value::<Data>(json!({"base64": "!"})).unwrap();
+        assert!(matches!(invalid.bytes(), Err(Error::InvalidArgument(_))));
+    }
+}
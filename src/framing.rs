@@ -0,0 +1,170 @@
+//! Message framing of the command channel
+
+use std::{
+    io::{self, BufRead, Write},
+    mem,
+};
+
+use crate::Error;
+
+/// Largest length-prefixed message, against a garbage length
+pub const MAX_MESSAGE: usize = 1 << 30;
+
+pub trait Framing: Send + Sync {
+    /// Next message into `buf`, replacing its content, false at EOF
+    fn read(&self, from: &mut dyn BufRead, buf: &mut String) -> io::Result<bool>;
+
+    fn write(&self, to: &mut dyn Write, message: &[u8]) -> io::Result<()>;
+}
+
+/// One message per line, what jq emits and reads by default
+#[derive(Debug, Clone, Copy)]
+pub struct Line;
+
+impl Framing for Line {
+    fn read(&self, from: &mut dyn BufRead, line: &mut String) -> io::Result<bool> {
+        line.clear();
+        if from.read_line(line)? == 0 {
+            return Ok(false);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(true)
+    }
+
+    fn write(&self, to: &mut dyn Write, message: &[u8]) -> io::Result<()> {
+        to.write_all(message)?;
+        to.write_all(b"\n")
+    }
+}
+
+/// A decimal byte count and a newline before each message,
+/// the count is a separate input when read by jq
+///
+/// A newline not counted follows the message, jq only parses complete lines
+#[derive(Debug, Clone, Copy)]
+pub struct LengthPrefixed;
+
+impl Framing for LengthPrefixed {
+    fn read(&self, from: &mut dyn BufRead, buf: &mut String) -> io::Result<bool> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut header = String::new();
+        // whitespace between messages is allowed, e.g. the newline of `jq -j`
+        while header.trim().is_empty() {
+            header.clear();
+            if from.read_line(&mut header)? == 0 {
+                return Ok(false);
+            }
+        }
+        let len = header.trim().parse::<usize>()
+            .map_err(|e| invalid(format!("invalid message length {:?}: {e}", header.trim())))?;
+        if len > MAX_MESSAGE {
+            return Err(invalid(format!("message of {len} bytes exceeds {MAX_MESSAGE}")));
+        }
+        let mut message = mem::take(buf).into_bytes();
+        message.clear();
+        message.resize(len, 0);
+        from.read_exact(&mut message)?;
+        *buf = String::from_utf8(message).map_err(|e| invalid(e.to_string()))?;
+        Ok(true)
+    }
+
+    fn write(&self, to: &mut dyn Write, message: &[u8]) -> io::Result<()> {
+        writeln!(to, "{}", message.len())?;
+        to.write_all(message)?;
+        to.write_all(b"\n")
+    }
+}
+
+/// Framing of a `--framing` option, `line` or `length`
+pub fn by_name(name: &str) -> Result<Box<dyn Framing>, Error> {
+    match name {
+        "line" => Ok(Box::new(Line)),
+        "length" => Ok(Box::new(LengthPrefixed)),
+        _ => Err(Error::InvalidArgument(format!(
+            "unknown framing {name:?}, expected line or length",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read};
+
+    use super::*;
+
+    /// At most `step` bytes per read, like a slow pipe
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read_all(framing: &dyn Framing, data: &[u8], step: usize, capacity: usize) -> io::Result<Vec<String>> {
+        let mut from = BufReader::with_capacity(capacity, Trickle { data, step });
+        let mut messages = vec![];
+        let mut buf = String::new();
+        while framing.read(&mut from, &mut buf)? {
+            messages.push(buf.clone());
+        }
+        Ok(messages)
+    }
+
+    fn written(framing: &dyn Framing, messages: &[&str]) -> Vec<u8> {
+        let mut to = vec![];
+        for message in messages {
+            framing.write(&mut to, message.as_bytes()).unwrap();
+        }
+        to
+    }
+
+    #[test]
+    fn partial_reads() {
+        let large = "x".repeat(1000);
+        let messages = ["{\"echo\": 1}", "", "two\nlines", &large];
+        let data = written(&LengthPrefixed, &messages);
+        for step in [1, 3, 7, 4096] {
+            assert_eq!(read_all(&LengthPrefixed, &data, step, 16).unwrap(), messages, "step {step}");
+        }
+
+        let data = b"\"ping\"\r\n{\"echo\": [1]}\nlast";
+        assert_eq!(read_all(&Line, data, 1, 4).unwrap(), ["\"ping\"", "{\"echo\": [1]}", "last"]);
+        let large = written(&Line, &[&large]);
+        assert_eq!(read_all(&Line, &large, 5, 8).unwrap(), ["x".repeat(1000)]);
+    }
+
+    #[test]
+    fn length_errors() {
+        // blank lines between messages, e.g. of jq -j
+        assert_eq!(read_all(&LengthPrefixed, b"\n\n2\nab\n\n", 1, 4).unwrap(), ["ab"]);
+
+        let kind = |data: &[u8]| read_all(&LengthPrefixed, data, 4096, 64).unwrap_err().kind();
+        assert_eq!(kind(b"two\nab"), io::ErrorKind::InvalidData);
+        assert_eq!(kind(format!("{}\n", MAX_MESSAGE + 1).as_bytes()), io::ErrorKind::InvalidData);
+        assert_eq!(kind(b"5\nab"), io::ErrorKind::UnexpectedEof);
+        assert_eq!(kind(b"1\n\xff"), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sixteen_megabytes() {
+        let message: String = (0..16 << 20).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        let data = written(&LengthPrefixed, &[&message, "after"]);
+        assert!(data.starts_with(format!("{}\n", 16 << 20).as_bytes()));
+        let messages = read_all(&LengthPrefixed, &data, 1 << 16, 8 << 10).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0] == message);
+        assert_eq!(messages[1], "after");
+    }
+}
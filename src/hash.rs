@@ -0,0 +1,307 @@
+//! Digests and checksums of strings and files
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use adler2::Adler32;
+use crc::{Crc, CRC_32_ISCSI, CRC_32_ISO_HDLC};
+use hmac::{Hmac, Mac as _};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{encoding, Error, IsTrue};
+
+pub const ALGORITHMS: &[&str] = &["sha256", "sha512", "sha1", "md5", "blake3", "xxh3"];
+
+/// Incremental hasher of one of the [`ALGORITHMS`]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Sha1(Sha1),
+    Md5(Md5),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    pub fn new(algo: &str) -> Result<Self, Error> {
+        Ok(match algo {
+            "sha256" => Hasher::Sha256(Sha256::new()),
+            "sha512" => Hasher::Sha512(Sha512::new()),
+            "sha1" => Hasher::Sha1(Sha1::new()),
+            "md5" => Hasher::Md5(Md5::new()),
+            "blake3" => Hasher::Blake3(Default::default()),
+            "xxh3" => Hasher::Xxh3(Default::default()),
+            _ => return Err(Error::InvalidArgument(format!(
+                "unknown algorithm {algo:?}, expected one of {}",
+                ALGORITHMS.join(", "),
+            ))),
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Md5(h) => h.update(data),
+            Hasher::Blake3(h) => { h.update(data); },
+            Hasher::Xxh3(h) => h.update(data),
+        }
+    }
+
+    #[cfg(all(feature = "net", feature = "fs-write"))]
+    pub fn update_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        read_chunks(path.as_ref(), |chunk| self.update(chunk))
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Md5(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+            Hasher::Xxh3(h) => h.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Lowercase hex digest of `data`
+pub fn hash(algo: &str, data: &[u8]) -> Result<String, Error> {
+    let mut hasher = Hasher::new(algo)?;
+    hasher.update(data);
+    Ok(encoding::hex(&hasher.finish()))
+}
+
+/// Key and message of a MAC, the message is either `text` or `data_base64`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HmacInput {
+    algo: String,
+    key: String,
+    key_base64: Option<bool>,
+    text: Option<String>,
+    data_base64: Option<String>,
+}
+
+impl HmacInput {
+    fn mac(&self) -> Result<Mac, Error> {
+        // never echo the key in errors
+        let key = if self.key_base64.is_true() {
+            encoding::base64_decode(&self.key)
+                .map_err(|_| Error::InvalidArgument("invalid base64 key".into()))?
+        } else {
+            self.key.as_bytes().to_vec()
+        };
+        let message = encoding::input(
+            &self.text,
+            &self.data_base64,
+            "data_base64",
+            encoding::base64_decode,
+        )?;
+        let mut mac = match &*self.algo {
+            "sha256" => Mac::Sha256(Hmac::new_from_slice(&key).expect("any key length")),
+            "sha512" => Mac::Sha512(Hmac::new_from_slice(&key).expect("any key length")),
+            "sha1" => Mac::Sha1(Hmac::new_from_slice(&key).expect("any key length")),
+            algo => return Err(Error::InvalidArgument(format!(
+                "unknown algorithm {algo:?}, expected one of sha256, sha512, sha1",
+            ))),
+        };
+        mac.update(&message);
+        Ok(mac)
+    }
+
+    /// Lowercase hex MAC
+    pub fn hmac(&self) -> Result<String, Error> {
+        Ok(encoding::hex(&self.mac()?.finish()))
+    }
+
+    /// Compare with the hex `expected` in constant time,
+    /// a `sha256=` style prefix of the algorithm is allowed
+    pub fn verify(&self, expected: &str) -> Result<bool, Error> {
+        let mac = self.mac()?;
+        let expected = expected.strip_prefix(&format!("{}=", self.algo)).unwrap_or(expected);
+        let Ok(expected) = encoding::hex_decode(expected) else { return Ok(false) };
+        Ok(mac.verify(&expected))
+    }
+}
+
+enum Mac {
+    Sha256(Hmac<Sha256>),
+    Sha512(Hmac<Sha512>),
+    Sha1(Hmac<Sha1>),
+}
+
+impl Mac {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Mac::Sha256(mac) => mac.update(data),
+            Mac::Sha512(mac) => mac.update(data),
+            Mac::Sha1(mac) => mac.update(data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Mac::Sha256(mac) => mac.finalize().into_bytes().to_vec(),
+            Mac::Sha512(mac) => mac.finalize().into_bytes().to_vec(),
+            Mac::Sha1(mac) => mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn verify(self, expected: &[u8]) -> bool {
+        match self {
+            Mac::Sha256(mac) => mac.verify_slice(expected).is_ok(),
+            Mac::Sha512(mac) => mac.verify_slice(expected).is_ok(),
+            Mac::Sha1(mac) => mac.verify_slice(expected).is_ok(),
+        }
+    }
+}
+
+/// Feed a file to `f` in chunks
+fn read_chunks(path: &Path, mut f: impl FnMut(&[u8])) -> Result<(), Error> {
+    let path_io = |source| Error::PathIo { path: path.to_string_lossy().into(), source };
+    let mut file = File::open(path).map_err(path_io)?;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => f(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(path_io(e)),
+        }
+    }
+}
+
+static CRC32_IEEE: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+static CRC32_CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// Incremental 32-bit checksum, `ieee`, `castagnoli` or `adler32`
+pub enum Checksum {
+    Crc(crc::Digest<'static, u32>),
+    Adler(Adler32),
+}
+
+impl Checksum {
+    pub fn new(variant: &str) -> Result<Self, Error> {
+        Ok(match variant {
+            "ieee" => Checksum::Crc(CRC32_IEEE.digest()),
+            "castagnoli" => Checksum::Crc(CRC32_CASTAGNOLI.digest()),
+            "adler32" => Checksum::Adler(Adler32::new()),
+            _ => return Err(Error::InvalidArgument(format!(
+                "unknown variant {variant:?}, expected ieee, castagnoli or adler32",
+            ))),
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksum::Crc(digest) => digest.update(data),
+            Checksum::Adler(adler) => adler.write_slice(data),
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        match self {
+            Checksum::Crc(digest) => digest.finalize(),
+            Checksum::Adler(adler) => adler.checksum(),
+        }
+    }
+
+    pub fn update_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        read_chunks(path.as_ref(), |chunk| self.update(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_digests() {
+        let cases = [
+            ("sha256", "", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            ("sha256", "abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            ("sha512", "abc", "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"),
+            ("sha1", "abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            ("md5", "", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("md5", "abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("blake3", "", "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            ("blake3", "abc", "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+            ("xxh3", "", "2d06800538d394c2"),
+        ];
+        for (algo, data, digest) in cases {
+            assert_eq!(hash(algo, data.as_bytes()).unwrap(), digest, "{algo} {data:?}");
+        }
+        assert!(hash("sha3", b"").is_err());
+    }
+
+    #[test]
+    fn incremental_matches_one_shot() {
+        let data = vec![7; 200_000];
+        for algo in ALGORITHMS {
+            let mut hasher = Hasher::new(algo).unwrap();
+            for chunk in data.chunks(999) {
+                hasher.update(chunk);
+            }
+            assert_eq!(encoding::hex(&hasher.finish()), hash(algo, &data).unwrap(), "{algo}");
+        }
+    }
+
+    fn hmac_input(algo: &str, key: &[u8], text: &str) -> HmacInput {
+        HmacInput {
+            algo: algo.into(),
+            key: encoding::base64(key),
+            key_base64: Some(true),
+            text: Some(text.into()),
+            data_base64: None,
+        }
+    }
+
+    #[test]
+    fn rfc4231_vectors() {
+        let cases = [
+            ("sha256", &[0x0b; 20][..], "Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            ("sha512", &[0x0b; 20], "Hi There",
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde\
+                daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"),
+            ("sha256", b"Jefe", "what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            ("sha512", b"Jefe", "what do ya want for nothing?",
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"),
+            // RFC 2202
+            ("sha1", b"Jefe", "what do ya want for nothing?",
+                "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"),
+        ];
+        for (algo, key, text, mac) in cases {
+            let input = hmac_input(algo, key, text);
+            assert_eq!(input.hmac().unwrap(), mac, "{algo} {text:?}");
+            assert!(input.verify(mac).unwrap());
+            assert!(input.verify(&format!("{algo}={mac}")).unwrap());
+        }
+    }
+
+    #[test]
+    fn verify_rejects() {
+        let input = hmac_input("sha256", b"Jefe", "what do ya want for nothing?");
+        let mac = input.hmac().unwrap();
+        let mut off = encoding::hex_decode(&mac).unwrap();
+        off[31] ^= 1;
+        assert!(!input.verify(&encoding::hex(&off)).unwrap());
+        assert!(!input.verify(&mac[..62]).unwrap());
+        assert!(!input.verify("not hex").unwrap());
+        assert!(!input.verify(&format!("sha1={mac}")).unwrap());
+        let wrong_key = hmac_input("sha256", b"jefe", "what do ya want for nothing?");
+        assert!(!wrong_key.verify(&mac).unwrap());
+        assert!(hmac_input("md5", b"Jefe", "").hmac().is_err());
+    }
+}
@@ -0,0 +1,690 @@
+//! Blocking HTTP client
+
+use std::{collections::HashMap, time::Duration};
+#[cfg(feature = "fs-write")]
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use ureq::{
+    http::{self, HeaderMap, Request, Response},
+    Agent, Body,
+};
+
+use crate::{encoding, Error};
+#[cfg(feature = "fs-write")]
+use crate::{hash::Hasher, IsTrue};
+
+/// Limit of a response body held in memory
+pub const MAX_BODY: u64 = 64 << 20;
+
+pub const MAX_REDIRECTS: u32 = 10;
+
+pub fn agent(timeout_ms: Option<u64>, follow_redirects: bool) -> Agent {
+    Agent::config_builder()
+        .http_status_as_error(false)
+        .max_redirects(if follow_redirects { MAX_REDIRECTS } else { 0 })
+        .timeout_global(timeout_ms.map(Duration::from_millis))
+        .build()
+        .new_agent()
+}
+
+pub fn headers_it(headers: &HeaderMap) -> Value {
+    let mut map = Map::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        match map.get_mut(name.as_str()) {
+            // repeated headers are joined as a list
+            Some(Value::String(prev)) => {
+                prev.push_str(", ");
+                prev.push_str(&value);
+            },
+            _ => { map.insert(name.as_str().into(), value.into()); },
+        }
+    }
+    map.into()
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers.get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+        .is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"))
+}
+
+/// `{status, headers, body, body_json, body_base64}`,
+/// `body_json` is the parsed body of a JSON content type, otherwise null,
+/// a body that is not UTF-8 is null and carried in `body_base64` instead
+pub fn response_it(mut response: Response<Body>) -> Result<Value, Error> {
+    let body = response.body_mut()
+        .with_config()
+        .limit(MAX_BODY)
+        .read_to_vec()?;
+    Ok(parts_it(response.status().as_u16(), response.headers(), body))
+}
+
+fn parts_it(status: u16, headers: &HeaderMap, body: Vec<u8>) -> Value {
+    let (body, body_base64) = match String::from_utf8(body) {
+        Ok(body) => (Some(body), None),
+        Err(e) => (None, Some(encoding::base64(e.as_bytes()))),
+    };
+    let body_json = body.as_deref()
+        .filter(|_| is_json(headers))
+        .and_then(|body| serde_json::from_str(body).ok())
+        .unwrap_or(Value::Null);
+    json!({
+        "status": status,
+        "headers": headers_it(headers),
+        "body": body,
+        "body_json": body_json,
+        "body_base64": body_base64,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Option<HashMap<String, String>>,
+    body: Option<String>,
+    body_json: Option<Value>,
+    body_base64: Option<String>,
+    timeout_ms: Option<u64>,
+    basic_auth: Option<(String, String)>,
+    follow_redirects: Option<bool>,
+}
+
+impl HttpRequest {
+    pub fn get(url: String, headers: Option<HashMap<String, String>>, timeout_ms: Option<u64>) -> Self {
+        HttpRequest {
+            method: "GET".into(),
+            url,
+            headers,
+            body: None,
+            body_json: None,
+            body_base64: None,
+            timeout_ms,
+            basic_auth: None,
+            follow_redirects: None,
+        }
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().flatten().any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Request parts, and at most one of the body forms
+    pub fn builder(&self) -> Result<(http::request::Builder, Option<Vec<u8>>), Error> {
+        let mut request = Request::builder()
+            .method(&*self.method)
+            .uri(&self.url);
+        for (name, value) in self.headers.iter().flatten() {
+            request = request.header(name, value);
+        }
+        if let Some((user, password)) = &self.basic_auth
+            && !self.has_header("authorization")
+        {
+            let credentials = encoding::base64(format!("{user}:{password}").as_bytes());
+            request = request.header("Authorization", format!("Basic {credentials}"));
+        }
+        let body = match (&self.body, &self.body_json, &self.body_base64) {
+            (None, None, None) => None,
+            (Some(body), None, None) => Some(body.as_bytes().to_vec()),
+            (None, Some(json), None) => {
+                if !self.has_header("content-type") {
+                    request = request.header("Content-Type", "application/json");
+                }
+                Some(serde_json::to_vec(json)?)
+            },
+            (None, None, Some(data)) => Some(encoding::base64_decode(data)?),
+            _ => return Err(Error::InvalidArgument(
+                "expected at most one of body, body_json and body_base64".into(),
+            )),
+        };
+        Ok((request, body))
+    }
+
+    pub fn send(&self) -> Result<Value, Error> {
+        let agent = agent(self.timeout_ms, self.follow_redirects != Some(false));
+        let invalid = |e: http::Error| Error::InvalidArgument(e.to_string());
+        let response = match self.builder()? {
+            (request, None) => agent.run(request.body(()).map_err(invalid)?),
+            (request, Some(body)) => agent.run(request.body(body).map_err(invalid)?),
+        }?;
+        response_it(response)
+    }
+}
+
+#[cfg(feature = "fs-write")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Download {
+    url: String,
+    path: String,
+    headers: Option<HashMap<String, String>>,
+    resume: Option<bool>,
+    expected_sha256: Option<String>,
+    timeout_ms: Option<u64>,
+}
+
+#[cfg(feature = "fs-write")]
+impl Download {
+    /// The destination argument, to be resolved by the caller
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Stream into `{path}.part`, renamed to `path` once complete (and verified),
+    /// the part file is kept after a transport error or a cancellation for resuming,
+    /// `cancelled` is checked between the chunks
+    pub fn run(&self, path: &Path, cancelled: &dyn Fn() -> bool) -> Result<Value, Error> {
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let path_io = |path: &Path| {
+            let path = path.to_string_lossy().into_owned();
+            move |source| Error::PathIo { path, source }
+        };
+        let offset = match fs::metadata(&part) {
+            Ok(meta) if self.resume.is_true() => meta.len(),
+            _ => 0,
+        };
+
+        let mut request = Request::get(&self.url);
+        for (name, value) in self.headers.iter().flatten() {
+            request = request.header(name, value);
+        }
+        if offset > 0 {
+            request = request.header("Range", format!("bytes={offset}-"));
+        }
+        let request = request.body(())
+            .map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        let mut response = agent(self.timeout_ms, true).run(request)?;
+        let status = response.status().as_u16();
+        let resumed = offset > 0 && status == 206;
+        if !response.status().is_success() {
+            return Err(Error::HttpStatus(status));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part)
+            .map_err(path_io(&part))?;
+        let mut reader = response.body_mut()
+            .with_config()
+            .limit(u64::MAX)
+            .reader();
+        // chunked by hand to stop at a cancellation, the part is kept for a resume
+        let mut buf = vec![0; 64 * 1024];
+        let mut written = 0;
+        loop {
+            if cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(match e.downcast::<ureq::Error>() {
+                    Ok(e) => e.into(),
+                    Err(e) => e.into(),
+                }),
+            };
+            file.write_all(&buf[..n]).map_err(path_io(&part))?;
+            written += n as u64;
+        }
+        file.sync_all().map_err(path_io(&part))?;
+        drop(file);
+
+        if let Some(expected) = &self.expected_sha256 {
+            let mut hasher = Hasher::new("sha256")?;
+            hasher.update_file(&part)?;
+            let actual = encoding::hex(&hasher.finish());
+            if !actual.eq_ignore_ascii_case(expected) {
+                fs::remove_file(&part).map_err(path_io(&part))?;
+                return Err(Error::ChecksumMismatch { expected: expected.clone(), actual });
+            }
+        }
+        fs::rename(&part, path).map_err(path_io(path))?;
+
+        Ok(json!({
+            "status": status,
+            "bytes_written": written,
+            "resumed": resumed,
+        }))
+    }
+}
+
+/// HTTP/1.1 over a unix socket, answered like [`HttpRequest::send`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpUnix {
+    socket: String,
+    method: Option<String>,
+    path: String,
+    headers: Option<HashMap<String, String>>,
+    body: Option<String>,
+    body_json: Option<Value>,
+    timeout_ms: Option<u64>,
+}
+
+impl HttpUnix {
+    #[cfg(unix)]
+    pub fn send(&self) -> Result<Value, Error> {
+        use std::io::{BufReader, Write};
+
+        use crate::net::{self, Timeouts};
+
+        let mut stream = net::unix_connect(&self.socket)?;
+        stream.set_timeouts(self.timeout_ms.map(Duration::from_millis))?;
+
+        let has_header = |name: &str| self.headers.iter().flatten()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name));
+        let body = match (&self.body, &self.body_json) {
+            (None, None) => vec![],
+            (Some(body), None) => body.as_bytes().to_vec(),
+            (None, Some(json)) => serde_json::to_vec(json)?,
+            _ => return Err(Error::InvalidArgument(
+                "expected at most one of body and body_json".into(),
+            )),
+        };
+        let method = self.method.as_deref().unwrap_or("GET");
+        let mut head = format!("{method} {} HTTP/1.1\r\n", self.path);
+        let mut header = |name: &str, value: &str| {
+            if !has_header(name) {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        };
+        header("Host", "localhost");
+        header("Connection", "close");
+        if self.body_json.is_some() {
+            header("Content-Type", "application/json");
+        }
+        if self.body.is_some() || self.body_json.is_some() {
+            header("Content-Length", &body.len().to_string());
+        }
+        for (name, value) in self.headers.iter().flatten() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let (status, headers, body) = http1::read_response(BufReader::new(stream), method)?;
+        Ok(parts_it(status, &headers, body))
+    }
+
+    #[cfg(not(unix))]
+    pub fn send(&self) -> Result<Value, Error> {
+        Err(Error::Unsupported("http_unix"))
+    }
+}
+
+/// Minimal HTTP/1.1 response reader
+#[cfg(unix)]
+mod http1 {
+    use std::io::{BufRead, Read};
+
+    use ureq::http::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::MAX_BODY;
+    use crate::Error;
+
+    fn invalid(what: &str) -> Error {
+        Error::InvalidArgument(format!("invalid http response: {what}"))
+    }
+
+    fn line(reader: &mut impl BufRead) -> Result<String, Error> {
+        let mut line = vec![];
+        reader.by_ref().take(64 * 1024).read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(invalid("unexpected end of head"));
+        }
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| invalid("head is not UTF-8"))
+    }
+
+    fn read_limited(reader: &mut impl Read, len: u64, buf: &mut Vec<u8>) -> Result<(), Error> {
+        if len > MAX_BODY.saturating_sub(buf.len() as u64) {
+            return Err(invalid("body exceeds the size limit"));
+        }
+        let read = reader.take(len).read_to_end(buf)?;
+        if (read as u64) < len {
+            return Err(invalid("truncated body"));
+        }
+        Ok(())
+    }
+
+    /// Status, headers and the decoded body
+    pub fn read_response(mut reader: impl BufRead, method: &str) -> Result<(u16, HeaderMap, Vec<u8>), Error> {
+        let status_line = line(&mut reader)?;
+        let status = status_line.strip_prefix("HTTP/1.")
+            .and_then(|rest| rest.split(' ').nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| invalid("status line"))?;
+        let mut headers = HeaderMap::new();
+        loop {
+            let line = line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid("header line"))?;
+            let name = HeaderName::try_from(name.trim()).map_err(|_| invalid("header name"))?;
+            let value = HeaderValue::try_from(value.trim()).map_err(|_| invalid("header value"))?;
+            headers.append(name, value);
+        }
+
+        let mut body = vec![];
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let chunked = header("transfer-encoding")
+            .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+        let no_body = method == "HEAD" || status == 204 || status == 304 || status < 200;
+        if no_body {
+            // whatever the headers say
+        } else if chunked {
+            loop {
+                let size = line(&mut reader)?;
+                let size = size.split(';').next().unwrap_or_default().trim();
+                let size = u64::from_str_radix(size, 16).map_err(|_| invalid("chunk size"))?;
+                if size == 0 {
+                    // trailers
+                    while !line(&mut reader)?.is_empty() {}
+                    break;
+                }
+                read_limited(&mut reader, size, &mut body)?;
+                if !line(&mut reader)?.is_empty() {
+                    return Err(invalid("chunk terminator"));
+                }
+            }
+        } else if let Some(len) = header("content-length") {
+            let len = len.parse().map_err(|_| invalid("content-length"))?;
+            read_limited(&mut reader, len, &mut body)?;
+        } else {
+            reader.take(MAX_BODY + 1).read_to_end(&mut body)?;
+            if body.len() as u64 > MAX_BODY {
+                return Err(invalid("body exceeds the size limit"));
+            }
+        }
+        Ok((status, headers, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+        time::Instant,
+    };
+
+    use super::*;
+
+    struct Received {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| &**value)
+        }
+    }
+
+    fn reply(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\n", body.len());
+        for (name, value) in headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str("Connection: close\r\n\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Base URL of a server answering each connection with `respond`
+    fn serve(respond: impl Fn(Received) -> Vec<u8> + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split(' ');
+                let (method, path) = (parts.next().unwrap().into(), parts.next().unwrap().into());
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(':') else { break };
+                    headers.push((name.into(), value.trim().into()));
+                }
+                let mut received = Received { method, path, headers, body: vec![] };
+                let len = received.header("content-length").map_or(0, |len| len.parse().unwrap());
+                reader.take(len).read_to_end(&mut received.body).unwrap();
+                // the client may have given up
+                let _ = stream.write_all(&respond(received));
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    fn get(url: String, timeout_ms: Option<u64>) -> Result<Value, Error> {
+        HttpRequest::get(url, None, timeout_ms).send()
+    }
+
+    #[test]
+    fn http_get() {
+        let base = serve(|received| match (&*received.method, &*received.path) {
+            ("GET", "/json") => reply("200 OK", &[("Content-Type", "application/json; charset=utf-8")], br#"{"a": [1]}"#),
+            ("GET", "/moved") => reply("302 Found", &[("Location", "/json")], b""),
+            ("GET", "/slow") => {
+                thread::sleep(Duration::from_secs(2));
+                reply("200 OK", &[], b"late")
+            },
+            _ => reply("404 Not Found", &[("X-Reason", "missing")], b"not here"),
+        });
+
+        let ok = get(format!("{base}/json"), None).unwrap();
+        assert_eq!(ok["status"], 200);
+        assert_eq!(ok["body"], r#"{"a": [1]}"#);
+        assert_eq!(ok["body_json"], json!({"a": [1]}));
+        assert_eq!(ok["body_base64"], Value::Null);
+        assert_eq!(ok["headers"]["content-type"], "application/json; charset=utf-8");
+
+        let missing = get(format!("{base}/nothing"), None).unwrap();
+        assert_eq!(missing["status"], 404);
+        assert_eq!(missing["body"], "not here");
+        assert_eq!(missing["body_json"], Value::Null);
+        assert_eq!(missing["headers"]["x-reason"], "missing");
+
+        let moved = get(format!("{base}/moved"), None).unwrap();
+        assert_eq!(moved["status"], 200);
+        assert_eq!(moved["body_json"], json!({"a": [1]}));
+
+        let start = Instant::now();
+        let slow = get(format!("{base}/slow"), Some(200));
+        assert!(matches!(slow, Err(Error::Http(_))), "{slow:?}");
+        assert!(start.elapsed() < Duration::from_millis(1500), "{:?}", start.elapsed());
+    }
+
+    fn request(request: Value) -> Result<Value, Error> {
+        serde_json::from_value::<HttpRequest>(request)?.send()
+    }
+
+    #[test]
+    fn http_request() {
+        let base = serve(|received| match (&*received.method, &*received.path) {
+            ("POST", "/echo") => {
+                let content_type = received.header("content-type").unwrap_or("none").to_owned();
+                reply("201 Created", &[("Content-Type", &content_type)], &received.body)
+            },
+            (_, "/auth") => {
+                let auth = received.header("authorization").unwrap_or("none").to_owned();
+                reply("200 OK", &[], auth.as_bytes())
+            },
+            (_, "/binary") => reply("200 OK", &[("Content-Type", "application/octet-stream")], &[0, 0xff, 0xfe]),
+            _ => reply("302 Found", &[("Location", "/binary")], b"moved"),
+        });
+
+        let echo = request(json!({"method": "POST", "url": format!("{base}/echo"), "body_json": {"k": "v"}})).unwrap();
+        assert_eq!(echo["status"], 201);
+        assert_eq!(echo["headers"]["content-type"], "application/json");
+        assert_eq!(echo["body_json"], json!({"k": "v"}));
+        let raw = request(json!({
+            "method": "POST",
+            "url": format!("{base}/echo"),
+            "headers": {"content-type": "text/plain"},
+            "body_base64": "aGk=",
+        })).unwrap();
+        assert_eq!((&raw["body"], &raw["headers"]["content-type"]), (&json!("hi"), &json!("text/plain")));
+        let both = request(json!({"method": "POST", "url": base, "body": "a", "body_json": 1}));
+        assert!(matches!(both, Err(Error::InvalidArgument(_))));
+
+        let binary = request(json!({"method": "GET", "url": format!("{base}/binary")})).unwrap();
+        assert_eq!(binary["body"], Value::Null);
+        assert_eq!(binary["body_base64"], "AP/+");
+
+        let auth = request(json!({"method": "GET", "url": format!("{base}/auth"), "basic_auth": ["user", "pass"]})).unwrap();
+        assert_eq!(auth["body"], "Basic dXNlcjpwYXNz");
+        let explicit = request(json!({
+            "method": "GET",
+            "url": format!("{base}/auth"),
+            "headers": {"Authorization": "Bearer token"},
+            "basic_auth": ["user", "pass"],
+        })).unwrap();
+        assert_eq!(explicit["body"], "Bearer token");
+
+        let followed = request(json!({"method": "GET", "url": format!("{base}/other")})).unwrap();
+        assert_eq!(followed["body_base64"], "AP/+");
+        let stopped = request(json!({"method": "GET", "url": format!("{base}/other"), "follow_redirects": false})).unwrap();
+        assert_eq!(stopped["status"], 302);
+        assert_eq!(stopped["headers"]["location"], "/binary");
+        assert_eq!(stopped["body"], "moved");
+    }
+
+    #[cfg(feature = "fs-write")]
+    #[test]
+    fn download() {
+        use crate::hash;
+
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        let served = content.clone();
+        let base = serve(move |received| {
+            let offset = received.header("range")
+                .and_then(|range| range.strip_prefix("bytes="))
+                .and_then(|range| range.strip_suffix('-'))
+                .map(|offset| offset.parse::<usize>().unwrap());
+            match offset {
+                Some(offset) => {
+                    let range = format!("bytes {offset}-{}/{}", served.len() - 1, served.len());
+                    reply("206 Partial Content", &[("Content-Range", &range)], &served[offset..])
+                },
+                None => reply("200 OK", &[], &served),
+            }
+        });
+        let dir = std::env::temp_dir().join(format!("jq-bridge-download-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        let part = dir.join("file.part");
+        let download = |extra: Value| {
+            let mut args = json!({"url": format!("{base}/file"), "path": "unused"});
+            args.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<Download>(args).unwrap().run(&path, &|| false)
+        };
+
+        let full = download(json!({})).unwrap();
+        assert_eq!(full, json!({"status": 200, "bytes_written": content.len(), "resumed": false}));
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert!(!part.exists());
+
+        fs::write(&part, &content[..1000]).unwrap();
+        let resumed = download(json!({"resume": true})).unwrap();
+        assert_eq!(resumed, json!({"status": 206, "bytes_written": content.len() - 1000, "resumed": true}));
+        assert_eq!(fs::read(&path).unwrap(), content);
+
+        let sha256 = hash::hash("sha256", &content).unwrap();
+        assert_eq!(download(json!({"expected_sha256": sha256.to_uppercase()})).unwrap()["status"], 200);
+
+        fs::remove_file(&path).unwrap();
+        let mismatch = download(json!({"expected_sha256": "00".repeat(32)}));
+        assert!(matches!(mismatch, Err(Error::ChecksumMismatch { actual, .. }) if actual == sha256));
+        assert!(!path.exists() && !part.exists());
+
+        let cancelled = serde_json::from_value::<Download>(json!({"url": format!("{base}/file"), "path": "unused"}))
+            .unwrap()
+            .run(&path, &|| true);
+        assert!(matches!(cancelled, Err(Error::Cancelled)));
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Socket path of a unix listener answering the connections with `responses` in turn,
+    /// the request heads are sent to the returned channel
+    #[cfg(unix)]
+    fn serve_unix(name: &str, responses: Vec<Vec<u8>>) -> (String, std::sync::mpsc::Receiver<String>) {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("jq-bridge-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (heads, received) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                heads.send(head).unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        (path.to_str().unwrap().into(), received)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn http_unix() {
+        let (socket, heads) = serve_unix("http-unix", vec![
+            reply("200 OK", &[("Content-Type", "application/json")], br#"[1, 2]"#),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n".to_vec(),
+        ]);
+        let send = |path: &str| {
+            serde_json::from_value::<HttpUnix>(json!({"socket": socket, "path": path})).unwrap().send()
+        };
+
+        let json = send("/containers/json?all=1").unwrap();
+        assert_eq!((&json["status"], &json["body_json"]), (&json!(200), &json!([1, 2])));
+        let head = heads.recv().unwrap();
+        assert!(head.starts_with("GET /containers/json?all=1 HTTP/1.1\r\n"), "{head}");
+        assert!(head.contains("\r\nHost: localhost\r\n"), "{head}");
+
+        assert_eq!(send("/chunked").unwrap()["body"], "abcde");
+        for path in ["/huge-chunk", "/huge-length"] {
+            let huge = send(path);
+            assert!(matches!(&huge, Err(Error::InvalidArgument(msg))
+                if msg == "invalid http response: body exceeds the size limit"), "{huge:?}");
+        }
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
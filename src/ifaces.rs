@@ -0,0 +1,275 @@
+//! Network interfaces and local addresses
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+
+use serde::Serialize;
+
+use crate::Error;
+
+#[derive(Debug, Serialize)]
+pub struct Interface {
+    pub name: String,
+    pub addrs: Vec<Addr>,
+    pub is_loopback: bool,
+    pub is_up: bool,
+    pub mac: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Addr {
+    pub ip: IpAddr,
+    pub prefix: u8,
+    pub family: &'static str,
+    pub link_local: bool,
+}
+
+impl Addr {
+    fn new(ip: IpAddr, prefix: u8) -> Self {
+        let (family, link_local) = match ip {
+            IpAddr::V4(ip) => ("v4", ip.is_link_local()),
+            IpAddr::V6(ip) => ("v6", ip.is_unicast_link_local()),
+        };
+        Addr { ip, prefix, family, link_local }
+    }
+}
+
+fn mac_it(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() || bytes.iter().all(|&b| b == 0) {
+        return None;
+    }
+    let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>();
+    Some(hex.join(":"))
+}
+
+/// Find or add the interface `name`, keeping the system order
+fn entry<'a>(ifaces: &'a mut Vec<Interface>, name: &str) -> &'a mut Interface {
+    let i = match ifaces.iter().position(|iface| iface.name == name) {
+        Some(i) => i,
+        None => {
+            ifaces.push(Interface {
+                name: name.into(),
+                addrs: vec![],
+                is_loopback: false,
+                is_up: false,
+                mac: None,
+            });
+            ifaces.len() - 1
+        },
+    };
+    &mut ifaces[i]
+}
+
+#[cfg(unix)]
+pub fn interfaces() -> Result<Vec<Interface>, Error> {
+    use std::{ffi::CStr, io, ptr::null_mut};
+
+    unsafe fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+        if addr.is_null() {
+            return None;
+        }
+        match unsafe { (*addr).sa_family } as i32 {
+            libc::AF_INET => {
+                let addr = unsafe { &*addr.cast::<libc::sockaddr_in>() };
+                Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into())
+            },
+            libc::AF_INET6 => {
+                let addr = unsafe { &*addr.cast::<libc::sockaddr_in6>() };
+                Some(Ipv6Addr::from(addr.sin6_addr.s6_addr).into())
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn mac(addr: *const libc::sockaddr) -> Option<String> {
+        if addr.is_null() || unsafe { (*addr).sa_family } as i32 != libc::AF_PACKET {
+            return None;
+        }
+        let addr = unsafe { &*addr.cast::<libc::sockaddr_ll>() };
+        mac_it(&addr.sll_addr[..(addr.sll_halen as usize).min(addr.sll_addr.len())])
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe fn mac(addr: *const libc::sockaddr) -> Option<String> {
+        if addr.is_null() || unsafe { (*addr).sa_family } as i32 != libc::AF_LINK {
+            return None;
+        }
+        let addr = addr.cast::<libc::sockaddr_dl>();
+        let (nlen, alen) = unsafe { ((*addr).sdl_nlen as usize, (*addr).sdl_alen as usize) };
+        // the link layer address follows the name, past the declared array
+        let data = unsafe { (&raw const (*addr).sdl_data).cast::<u8>().add(nlen) };
+        mac_it(unsafe { std::slice::from_raw_parts(data, alen) })
+    }
+
+    #[cfg(not(any(
+        target_os = "linux", target_os = "android",
+        target_os = "macos", target_os = "ios", target_os = "freebsd",
+    )))]
+    unsafe fn mac(_addr: *const libc::sockaddr) -> Option<String> {
+        None
+    }
+
+    let mut addrs = null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut ifaces = vec![];
+    let mut cur = addrs;
+    while let Some(ifa) = unsafe { cur.as_ref() } {
+        cur = ifa.ifa_next;
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy();
+        let iface = entry(&mut ifaces, &name);
+        let flags = ifa.ifa_flags as i32;
+        iface.is_up |= flags & libc::IFF_UP != 0;
+        iface.is_loopback |= flags & libc::IFF_LOOPBACK != 0;
+        if let Some(mac) = unsafe { mac(ifa.ifa_addr) } {
+            iface.mac = Some(mac);
+        }
+        if let Some(ip) = unsafe { sockaddr_ip(ifa.ifa_addr) } {
+            let prefix = match unsafe { sockaddr_ip(ifa.ifa_netmask) } {
+                Some(IpAddr::V4(mask)) => u32::from(mask).count_ones(),
+                Some(IpAddr::V6(mask)) => u128::from(mask).count_ones(),
+                None => 0,
+            };
+            iface.addrs.push(Addr::new(ip, prefix as u8));
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(ifaces)
+}
+
+#[cfg(windows)]
+pub fn interfaces() -> Result<Vec<Interface>, Error> {
+    use std::{io, ptr::null};
+
+    use windows_sys::Win32::{
+        Foundation::{ERROR_BUFFER_OVERFLOW, ERROR_SUCCESS},
+        NetworkManagement::{
+            IpHelper::{
+                GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+                GAA_FLAG_SKIP_MULTICAST, IF_TYPE_SOFTWARE_LOOPBACK, IP_ADAPTER_ADDRESSES_LH,
+            },
+            Ndis::IfOperStatusUp,
+        },
+        Networking::WinSock::{AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6},
+    };
+
+    unsafe fn sockaddr_ip(addr: *const SOCKADDR) -> Option<IpAddr> {
+        if addr.is_null() {
+            return None;
+        }
+        match unsafe { (*addr).sa_family } {
+            AF_INET => {
+                let addr = unsafe { &*addr.cast::<SOCKADDR_IN>() };
+                let ip = unsafe { addr.sin_addr.S_un.S_addr };
+                Some(Ipv4Addr::from(u32::from_be(ip)).into())
+            },
+            AF_INET6 => {
+                let addr = unsafe { &*addr.cast::<SOCKADDR_IN6>() };
+                Some(Ipv6Addr::from(unsafe { addr.sin6_addr.u.Byte }).into())
+            },
+            _ => None,
+        }
+    }
+
+    unsafe fn wide(s: *const u16) -> String {
+        if s.is_null() {
+            return String::new();
+        }
+        let len = (0..).take_while(|&i| unsafe { *s.add(i) } != 0).count();
+        String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(s, len) })
+    }
+
+    let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size = 16 * 1024u32;
+    // u64 for the alignment of the adapter structures
+    let mut buf: Vec<u64>;
+    loop {
+        buf = vec![0; (size as usize).div_ceil(8)];
+        let code = unsafe {
+            GetAdaptersAddresses(AF_UNSPEC as u32, flags, null(), buf.as_mut_ptr().cast(), &mut size)
+        };
+        match code {
+            ERROR_SUCCESS => break,
+            ERROR_BUFFER_OVERFLOW => continue,
+            code => return Err(io::Error::from_raw_os_error(code as i32).into()),
+        }
+    }
+
+    let mut ifaces = vec![];
+    let mut cur = buf.as_ptr().cast::<IP_ADAPTER_ADDRESSES_LH>();
+    while let Some(adapter) = unsafe { cur.as_ref() } {
+        cur = adapter.Next;
+        let name = unsafe { wide(adapter.FriendlyName) };
+        let iface = entry(&mut ifaces, &name);
+        iface.is_up = adapter.OperStatus == IfOperStatusUp;
+        iface.is_loopback = adapter.IfType == IF_TYPE_SOFTWARE_LOOPBACK;
+        let len = (adapter.PhysicalAddressLength as usize).min(adapter.PhysicalAddress.len());
+        iface.mac = mac_it(&adapter.PhysicalAddress[..len]);
+        let mut unicast = adapter.FirstUnicastAddress;
+        while let Some(addr) = unsafe { unicast.as_ref() } {
+            unicast = addr.Next;
+            if let Some(ip) = unsafe { sockaddr_ip(addr.Address.lpSockaddr) } {
+                iface.addrs.push(Addr::new(ip, addr.OnLinkPrefixLength));
+            }
+        }
+    }
+    Ok(ifaces)
+}
+
+/// Source address of a route to a public IP, nothing is sent
+pub fn primary_ip(family: Option<&str>) -> Result<IpAddr, Error> {
+    let probe = |v6: bool| {
+        let (bind, target): (IpAddr, IpAddr) = if v6 {
+            (Ipv6Addr::UNSPECIFIED.into(), Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888).into())
+        } else {
+            (Ipv4Addr::UNSPECIFIED.into(), Ipv4Addr::new(8, 8, 8, 8).into())
+        };
+        let socket = UdpSocket::bind((bind, 0))?;
+        socket.connect((target, 53))?;
+        Ok::<_, Error>(socket.local_addr()?.ip())
+    };
+    match family {
+        None => probe(false).or_else(|_| probe(true)),
+        Some("v4") => probe(false),
+        Some("v6") => probe(true),
+        Some(family) => Err(Error::InvalidArgument(format!(
+            "unknown family {family:?}, expected v4 or v6",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback() {
+        let ifaces = interfaces().unwrap();
+        let loopback = ifaces.iter()
+            .find(|iface| iface.addrs.iter().any(|addr| addr.ip == Ipv4Addr::LOCALHOST))
+            .expect("an interface with 127.0.0.1");
+        assert!(loopback.is_loopback && loopback.is_up, "{loopback:?}");
+        let addr = loopback.addrs.iter().find(|addr| addr.ip == Ipv4Addr::LOCALHOST).unwrap();
+        assert_eq!((addr.prefix, addr.family, addr.link_local), (8, "v4", false));
+        assert!(loopback.mac.is_none());
+    }
+
+    #[test]
+    fn primary_ip_is_not_loopback() {
+        let routable = interfaces().unwrap().into_iter()
+            .any(|iface| iface.is_up && !iface.is_loopback && iface.addrs.iter().any(|addr| addr.family == "v4"));
+        // a sandbox may have no route at all
+        if let (true, Ok(ip)) = (routable, primary_ip(Some("v4"))) {
+            assert!(ip.is_ipv4() && !ip.is_loopback() && !ip.is_unspecified(), "{ip}");
+        }
+        assert!(matches!(primary_ip(Some("v5")), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn mac_format() {
+        assert_eq!(mac_it(&[0x02, 0x42, 0xac, 0x11, 0x00, 0x0a]).as_deref(), Some("02:42:ac:11:00:0a"));
+        assert_eq!(mac_it(&[0; 6]), None);
+        assert_eq!(mac_it(&[]), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Confinement of the path arguments to a root directory
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::Error;
+
+/// Resolve `raw` inside `root`, relative to `cwd` or to `root` when absolute
+///
+/// `..` above the root and symlinks resolving outside of it are refused,
+/// checked on the nearest existing ancestor, or the path itself with `follow`,
+/// a dangling symlink on the way by its target
+pub fn resolve(root: &Path, cwd: &Path, raw: &str, follow: bool) -> Result<PathBuf, Error> {
+    let escape = || Error::PathEscape(raw.into());
+    let raw_path = Path::new(raw);
+    let mut path = if raw_path.has_root() { root.to_path_buf() } else { cwd.to_path_buf() };
+    if !path.starts_with(root) {
+        return Err(escape());
+    }
+    for component in raw_path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => {
+                if path == root || !path.pop() {
+                    return Err(escape());
+                }
+            },
+            Component::Normal(name) => path.push(name),
+            // another drive on Windows
+            Component::Prefix(_) => return Err(escape()),
+        }
+    }
+
+    let probe = if follow { path.as_path() } else { path.parent().unwrap_or(&path) };
+    if !inside(root, probe, 0) {
+        return Err(escape());
+    }
+    Ok(path)
+}
+
+/// Limit of the dangling symlinks followed by hand, as the `ELOOP` of Linux
+const MAX_HOPS: u32 = 40;
+
+/// Whether the nearest existing ancestor of `path` resolves inside `root`,
+/// a dangling symlink is checked by its target, which writing through it would create
+fn inside(root: &Path, path: &Path, hops: u32) -> bool {
+    let mut probe = path;
+    loop {
+        match probe.canonicalize() {
+            Ok(real) => return real.starts_with(root),
+            Err(_) if probe.is_symlink() => {
+                let Ok(target) = probe.read_link() else { return false };
+                if hops == MAX_HOPS {
+                    return false;
+                }
+                // an absolute target replaces the parent
+                let parent = probe.parent().unwrap_or(probe);
+                return inside(root, &parent.join(target), hops + 1);
+            },
+            Err(_) => match probe.parent() {
+                Some(parent) => probe = parent,
+                None => return true,
+            },
+        }
+    }
+}
+
+/// `path` as seen inside `root`, e.g. `/sub/file`, `raw` names it in the escape error
+pub fn relative(root: &Path, path: &Path, raw: &str) -> Result<String, Error> {
+    let inner = path.strip_prefix(root)
+        .map_err(|_| Error::PathEscape(raw.into()))?;
+    let mut relative = String::from("/");
+    for (i, component) in inner.components().enumerate() {
+        if i != 0 {
+            relative.push('/');
+        }
+        let component = component.as_os_str();
+        relative.push_str(component.to_str()
+            .ok_or_else(|| Error::InvalidString(component.to_string_lossy().into()))?);
+    }
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    /// Canonical scratch root of the test `name`
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("jq-bridge-jail-{name}-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root/sub")).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn escapes(result: Result<PathBuf, Error>) -> bool {
+        matches!(result, Err(Error::PathEscape(_)))
+    }
+
+    #[test]
+    fn lexical() {
+        let dir = scratch("lexical");
+        let root = dir.join("root");
+        let cwd = root.join("sub");
+        assert_eq!(resolve(&root, &cwd, "file", true).unwrap(), cwd.join("file"));
+        assert_eq!(resolve(&root, &cwd, "../file", true).unwrap(), root.join("file"));
+        assert_eq!(resolve(&root, &cwd, "./a/../b", true).unwrap(), cwd.join("b"));
+        assert_eq!(resolve(&root, &cwd, "/etc/passwd", true).unwrap(), root.join("etc/passwd"));
+        assert!(escapes(resolve(&root, &cwd, "../../etc/passwd", true)));
+        assert!(escapes(resolve(&root, &root, "../etc/passwd", true)));
+        assert!(escapes(resolve(&root, &root, "/../etc/passwd", true)));
+        assert!(escapes(resolve(&root, &dir, "file", true)));
+        assert_eq!(relative(&root, &cwd.join("file"), "file").unwrap(), "/sub/file");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = scratch("symlinks");
+        let root = dir.join("root");
+        let outside = dir.join("outside");
+        fs::create_dir(&outside).unwrap();
+        symlink(&outside, root.join("out")).unwrap();
+        symlink(outside.join("missing"), root.join("dangling")).unwrap();
+        symlink("../../outside/missing", root.join("sub/relative")).unwrap();
+        symlink("dangling", root.join("chain")).unwrap();
+        symlink(root.join("sub/inner"), root.join("inside")).unwrap();
+        symlink("../loop", root.join("sub/loop")).unwrap();
+        symlink("sub/loop", root.join("loop")).unwrap();
+
+        for raw in ["out", "out/file", "dangling", "dangling/file", "sub/relative", "chain", "loop"] {
+            assert!(escapes(resolve(&root, &root, raw, true)), "{raw}");
+        }
+        // the link itself is inside
+        assert_eq!(resolve(&root, &root, "dangling", false).unwrap(), root.join("dangling"));
+        assert!(escapes(resolve(&root, &root, "dangling/file", false)));
+        assert_eq!(resolve(&root, &root, "inside", true).unwrap(), root.join("inside"));
+        assert_eq!(resolve(&root, &root, "inside/file", true).unwrap(), root.join("inside/file"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
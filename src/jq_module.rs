@@ -0,0 +1,97 @@
+//! jq library of the commands, written by `--emit-jq-module`
+
+use std::fmt::Write;
+
+use crate::{policy::command_names, Command, Shape};
+
+const PRELUDE: &str = r#"# Commands are outputs of jq and answers its inputs, so the result of
+# `NAME(args; f)` is passed to `f`, whose outputs are the next commands,
+# and `NAME(args)` runs the command only for its effect.
+# An err answer is raised with error(err), the parts of stream_command are skipped.
+# Prefer `import "jq-bridge" as b;`, an include shadows builtins such as `now`.
+
+def _bridge_answer(f):
+  input
+  | if has("part") then _bridge_answer(f)
+    elif has("err") then error(.err)
+    else .ok | f end;
+def bridge(c; f): c, _bridge_answer(f);
+def bridge(c): bridge(c; empty);
+"#;
+
+/// The module text, `bridge(c; f)` and two wrappers of each command
+pub fn generate() -> String {
+    let mut module = format!(
+        "# Generated by {} {} --emit-jq-module, do not edit\n#\n{PRELUDE}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    for name in command_names() {
+        let (params, cmd) = match Command::shape_of(name) {
+            Some(Shape::Unit) | None => ("", format!("{name:?}")),
+            Some(Shape::Value) => ("$a; ", format!("{{{name}: $a}}")),
+            Some(Shape::Fields) => ("$o; ", format!("{{{name}: $o}}")),
+        };
+        let effect_params = params.trim_end_matches("; ");
+        let effect = if effect_params.is_empty() {
+            String::new()
+        } else {
+            format!("({effect_params})")
+        };
+        _ = writeln!(module, "def {name}({params}f): bridge({cmd}; f);");
+        _ = writeln!(module, "def {name}{effect}: bridge({cmd});");
+    }
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    /// The module of the default features, `JQ_BRIDGE_UPDATE_GOLDEN=1` rewrites it
+    #[cfg(all(
+        feature = "proc",
+        feature = "net",
+        feature = "env-write",
+        feature = "fs-write",
+        feature = "notify",
+        feature = "clipboard",
+        feature = "open",
+        feature = "sqlite",
+    ))]
+    #[test]
+    fn golden() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/jq-bridge.jq");
+        let module = generate();
+        if env::var_os("JQ_BRIDGE_UPDATE_GOLDEN").is_some() {
+            fs::write(path, &module).unwrap();
+        }
+        let golden = fs::read_to_string(path).unwrap();
+        assert!(golden == module, "{path} is outdated, rerun with JQ_BRIDGE_UPDATE_GOLDEN=1");
+    }
+
+    #[test]
+    fn loads_in_jq() {
+        if process::Command::new("jq").arg("--version").output().is_err() {
+            eprintln!("no jq binary, skipped");
+            return;
+        }
+        let dir = env::temp_dir().join(format!("jq-bridge-module-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("jq-bridge.jq"), generate()).unwrap();
+        for program in [r#"include "jq-bridge"; 0"#, r#"import "jq-bridge" as b; 0"#] {
+            let output = process::Command::new("jq")
+                .args(["-n", "-L"])
+                .arg(&dir)
+                .arg(program)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "{program}: {stderr}");
+            assert_eq!(output.stdout, b"0\n");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+//! Opening of paths and URLs with their default application
+
+use std::{ffi::OsStr, io, path::Path};
+
+use url::Url;
+
+use crate::Error;
+
+/// Open `target` with the default application or `with`, the application is not waited for
+fn launch(target: &OsStr, with: Option<&str>) -> Result<(), Error> {
+    match with {
+        Some(app) => open::with_detached(target, app),
+        None => open::that_detached(target),
+    }
+    .map_err(|e| Error::NoApplication(e.to_string()))
+}
+
+/// Open an existing path, checked first as the launcher may silently do nothing
+pub fn open_path(path: &Path, raw: &str, with: Option<&str>) -> Result<(), Error> {
+    let path_io = |source| Error::PathIo { path: raw.into(), source };
+    if !path.try_exists().map_err(path_io)? {
+        return Err(path_io(io::ErrorKind::NotFound.into()));
+    }
+    launch(path.as_os_str(), with)
+}
+
+/// Open an absolute URL, normalized, a one letter scheme is taken for a Windows drive
+pub fn open_url(url: &str, with: Option<&str>) -> Result<(), Error> {
+    let url = match Url::parse(url) {
+        Ok(parsed) if parsed.scheme().len() > 1 => parsed,
+        _ => return Err(Error::InvalidArgument(format!(
+            "{url:?} is not a URL, expected e.g. https://example.com",
+        ))),
+    };
+    launch(url.as_str().as_ref(), with)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refused_before_launching() {
+        for url in ["", "example.com", "/tmp/file", "c:/file", "https//example.com"] {
+            let Err(Error::InvalidArgument(message)) = open_url(url, None) else { panic!("{url:?}") };
+            assert!(message.contains("is not a URL"), "{message}");
+        }
+        let missing = std::env::temp_dir().join(format!("jq-bridge-missing-{}", std::process::id()));
+        let err = open_path(&missing, "missing", Some("true")).unwrap_err();
+        assert_eq!(err.kind(), "not_found");
+        assert_eq!(err.path(), Some("missing"));
+    }
+}
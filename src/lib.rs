@@ -1,22 +1,86 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
     convert::identity,
     env,
     ffi::OsStr,
-    fs::{self, File, OpenOptions},
-    io::{self, stdin, stdout, Write},
+    fs,
+    io::{self, stdin, stdout, IsTerminal, Write},
     iter,
-    path::Path,
-    process::{self, exit, Child, Stdio},
+    path::{Path, PathBuf},
+    process::{self, Child},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::sleep,
+    time::{Duration, Instant, SystemTime},
+};
+#[cfg(feature = "proc")]
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    process::{ExitStatus, Stdio},
     thread::spawn,
-    time::SystemTime,
 };
+#[cfg(any(feature = "proc", feature = "fs-write"))]
+use std::fs::OpenOptions;
 
-use rand::{rngs::ThreadRng, Rng};
+use rand::{
+    rngs::{OsRng, StdRng},
+    seq::{IndexedRandom, SliceRandom},
+    Rng, RngCore, SeedableRng, TryRngCore,
+};
+use rand_distr::{Exp, Normal};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value::{self, Null}};
+use serde_json::{json, Map, Value::{self, Null}};
 use thiserror::Error;
-use time::UtcDateTime;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcDateTime};
+
+use diagnose::invalid_command;
+use output::{print_it, Output, Style};
+
+mod ansi;
+pub mod audit;
+pub mod bridge;
+pub mod channel;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod compress;
+pub mod diagnose;
+mod dirs;
+mod dotenv;
+mod encoding;
+pub mod framing;
+mod hash;
+mod jail;
+pub mod jq_module;
+#[cfg(feature = "net")]
+mod http;
+#[cfg(feature = "net")]
+mod ifaces;
+#[cfg(feature = "open")]
+mod launch;
+mod locale;
+#[cfg(feature = "net")]
+mod net;
+#[cfg(feature = "notify")]
+mod notify;
+pub mod output;
+pub mod policy;
+#[cfg_attr(not(feature = "proc"), allow(dead_code))]
+mod procs;
+mod progress;
+#[cfg(all(feature = "proc", unix))]
+mod pty;
+mod random;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod sys;
+mod tasks;
+mod term;
+mod urls;
+pub mod times;
+mod users;
 
 pub trait IsTrue {
     fn is_true(&self) -> bool;
@@ -74,20 +138,50 @@ impl<T, E, I: Iterator<Item = Result<T, E>>> MapResult for I {
     type Err = E;
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg(feature = "proc")]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct CommandBuilder {
     args: Option<Vec<String>>,
     env_clear: Option<bool>,
     envs: Option<HashMap<String, String>>,
     remove_envs: Option<Vec<String>>,
+    pass_envs: Option<Vec<String>>,
+    strict_pass_envs: Option<bool>,
     current_dir: Option<String>,
     stdin: Option<String>,
     stdout: Option<String>,
     stderr: Option<String>,
     stdout_append: Option<bool>,
     stderr_append: Option<bool>,
+    timing: Option<bool>,
+    umask: Option<u32>,
+    /// Process creation flags of Windows, e.g. `0x08000000` (`CREATE_NO_WINDOW`)
+    creation_flags: Option<u32>,
 }
+#[cfg(feature = "proc")]
 impl CommandBuilder {
+    fn timing(&self) -> Option<procs::Timing> {
+        self.timing.is_true().then(procs::Timing::start)
+    }
+
+    /// Error on the stdio fields, the pty replaces them after the files would be truncated
+    #[cfg(unix)]
+    fn check_pty(&self) -> Result<(), Error> {
+        let stdio = [
+            ("stdin", self.stdin.is_some()),
+            ("stdout", self.stdout.is_some()),
+            ("stderr", self.stderr.is_some()),
+            ("stdout_append", self.stdout_append.is_some()),
+            ("stderr_append", self.stderr_append.is_some()),
+        ];
+        match stdio.iter().find(|(_, given)| *given) {
+            Some((name, _)) => Err(Error::InvalidArgument(format!(
+                "{name} is not supported by command_pty, its stdio is the pty",
+            ))),
+            None => Ok(()),
+        }
+    }
+
     pub fn apply<F>(
         &self,
         mut command: process::Command,
@@ -100,24 +194,40 @@ impl CommandBuilder {
             env_clear,
             envs,
             remove_envs,
+            pass_envs,
+            strict_pass_envs,
             current_dir,
             stdin,
             stdout,
             stderr,
             stdout_append,
             stderr_append,
+            timing: _,
+            umask,
+            creation_flags,
         } = self;
 
         if let Some(args) = args {
             command.args(args);
         }
 
-        command.envs(envs.iter().flatten());
-
-        if env_clear.is_true() {
+        // precedence: envs > pass_envs > (cleared) inherited
+        if env_clear.is_true() || pass_envs.is_some() {
             command.env_clear();
         }
 
+        for name in pass_envs.iter().flatten() {
+            match env::var_os(name) {
+                Some(value) => { command.env(name, value); },
+                None if strict_pass_envs.is_true() => {
+                    return Err(Error::EnvNotFound(name.clone()));
+                },
+                None => (),
+            }
+        }
+
+        command.envs(envs.iter().flatten());
+
         for name in remove_envs.iter().flatten() {
             command.env_remove(name);
         }
@@ -126,6 +236,33 @@ impl CommandBuilder {
             command.current_dir(current_dir);
         }
 
+        #[cfg(unix)]
+        if let &Some(umask) = umask {
+            use std::os::unix::process::CommandExt;
+
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(umask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        if umask.is_some() {
+            return Err(Error::Unsupported("umask"));
+        }
+
+        #[cfg(windows)]
+        if let &Some(flags) = creation_flags {
+            use std::os::windows::process::CommandExt;
+
+            command.creation_flags(flags);
+        }
+        #[cfg(not(windows))]
+        if creation_flags.is_some() {
+            return Err(Error::Unsupported("creation_flags"));
+        }
+
         let stdin = stdin.as_ref()
             .map(File::open)
             .transpose()?;
@@ -146,65 +283,299 @@ impl CommandBuilder {
                 .open(path))
             .transpose()?;
 
-        let mut child = f(command)?;
-
-        let mut child_stdin = child.stdin.take().unwrap();
-        let mut child_stdout = child.stdout.take().unwrap();
-        let mut child_stderr = child.stderr.take().unwrap();
-
-        let jobs = [
-            spawn(move || stdin.map(|mut in_file| {
-                io::copy(&mut in_file, &mut child_stdin)
-            }).transpose()),
-            spawn(move || stdout.map(|mut out_file| {
-                io::copy(&mut child_stdout, &mut out_file)
-            }).transpose()),
-            spawn(move || stderr.map(|mut err_file| {
-                io::copy(&mut child_stderr, &mut err_file)
-            }).transpose()),
-        ];
-
-        for job in jobs {
-            job.join().unwrap()?;
+        if let Some(stdin) = stdin {
+            command.stdin(stdin);
         }
+        command.stdout(stdout.map_or_else(Stdio::piped, Into::into));
+        command.stderr(stderr.map_or_else(Stdio::piped, Into::into));
+
+        let child = f(command)?;
 
         Ok(child)
     }
 }
 
+/// Path, or path with options
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MetadataExtra {
+    Path(String),
+    With { path: String, local: Option<bool> },
+}
+
+/// Commands, or commands with options
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Batch {
+    Cmds(Vec<Command>),
+    With { cmds: Vec<Command>, stop_on_error: Option<bool> },
+}
+
+/// Text to copy, or text with options
+#[cfg(feature = "clipboard")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged, expecting = "expected a string or {\"text\": string, \"wait\": bool}")]
+pub enum ClipboardSet {
+    Text(String),
+    With { text: String, wait: Option<bool> },
+}
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Command {
     read(String),
-    write { path: String, text: String, must_new: Option<bool> },
-    append { path: String, text: String, must_exist: Option<bool> },
+    #[cfg(feature = "fs-write")]
+    write { path: String, text: encoding::Data, must_new: Option<bool> },
+    #[cfg(feature = "fs-write")]
+    append { path: String, text: encoding::Data, must_exist: Option<bool> },
     read_dir(String),
     read_link(String),
     metadata(String),
-    metadata_extra(String),
+    metadata_extra(MetadataExtra),
     exists(String),
     is_symlink(String),
     is_dir(String),
     is_file(String),
+    #[cfg(feature = "sqlite")]
+    sqlite_query {
+        path: String,
+        sql: String,
+        params: Option<Vec<Value>>,
+        readonly: Option<bool>,
+    },
+    #[cfg(feature = "sqlite")]
+    sqlite_close(String),
     print(Value),
     println(Value),
     pretty(Value),
     pretty_pipe(Value),
+    eprint(Value),
+    eprintln(Value),
+    eprint_pretty(Value),
+    set_buffering { enabled: bool, capacity: Option<usize> },
+    flush,
     stdin,
     stdin_line,
+    prompt { text: String, default: Option<String>, trim: Option<bool> },
+    read_password { prompt: Option<String> },
+    readline { prompt: String, history_id: Option<String> },
+    confirm { text: String, default: Option<bool> },
+    history_add { line: String, history_id: Option<String> },
+    history_load { path: String, history_id: Option<String> },
+    #[cfg(feature = "fs-write")]
+    history_save { path: String, history_id: Option<String> },
+    is_tty { stream: String },
+    terminal_size,
+    style {
+        text: String,
+        fg: Option<String>,
+        bg: Option<String>,
+        bold: Option<bool>,
+        underline: Option<bool>,
+        print: Option<bool>,
+        force: Option<bool>,
+    },
+    progress_start { total: Option<u64>, label: Option<String> },
+    progress_update { id: u32, current: Option<u64>, delta: Option<u64>, label: Option<String> },
+    progress_finish { id: u32, clear: Option<bool> },
+    #[cfg(feature = "notify")]
+    notify {
+        summary: String,
+        body: Option<String>,
+        urgency: Option<String>,
+        timeout_ms: Option<u64>,
+    },
+    #[cfg(feature = "clipboard")]
+    clipboard_get,
+    #[cfg(feature = "clipboard")]
+    clipboard_set(ClipboardSet),
+    current_exe,
+    exe_dir,
     current_dir,
+    set_current_dir(String),
     temp_dir,
+    app_dirs { app: Option<String> },
     get_env(String),
-    set_env(String, String),
+    env_vars { prefix: Option<String>, lossy: Option<bool> },
+    load_dotenv { path: Option<String>, apply: Option<bool>, override_existing: Option<bool> },
+    #[cfg(feature = "env-write")]
+    set_env(String, encoding::Data),
+    #[cfg(feature = "env-write")]
     remove_env(String),
+    #[cfg(feature = "proc")]
     system(String, Vec<String>),
+    #[cfg(feature = "proc")]
     popen(String, Vec<String>),
+    #[cfg(feature = "proc")]
     command(String, CommandBuilder),
+    #[cfg(feature = "proc")]
+    spawn(String, CommandBuilder),
+    #[cfg(feature = "proc")]
+    stream_command { prog: String, builder: CommandBuilder },
+    #[cfg(feature = "proc")]
+    spawn_detached(String, CommandBuilder),
+    #[cfg(feature = "open")]
+    open_path { path: String, with: Option<String> },
+    #[cfg(feature = "open")]
+    open_url { url: String, with: Option<String> },
+    #[cfg(feature = "proc")]
+    command_pty {
+        prog: String,
+        builder: CommandBuilder,
+        cols: Option<u16>,
+        rows: Option<u16>,
+        strip_ansi: Option<bool>,
+    },
+    #[cfg(feature = "proc")]
     wait_id { id: u32, output: Option<bool> },
+    #[cfg(feature = "proc")]
+    wait_any { ids: Option<Vec<u32>>, timeout_ms: Option<u64> },
+    #[cfg(feature = "proc")]
     kill_id { id: u32 },
+    #[cfg(feature = "proc")]
+    kill_tree_id { id: u32, signal: Option<String> },
+    platform,
+    cpu_count,
+    memory_info,
+    load_average,
+    uptime,
+    hostname,
+    user_info,
+    lookup_user(String),
+    lookup_group(String),
+    groups,
+    locale_info,
+    #[cfg(feature = "net")]
+    fqdn,
+    now,
+    now_monotonic,
+    local_now,
+    timezone,
+    timer_start(String),
+    timer_elapsed(String),
+    timer_stop(String),
+    timer_list,
+    sleep(u64),
+    sleep_until { epoch_ms: u64 },
+    format_time { epoch: times::Epoch, format: String, utc: Option<bool> },
+    parse_time { text: String, format: String },
     process_id,
+    parent_process_id,
+    process_info(Option<u32>),
+    list_processes { name_filter: Option<String> },
+    get_umask,
+    set_umask(u32),
+    pid_alive(u32),
+    pid_start_time(u32),
     random,
     random_float,
+    random_normal { mean: f64, std_dev: f64, count: Option<usize> },
+    random_exp { lambda: f64, count: Option<usize> },
+    set_seed(u64),
+    clear_seed,
+    random_range { min: i64, max: i64 },
+    random_float_range { min: f64, max: f64 },
+    random_below(u64),
+    random_bytes { len: usize, encoding: Option<String>, secure: Option<bool> },
+    random_string { len: usize, charset: Option<String> },
+    base64_encode {
+        text: Option<String>,
+        data_hex: Option<String>,
+        url_safe: Option<bool>,
+        no_pad: Option<bool>,
+    },
+    base64_decode { data: String, as_text: Option<bool> },
+    base32_encode {
+        text: Option<String>,
+        data_base64: Option<String>,
+        extended_hex: Option<bool>,
+        no_pad: Option<bool>,
+    },
+    base32_decode { data: String, as_text: Option<bool>, extended_hex: Option<bool> },
+    hex_encode { text: Option<String>, data_base64: Option<String>, upper: Option<bool> },
+    hex_decode { data: String, as_text: Option<bool>, lenient: Option<bool> },
+    url_encode { text: String, component: Option<bool> },
+    url_decode(String),
+    url_parse(String),
+    url_build(urls::UrlParts),
+    compress {
+        text: Option<String>,
+        data_base64: Option<String>,
+        format: String,
+        level: Option<u32>,
+    },
+    decompress {
+        data_base64: String,
+        format: String,
+        as_text: Option<bool>,
+        max_size: Option<u64>,
+    },
+    #[cfg(feature = "net")]
+    http_get {
+        url: String,
+        headers: Option<HashMap<String, String>>,
+        timeout_ms: Option<u64>,
+    },
+    #[cfg(feature = "net")]
+    http_request(http::HttpRequest),
+    #[cfg(all(feature = "net", feature = "fs-write"))]
+    download(http::Download),
+    #[cfg(feature = "net")]
+    resolve { host: String, port: Option<u16> },
+    #[cfg(feature = "net")]
+    reverse_resolve(String),
+    #[cfg(feature = "net")]
+    tcp_request(net::TcpRequest),
+    #[cfg(feature = "net")]
+    tcp_connect_check { host: String, port: u16, timeout_ms: Option<u64> },
+    #[cfg(feature = "net")]
+    unix_request(net::UnixRequest),
+    #[cfg(feature = "net")]
+    udp_send(net::UdpSend),
+    #[cfg(feature = "net")]
+    http_unix(http::HttpUnix),
+    #[cfg(feature = "net")]
+    interfaces,
+    #[cfg(feature = "net")]
+    primary_ip { family: Option<String> },
+    hash { text: String, algo: String },
+    crc32 {
+        text: Option<String>,
+        data_base64: Option<String>,
+        path: Option<String>,
+        variant: Option<String>,
+    },
+    hash_bytes { data: String, algo: String },
+    hmac(hash::HmacInput),
+    hmac_verify {
+        #[serde(flatten)]
+        input: hash::HmacInput,
+        expected: String,
+    },
+    uuid(Option<usize>),
+    uuid_v7(Option<usize>),
+    uuid_parse(String),
+    shuffle(Value),
+    choice(Value),
+    sample { array: Value, count: usize, replace: Option<bool> },
+    retry {
+        cmd: Box<Command>,
+        attempts: u32,
+        delay_ms: u64,
+        backoff: Option<f64>,
+        retry_on: Option<Vec<i32>>,
+    },
+    batch(Batch),
+    hello,
+    echo(Value),
+    ping,
+    stats,
+    stats_reset,
+    spawn_task(Box<Command>),
+    task_status(u32),
+    task_result { id: u32, timeout_ms: Option<u64> },
+    cancel_task(u32),
+    with_timeout { timeout_ms: u64, cmd: Box<Command> },
     exit(i32),
 }
 
@@ -212,21 +583,259 @@ pub enum Command {
 pub enum Error {
     #[error("{0}")]
     IoError(#[from] io::Error),
+    #[error("{source}: {path:?}")]
+    PathIo { path: String, source: io::Error },
     #[error("json error: {0}")]
     JsonError(#[from] serde_json::Error),
+    #[cfg(feature = "net")]
+    #[error("http error: {0}")]
+    Http(#[from] ureq::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("name not found: {0:?}")]
+    NameNotFound(String),
+    #[error("resolve error: {0}")]
+    Resolve(String),
+    #[error("connect {addr}: {source}")]
+    Connect { addr: String, source: io::Error },
+    #[error("http status {0}")]
+    HttpStatus(u16),
+    #[error("checksum mismatch, expected {expected} but got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("time format error: {0}")]
+    TimeFormat(#[from] time::error::Format),
+    #[error("invalid time: {0}")]
+    InvalidTime(String),
+    #[error("invalid command: {0}")]
+    InvalidCommand(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
     #[error("invalid string: {0:?}")]
     InvalidString(String),
     #[error("invalid processor id: {0}")]
     InvalidProcessorId(u32),
+    #[error("invalid signal: {0:?}")]
+    InvalidSignal(String),
+    #[error("environment variable not found: {0}")]
+    EnvNotFound(String),
+    #[error("timer not found: {0:?}")]
+    TimerNotFound(String),
+    #[error("no child process to wait")]
+    NoChildProcess,
+    #[error("invalid task id: {0}")]
+    InvalidTaskId(u32),
+    #[error("task {0} is still running")]
+    TaskPending(u32),
+    #[error("invalid progress id: {0}")]
+    InvalidProgressId(u32),
+    #[error("no notification service: {0}")]
+    NotifyUnavailable(String),
+    #[error("no application to open it: {0}")]
+    NoApplication(String),
+    #[error("no clipboard: {0}")]
+    ClipboardUnavailable(String),
+    #[error("timed out after {0} ms")]
+    Timeout(u64),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("command is not allowed inside {0}")]
+    NotAllowedInside(&'static str),
+    #[error("path escapes the root: {0:?}")]
+    PathEscape(String),
+    #[error("command is denied: {0}")]
+    Denied(String),
+    #[error("unsupported on this platform: {0}")]
+    Unsupported(&'static str),
+    /// `size` is the bytes serialized when the answer was given up, not its whole size
+    #[error("response exceeds {limit} bytes")]
+    ResponseTooLarge { size: usize, limit: usize },
+}
+
+impl Error {
+    /// The underlying OS error, if any
+    pub fn io(&self) -> Option<&io::Error> {
+        match self {
+            Error::IoError(e)
+            | Error::PathIo { source: e, .. }
+            | Error::Connect { source: e, .. } => Some(e),
+            #[cfg(feature = "net")]
+            Error::Http(ureq::Error::Io(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The offending path of filesystem errors
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Error::PathIo { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Stable snake_case name, the [`io::ErrorKind`] of OS errors
+    pub fn kind(&self) -> &'static str {
+        if let Some(e) = self.io() {
+            return io_kind(e.kind());
+        }
+        match self {
+            Error::IoError(_) | Error::PathIo { .. } | Error::Connect { .. } => "other",
+            Error::JsonError(_) => "json",
+            #[cfg(feature = "net")]
+            Error::Http(_) => "http",
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(_) => "sqlite",
+            Error::NameNotFound(_) => "name_not_found",
+            Error::Resolve(_) => "resolve",
+            Error::HttpStatus(_) => "http_status",
+            Error::ChecksumMismatch { .. } => "checksum_mismatch",
+            Error::TimeFormat(_) => "time_format",
+            Error::InvalidTime(_) => "invalid_time",
+            Error::InvalidCommand(_) => "invalid_command",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::InvalidString(_) => "invalid_string",
+            Error::InvalidProcessorId(_) => "invalid_processor_id",
+            Error::InvalidSignal(_) => "invalid_signal",
+            Error::EnvNotFound(_) => "env_not_found",
+            Error::TimerNotFound(_) => "timer_not_found",
+            Error::NoChildProcess => "no_child_process",
+            Error::InvalidTaskId(_) => "invalid_task_id",
+            Error::TaskPending(_) => "task_pending",
+            Error::InvalidProgressId(_) => "invalid_progress_id",
+            Error::NotifyUnavailable(_) => "notify_unavailable",
+            Error::NoApplication(_) => "no_application",
+            Error::ClipboardUnavailable(_) => "clipboard_unavailable",
+            Error::Timeout(_) => "timeout",
+            Error::Cancelled => "cancelled",
+            Error::NotAllowedInside(_) => "not_allowed_inside",
+            Error::PathEscape(_) => "path_escape",
+            Error::Denied(_) => "denied",
+            Error::Unsupported(_) => "unsupported",
+            Error::ResponseTooLarge { .. } => "response_too_large",
+        }
+    }
+
+    /// `{message, kind, os_code, path}`, and `size` and `limit` of `response_too_large`
+    pub fn to_value(&self) -> Value {
+        let mut value = json!({
+            "message": self.to_string(),
+            "kind": self.kind(),
+            "os_code": self.io().and_then(io::Error::raw_os_error),
+            "path": self.path(),
+        });
+        if let Error::ResponseTooLarge { size, limit } = self {
+            value["size"] = (*size).into();
+            value["limit"] = (*limit).into();
+        }
+        value
+    }
+}
+
+fn io_kind(kind: io::ErrorKind) -> &'static str {
+    use io::ErrorKind as K;
+    match kind {
+        K::NotFound => "not_found",
+        K::PermissionDenied => "permission_denied",
+        K::ConnectionRefused => "connection_refused",
+        K::ConnectionReset => "connection_reset",
+        K::HostUnreachable => "host_unreachable",
+        K::NetworkUnreachable => "network_unreachable",
+        K::ConnectionAborted => "connection_aborted",
+        K::NotConnected => "not_connected",
+        K::AddrInUse => "addr_in_use",
+        K::AddrNotAvailable => "addr_not_available",
+        K::NetworkDown => "network_down",
+        K::BrokenPipe => "broken_pipe",
+        K::AlreadyExists => "already_exists",
+        K::WouldBlock => "would_block",
+        K::NotADirectory => "not_a_directory",
+        K::IsADirectory => "is_a_directory",
+        K::DirectoryNotEmpty => "directory_not_empty",
+        K::ReadOnlyFilesystem => "read_only_filesystem",
+        K::StaleNetworkFileHandle => "stale_network_file_handle",
+        K::InvalidInput => "invalid_input",
+        K::InvalidData => "invalid_data",
+        K::TimedOut => "timed_out",
+        K::WriteZero => "write_zero",
+        K::StorageFull => "storage_full",
+        K::NotSeekable => "not_seekable",
+        K::QuotaExceeded => "quota_exceeded",
+        K::FileTooLarge => "file_too_large",
+        K::ResourceBusy => "resource_busy",
+        K::ExecutableFileBusy => "executable_file_busy",
+        K::Deadlock => "deadlock",
+        K::CrossesDevices => "crosses_devices",
+        K::TooManyLinks => "too_many_links",
+        K::InvalidFilename => "invalid_filename",
+        K::ArgumentListTooLong => "argument_list_too_long",
+        K::Interrupted => "interrupted",
+        K::Unsupported => "unsupported",
+        K::UnexpectedEof => "unexpected_eof",
+        K::OutOfMemory => "out_of_memory",
+        _ => "other",
+    }
 }
 
 pub const NONE_EXIT_CODE: i32 = 250;
 
-fn path_it(path: impl AsRef<Path>) -> Result<Value, Error> {
-    let path = path.as_ref();
-    path.to_str()
-        .map(Into::into)
-        .ok_or_else(|| Error::InvalidString(path.to_string_lossy().into()))
+/// Exited children kept for `wait_id` before the oldest are dropped
+pub const MAX_FINISHED: usize = 1024;
+
+/// Answer of `task_result`, `{"ok"}` or `{"err"}` of the command,
+/// a pending task is to be put back
+fn task_result(
+    ctx: &Context,
+    id: u32,
+    task: &tasks::Task,
+    result: Result<Result<Value, Error>, mpsc::RecvTimeoutError>,
+) -> Result<Value, Error> {
+    match result {
+        Ok(Ok(value)) => Ok(json!({"ok": value})),
+        Ok(Err(e)) => Ok(json!({"err": ctx.error_it(Some(task.command()), &e)})),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::TaskPending(id)),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(Error::InvalidTaskId(id)),
+    }
+}
+
+/// Blank lines and repeats of the last entry are not kept
+fn add_history(history: &mut Vec<String>, line: String) {
+    if !line.trim().is_empty() && history.last() != Some(&line) {
+        history.push(line);
+    }
+}
+
+/// Answer of `readline`, the line is added to the history
+fn readline_it(ctx: &mut Context, history_id: &Option<String>, read: term::Readline) -> Value {
+    match read {
+        term::Readline::Line(line) => {
+            add_history(ctx.history(history_id), line.clone());
+            line.into()
+        },
+        term::Readline::Interrupted => json!({"interrupted": true}),
+        term::Readline::Eof => Value::Null,
+    }
+}
+
+/// Version of the line protocol, reported by `hello`
+pub const PROTOCOL_VERSION: u32 = 1;
+
+fn path_io(path: &str) -> impl Fn(io::Error) -> Error + '_ {
+    move |source| Error::PathIo { path: path.into(), source }
+}
+
+/// Path of the bridge executable, error if it has been unlinked
+///
+/// Linux reports an unlinked executable with a ` (deleted)` suffix,
+/// which is surfaced as the not found error
+fn current_exe() -> Result<PathBuf, Error> {
+    let exe = env::current_exe()?;
+    if !exe.exists() {
+        return Err(Error::PathIo {
+            path: exe.to_string_lossy().into(),
+            source: io::ErrorKind::NotFound.into(),
+        });
+    }
+    Ok(exe)
 }
 
 fn oss_it(path: impl AsRef<OsStr>) -> Result<Value, Error> {
@@ -236,207 +845,3162 @@ fn oss_it(path: impl AsRef<OsStr>) -> Result<Value, Error> {
         .ok_or_else(|| Error::InvalidString(s.to_string_lossy().into()))
 }
 
-fn time_it(time: SystemTime) -> String {
-    UtcDateTime::from(time).to_string()
+/// OS string of bytes, which must be UTF-8 off unix
+#[cfg(feature = "env-write")]
+fn os_string(bytes: Vec<u8>) -> Result<std::ffi::OsString, Error> {
+    #[cfg(unix)]
+    return Ok(std::os::unix::ffi::OsStringExt::from_vec(bytes));
+    #[cfg(not(unix))]
+    String::from_utf8(bytes)
+        .map(Into::into)
+        .map_err(|e| Error::InvalidString(String::from_utf8_lossy(e.as_bytes()).into()))
+}
+
+/// Refuse what `env::set_var` panics on, an empty name, `=` or NUL in the name and NUL in the value
+#[cfg(feature = "env-write")]
+fn env_checked(name: &str, value: Option<&OsStr>) -> Result<(), Error> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(Error::InvalidArgument(format!("invalid variable name {name:?}")));
+    }
+    if value.is_some_and(|value| value.as_encoded_bytes().contains(&0)) {
+        return Err(Error::InvalidArgument(format!("NUL in the value of {name}")));
+    }
+    Ok(())
+}
+
+/// Set the variables, those already set only when `override_existing`
+#[cfg(feature = "env-write")]
+fn apply_env(vars: &BTreeMap<String, String>, override_existing: bool) -> Result<(), Error> {
+    for (name, value) in vars {
+        env_checked(name, Some(value.as_ref()))?;
+    }
+    for (name, value) in vars {
+        if override_existing || env::var_os(name).is_none() {
+            unsafe { env::set_var(name, value) }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "env-write"))]
+fn apply_env(_vars: &BTreeMap<String, String>, _override_existing: bool) -> Result<(), Error> {
+    Err(Error::InvalidArgument("apply needs the env-write feature".into()))
+}
+
+fn time_it(time: SystemTime) -> Result<String, Error> {
+    Ok(UtcDateTime::from(time).format(&Rfc3339)?)
+}
+
+fn millis_it(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e3
+}
+
+/// Write a frame of a progress bar
+fn draw(output: &Output, frame: &str) -> io::Result<()> {
+    let mut out = output.lock();
+    out.write_all(frame.as_bytes())?;
+    out.print_done()
+}
+
+/// `{"status": code}`, with `status` null and `signal` / `core_dumped`
+/// added when the child was terminated by a signal
+#[cfg(feature = "proc")]
+fn status_it(status: ExitStatus) -> Map<String, Value> {
+    let mut result = Map::new();
+    result.insert("status".into(), status.code().into());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            result.insert("signal".into(), signal.into());
+            result.insert("core_dumped".into(), status.core_dumped().into());
+        }
+    }
+    // a crash exits with the NTSTATUS of the exception, e.g. 0xC0000005
+    #[cfg(windows)]
+    if let Some(code) = status.code()
+        && code as u32 & 0xC000_0000 == 0xC000_0000
+    {
+        result.insert("exception".into(), format!("{:#010X}", code as u32).into());
+    }
+    result
+}
+
+/// `file_attributes` and its `hidden` and `system` bits on Windows
+#[cfg(windows)]
+fn attributes_it(metadata: &fs::Metadata, result: &mut Value) {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM};
+
+    let attributes = metadata.file_attributes();
+    result["file_attributes"] = attributes.into();
+    result["hidden"] = (attributes & FILE_ATTRIBUTE_HIDDEN != 0).into();
+    result["system"] = (attributes & FILE_ATTRIBUTE_SYSTEM != 0).into();
+}
+
+#[cfg(not(windows))]
+fn attributes_it(_metadata: &fs::Metadata, _result: &mut Value) {}
+
+/// Poll `exited` with a backoff, `{"timed_out": true}` once `timeout_ms` is over
+#[cfg(feature = "proc")]
+fn wait_any(
+    timeout_ms: Option<u64>,
+    mut exited: impl FnMut() -> Result<Option<Value>, Error>,
+) -> Result<Value, Error> {
+    let deadline = timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(exited) = exited()? {
+            return Ok(exited);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(json!({"timed_out": true}));
+        }
+        sleep(interval);
+        interval = (interval * 2).min(Duration::from_millis(50));
+    }
+}
+
+#[cfg(feature = "proc")]
+fn output_it(ctx: &Context, output: process::Output) -> Value {
+    let mut result = status_it(output.status);
+    result.insert("stdout".into(), ctx.bytes_it(output.stdout));
+    result.insert("stderr".into(), ctx.bytes_it(output.stderr));
+    result.into()
+}
+
+/// Send each line of `stream` without the newline
+#[cfg(feature = "proc")]
+fn stream_lines<R>(
+    name: &'static str,
+    stream: R,
+    send: mpsc::Sender<(&'static str, String)>,
+)
+where R: io::Read + Send + 'static,
+{
+    spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut line = vec![];
+        while reader.read_until(b'\n', &mut line)? != 0 {
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            if send.send((name, text)).is_err() {
+                break;
+            }
+            line.clear();
+        }
+        io::Result::Ok(())
+    });
+}
+
+/// Exit code of a process command result,
+/// `Some(None)` when the process has no exit code
+#[cfg_attr(not(feature = "proc"), allow(unused_variables))]
+fn process_code(cmd: &Command, value: &Value) -> Option<Option<i64>> {
+    match cmd {
+        #[cfg(feature = "proc")]
+        Command::system(..)
+        | Command::popen(..)
+        | Command::command(..)
+        | Command::command_pty { .. }
+        | Command::stream_command { .. } => {
+            Some(value["status"].as_i64())
+        },
+        _ => None,
+    }
+}
+
+/// Lock ignoring poison, a panicked command leaves the context usable
+pub fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A command line, optionally tagged with an `id` to be answered concurrently
+#[derive(Debug)]
+pub struct Request {
+    pub id: Option<Value>,
+    /// Echoed by the answer, the command is still answered in order
+    pub tag: Option<Value>,
+    pub cmd: Command,
+    /// An err answer does not end a `--strict` session
+    pub allow_error: bool,
+}
+
+/// A line that is not a valid command, the `id` and `tag` are kept for its answer
+#[derive(Debug)]
+pub struct InvalidRequest {
+    pub id: Option<Value>,
+    pub tag: Option<Value>,
+    pub error: Error,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    // a null id is still a tag
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    #[serde(default, deserialize_with = "present")]
+    tag: Option<Value>,
+    cmd: Value,
+    #[serde(default)]
+    allow_error: bool,
+}
+
+fn present<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(de).map(Some)
+}
+
+impl Request {
+    /// Parse a line, an invalid one keeps what it could of the envelope
+    pub fn parse(line: &str) -> Result<Self, Box<InvalidRequest>> {
+        let Ok(Envelope { id, tag, cmd, allow_error }) = serde_json::from_str(line) else {
+            return Command::parse(line)
+                .map(|cmd| Request { id: None, tag: None, cmd, allow_error: false })
+                .map_err(|error| Box::new(InvalidRequest { id: None, tag: None, error }));
+        };
+        match Command::deserialize(&cmd) {
+            Ok(cmd) => Ok(Request { id, tag, cmd, allow_error }),
+            Err(e) => {
+                let error = invalid_command(e, &cmd.to_string());
+                Err(Box::new(InvalidRequest { id, tag, error }))
+            },
+        }
+    }
+}
+
+/// Receive the parts of a multi-part response
+pub type PartSink<'a> = dyn FnMut(Value) -> Result<(), Error> + 'a;
+
+/// How the argument of a command is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// No argument, the command is the name string
+    Unit,
+    /// `{name: value}`
+    Value,
+    /// `{name: {field: value}}`
+    Fields,
+}
+
+/// [`Command::groups`], [`Command::name`], [`Command::groups_of`] and [`Command::shape_of`] from one list,
+/// unit variants are written `name _`
+macro_rules! command_groups {
+    (@pat $variant:ident _) => { Command::$variant };
+    (@pat $variant:ident $fields:tt) => { Command::$variant $fields };
+    (@shape _) => { Shape::Unit };
+    (@shape ($($_:tt)*)) => { Shape::Value };
+    (@shape {$($_:tt)*}) => { Shape::Fields };
+    ($($(#[$meta:meta])* $($variant:ident $fields:tt)|+ => $groups:expr,)+) => {
+        impl Command {
+            /// Policy groups of the command, see [`policy`],
+            /// the match is exhaustive so that a new command must be classified
+            pub fn groups(&self) -> &'static [&'static str] {
+                match self {
+                    $($(#[$meta])* $(command_groups!(@pat $variant $fields))|+ => $groups,)+
+                }
+            }
+
+            /// Variant name, e.g. `"read"`
+            pub fn name(&self) -> &'static str {
+                // the attributes are those of a line, matched again for its names
+                match self {
+                    $($(#[$meta])* $(command_groups!(@pat $variant $fields))|+ => match self {
+                        $(command_groups!(@pat $variant $fields) => stringify!($variant),)+
+                        _ => unreachable!(),
+                    },)+
+                }
+            }
+
+            /// Policy groups of the command `name`, those of its first listed form
+            pub fn groups_of(name: &str) -> Option<&'static [&'static str]> {
+                #[allow(unreachable_patterns)]
+                match name {
+                    $($(#[$meta])* $(stringify!($variant))|+ => Some($groups),)+
+                    _ => None,
+                }
+            }
+
+            /// Argument shape of the command `name`
+            pub fn shape_of(name: &str) -> Option<Shape> {
+                #[allow(unreachable_patterns)]
+                match name {
+                    $($(#[$meta])* $(stringify!($variant))|+ => match name {
+                        $(stringify!($variant) => Some(command_groups!(@shape $fields)),)+
+                        _ => None,
+                    },)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+command_groups! {
+    read(..)
+    | read_dir(..)
+    | read_link(..)
+    | metadata(..)
+    | metadata_extra(..)
+    | exists(..)
+    | is_symlink(..)
+    | is_dir(..)
+    | is_file(..)
+    | history_load { .. } => &["fs-read"],
+    #[cfg(feature = "fs-write")]
+    write { .. }
+    | append { .. }
+    | history_save { .. } => &["fs-write"],
+    // the plain form first, it gives the groups of the name
+    #[cfg(feature = "sqlite")]
+    sqlite_query { readonly: None | Some(true), .. } | sqlite_close(..) => &["fs-read"],
+    #[cfg(feature = "sqlite")]
+    sqlite_query { readonly: Some(false), .. } => &["fs-read", "fs-write"],
+    print(..)
+    | println(..)
+    | pretty(..)
+    | pretty_pipe(..)
+    | eprint(..)
+    | eprintln(..)
+    | eprint_pretty(..)
+    | set_buffering { .. }
+    | flush _
+    | stdin _
+    | stdin_line _
+    | prompt { .. }
+    | read_password { .. }
+    | readline { .. }
+    | history_add { .. }
+    | confirm { .. }
+    | is_tty { .. }
+    | terminal_size _
+    | style { .. }
+    | progress_start { .. }
+    | progress_update { .. }
+    | progress_finish { .. } => &["io"],
+    #[cfg(feature = "notify")]
+    notify { .. } => &["io"],
+    #[cfg(feature = "clipboard")]
+    clipboard_get _ | clipboard_set(..) => &["io"],
+    current_dir _
+    | set_current_dir(..)
+    | temp_dir _
+    | app_dirs { .. }
+    | get_env(..)
+    | env_vars { .. } => &["env"],
+    #[cfg(feature = "env-write")]
+    set_env(..) | remove_env(..) => &["env"],
+    // the plain form first, it gives the groups of the name
+    load_dotenv { apply: None | Some(false), .. } => &["fs-read"],
+    load_dotenv { apply: Some(true), .. } => &["fs-read", "env"],
+    #[cfg(feature = "proc")]
+    system(..)
+    | popen(..)
+    | command(..)
+    | spawn(..)
+    | stream_command { .. }
+    | spawn_detached(..)
+    | command_pty { .. }
+    | wait_id { .. }
+    | wait_any { .. }
+    | kill_id { .. }
+    | kill_tree_id { .. } => &["proc"],
+    #[cfg(feature = "open")]
+    open_path { .. } | open_url { .. } => &["proc"],
+    current_exe _
+    | exe_dir _
+    | platform _
+    | cpu_count _
+    | memory_info _
+    | load_average _
+    | uptime _
+    | hostname _
+    | user_info _
+    | lookup_user(..)
+    | lookup_group(..)
+    | groups _
+    | locale_info _
+    | process_id _
+    | parent_process_id _
+    | process_info(..)
+    | list_processes { .. }
+    | get_umask _
+    | set_umask(..)
+    | pid_alive(..)
+    | pid_start_time(..) => &["sys"],
+    now _
+    | now_monotonic _
+    | local_now _
+    | timezone _
+    | timer_start(..)
+    | timer_elapsed(..)
+    | timer_stop(..)
+    | timer_list _
+    | sleep(..)
+    | sleep_until { .. }
+    | format_time { .. }
+    | parse_time { .. } => &["time"],
+    random _
+    | random_float _
+    | random_normal { .. }
+    | random_exp { .. }
+    | set_seed(..)
+    | clear_seed _
+    | random_range { .. }
+    | random_float_range { .. }
+    | random_below(..)
+    | random_bytes { .. }
+    | random_string { .. }
+    | uuid(..)
+    | uuid_v7(..)
+    | shuffle(..)
+    | choice(..)
+    | sample { .. } => &["random"],
+    base64_encode { .. }
+    | base64_decode { .. }
+    | base32_encode { .. }
+    | base32_decode { .. }
+    | hex_encode { .. }
+    | hex_decode { .. }
+    | url_encode { .. }
+    | url_decode(..)
+    | url_parse(..)
+    | url_build(..)
+    | compress { .. }
+    | decompress { .. }
+    | hash { .. }
+    | hash_bytes { .. }
+    | hmac(..)
+    | hmac_verify { .. }
+    | uuid_parse(..) => &["encoding"],
+    #[cfg(feature = "net")]
+    http_get { .. }
+    | http_request(..)
+    | resolve { .. }
+    | reverse_resolve(..)
+    | tcp_request(..)
+    | tcp_connect_check { .. }
+    | unix_request(..)
+    | udp_send(..)
+    | http_unix(..)
+    | interfaces _
+    | primary_ip { .. } => &["net"],
+    exit(..) => &["control"],
+    #[cfg(feature = "net")]
+    fqdn _ => &["sys", "net"],
+    #[cfg(all(feature = "net", feature = "fs-write"))]
+    download(_) => &["net", "fs-write"],
+    // the plain form first, it gives the groups of the name
+    crc32 { path: None, .. } => &["encoding"],
+    crc32 { path: Some(_), .. } => &["encoding", "fs-read"],
+    // only the wrapped commands are checked
+    retry { .. } | batch(_) | spawn_task(_) | with_timeout { .. } => &[],
+    // protocol commands, always available
+    hello _
+    | echo(..)
+    | ping _
+    | stats _
+    | stats_reset _
+    | task_status(..)
+    | task_result { .. }
+    | cancel_task(..) => &[],
 }
 
 impl Command {
+    /// Parse a command line, the error quotes the line cut to a readable length
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        serde_json::from_str(line).map_err(|e| invalid_command(e, line))
+    }
+
+    /// Commands wrapped by `retry`, `batch`, `spawn_task` and `with_timeout`
+    pub fn inner(&self) -> &[Command] {
+        match self {
+            Command::retry { cmd, .. }
+            | Command::spawn_task(cmd)
+            | Command::with_timeout { cmd, .. } => std::slice::from_ref(cmd),
+            Command::batch(Batch::Cmds(cmds) | Batch::With { cmds, .. }) => cmds,
+            _ => &[],
+        }
+    }
+
+    /// Whether the command reads or changes the [`Context`]
+    pub fn uses_context(&self) -> bool {
+        match self {
+            Command::retry { .. } | Command::batch(_) => {
+                self.inner().iter().any(Command::uses_context)
+            },
+            Command::random_bytes { secure, .. } => !secure.is_true(),
+            #[cfg(feature = "proc")]
+            Command::spawn(..)
+            | Command::wait_id { .. }
+            | Command::wait_any { .. }
+            | Command::kill_id { .. }
+            | Command::kill_tree_id { .. } => true,
+            #[cfg(feature = "fs-write")]
+            Command::history_save { .. } => true,
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_query { .. } | Command::sqlite_close(_) => true,
+            _ => matches!(self,
+                | Command::now_monotonic
+                | Command::exit(_)
+                | Command::stats
+                | Command::stats_reset
+                | Command::readline { .. }
+                | Command::history_add { .. }
+                | Command::history_load { .. }
+                | Command::timer_start(_)
+                | Command::timer_elapsed(_)
+                | Command::timer_stop(_)
+                | Command::timer_list
+                | Command::progress_start { .. }
+                | Command::progress_update { .. }
+                | Command::progress_finish { .. }
+                | Command::random
+                | Command::random_float
+                | Command::random_normal { .. }
+                | Command::random_exp { .. }
+                | Command::set_seed(_)
+                | Command::clear_seed
+                | Command::random_range { .. }
+                | Command::random_float_range { .. }
+                | Command::random_below(_)
+                | Command::random_string { .. }
+                | Command::uuid(_)
+                | Command::uuid_v7(_)
+                | Command::shuffle(_)
+                | Command::choice(_)
+                | Command::sample { .. }
+                | Command::spawn_task(_)
+                | Command::task_status(_)
+                | Command::task_result { .. }
+                | Command::cancel_task(_)
+            ),
+        }
+    }
+
+    /// Run with a shared context, locked only by the commands using it,
+    /// `wait_id` only takes the child out and waits unlocked
+    pub fn run_shared(
+        &self,
+        ctx: &Mutex<Context>,
+        part: &mut PartSink<'_>,
+    ) -> Result<Value, Error> {
+        match self {
+            #[cfg(feature = "proc")]
+            Command::wait_id { id, output } => {
+                let mut child = lock(ctx).child(*id)?;
+                if output.is_true() {
+                    let output = child.wait_with_output()?;
+                    Ok(output_it(&lock(ctx), output))
+                } else {
+                    Ok(status_it(child.wait()?).into())
+                }
+            },
+            #[cfg(feature = "proc")]
+            Command::wait_any { ids, timeout_ms } => {
+                // locked for each poll, not while waiting
+                let ids = lock(ctx).wait_any_ids(ids)?;
+                wait_any(*timeout_ms, || lock(ctx).try_wait_any(&ids))
+            },
+            Command::task_result { id, timeout_ms } => {
+                let mut task = lock(ctx).task(*id)?;
+                let result = task.wait(timeout_ms.map(Duration::from_millis));
+                let mut ctx = lock(ctx);
+                let value = task_result(&ctx, *id, &task, result);
+                if value.is_err() {
+                    ctx.tasks.insert(*id, task);
+                }
+                value
+            },
+            Command::with_timeout { timeout_ms, cmd } => {
+                let detached = cmd.timeout_context(&mut lock(ctx))?;
+                cmd.run_timeout(detached, *timeout_ms)
+            },
+            Command::readline { prompt, history_id } => {
+                // only the history is locked, not the whole edit
+                let (output, history) = {
+                    let mut ctx = lock(ctx);
+                    ctx.flush_outputs()?;
+                    (ctx.error_output(), ctx.history(history_id).clone())
+                };
+                let read = term::readline(&output, prompt, &history)?;
+                Ok(readline_it(&mut lock(ctx), history_id, read))
+            },
+            _ if self.uses_context() => self.run_with(&mut lock(ctx), part),
+            _ => {
+                let mut detached = lock(ctx).detached();
+                self.run_with(&mut detached, part)
+            },
+        }
+    }
+
+    /// Whether the command changes files, the environment or processes, not run by `--dry-run`,
+    /// the match is exhaustive so that a new command must be classified
+    pub fn mutates(&self) -> bool {
+        match self {
+            Command::set_current_dir(..)
+            | Command::set_umask(..) => true,
+            #[cfg(feature = "fs-write")]
+            Command::write { .. }
+            | Command::append { .. }
+            | Command::history_save { .. } => true,
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_query { readonly, .. } => *readonly == Some(false),
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_close(_) => false,
+            #[cfg(feature = "env-write")]
+            Command::set_env(..)
+            | Command::remove_env(..) => true,
+            Command::load_dotenv { apply, .. } => apply.is_true(),
+            #[cfg(feature = "proc")]
+            Command::system(..)
+            | Command::popen(..)
+            | Command::command(..)
+            | Command::command_pty { .. }
+            | Command::stream_command { .. }
+            | Command::spawn(..)
+            | Command::spawn_detached(..)
+            | Command::kill_id { .. }
+            | Command::kill_tree_id { .. } => true,
+            #[cfg(all(feature = "net", feature = "fs-write"))]
+            Command::download(..) => true,
+            #[cfg(feature = "open")]
+            Command::open_path { .. } | Command::open_url { .. } => true,
+            // wrappers are not, their commands are checked when run
+            Command::read(..)
+            | Command::read_dir(..)
+            | Command::read_link(..)
+            | Command::metadata(..)
+            | Command::metadata_extra(..)
+            | Command::exists(..)
+            | Command::is_symlink(..)
+            | Command::is_dir(..)
+            | Command::is_file(..)
+            | Command::history_load { .. }
+            | Command::print(..)
+            | Command::println(..)
+            | Command::pretty(..)
+            | Command::pretty_pipe(..)
+            | Command::eprint(..)
+            | Command::eprintln(..)
+            | Command::eprint_pretty(..)
+            | Command::set_buffering { .. }
+            | Command::flush
+            | Command::stdin
+            | Command::stdin_line
+            | Command::prompt { .. }
+            | Command::read_password { .. }
+            | Command::readline { .. }
+            | Command::history_add { .. }
+            | Command::confirm { .. }
+            | Command::is_tty { .. }
+            | Command::terminal_size
+            | Command::style { .. }
+            | Command::progress_start { .. }
+            | Command::progress_update { .. }
+            | Command::progress_finish { .. }
+            | Command::current_dir
+            | Command::temp_dir
+            | Command::app_dirs { .. }
+            | Command::get_env(..)
+            | Command::env_vars { .. }
+            | Command::current_exe
+            | Command::exe_dir
+            | Command::platform
+            | Command::cpu_count
+            | Command::memory_info
+            | Command::load_average
+            | Command::uptime
+            | Command::hostname
+            | Command::user_info
+            | Command::lookup_user(..)
+            | Command::lookup_group(..)
+            | Command::groups
+            | Command::locale_info
+            | Command::process_id
+            | Command::parent_process_id
+            | Command::process_info(..)
+            | Command::list_processes { .. }
+            | Command::get_umask
+            | Command::pid_alive(..)
+            | Command::pid_start_time(..)
+            | Command::now
+            | Command::now_monotonic
+            | Command::local_now
+            | Command::timezone
+            | Command::timer_start(..)
+            | Command::timer_elapsed(..)
+            | Command::timer_stop(..)
+            | Command::timer_list
+            | Command::sleep(..)
+            | Command::sleep_until { .. }
+            | Command::format_time { .. }
+            | Command::parse_time { .. }
+            | Command::random
+            | Command::random_float
+            | Command::random_normal { .. }
+            | Command::random_exp { .. }
+            | Command::set_seed(..)
+            | Command::clear_seed
+            | Command::random_range { .. }
+            | Command::random_float_range { .. }
+            | Command::random_below(..)
+            | Command::random_bytes { .. }
+            | Command::random_string { .. }
+            | Command::uuid(..)
+            | Command::uuid_v7(..)
+            | Command::shuffle(..)
+            | Command::choice(..)
+            | Command::sample { .. }
+            | Command::base64_encode { .. }
+            | Command::base64_decode { .. }
+            | Command::base32_encode { .. }
+            | Command::base32_decode { .. }
+            | Command::hex_encode { .. }
+            | Command::hex_decode { .. }
+            | Command::url_encode { .. }
+            | Command::url_decode(..)
+            | Command::url_parse(..)
+            | Command::url_build(..)
+            | Command::compress { .. }
+            | Command::decompress { .. }
+            | Command::hash { .. }
+            | Command::hash_bytes { .. }
+            | Command::hmac(..)
+            | Command::hmac_verify { .. }
+            | Command::uuid_parse(..)
+            | Command::exit(..)
+            | Command::crc32 { .. }
+            | Command::retry { .. }
+            | Command::batch(..)
+            | Command::spawn_task(..)
+            | Command::with_timeout { .. }
+            | Command::hello
+            | Command::echo(..)
+            | Command::ping
+            | Command::stats
+            | Command::stats_reset
+            | Command::task_status(..)
+            | Command::task_result { .. }
+            | Command::cancel_task(..) => false,
+            #[cfg(feature = "proc")]
+            Command::wait_id { .. }
+            | Command::wait_any { .. } => false,
+            #[cfg(feature = "net")]
+            Command::http_get { .. }
+            | Command::http_request(..)
+            | Command::resolve { .. }
+            | Command::reverse_resolve(..)
+            | Command::tcp_request(..)
+            | Command::tcp_connect_check { .. }
+            | Command::unix_request(..)
+            | Command::udp_send(..)
+            | Command::http_unix(..)
+            | Command::interfaces
+            | Command::primary_ip { .. }
+            | Command::fqdn => false,
+            #[cfg(feature = "notify")]
+            Command::notify { .. } => false,
+            #[cfg(feature = "clipboard")]
+            Command::clipboard_get | Command::clipboard_set(_) => false,
+        }
+    }
+
+    /// Stand-in result of a mutating command under `--dry-run`, plausible to go on with
+    fn dry_run_result(&self, ctx: &Context) -> Result<Value, Error> {
+        Ok(match self {
+            Command::set_current_dir(_) => ctx.path_it(&env::current_dir()?, ".")?,
+            #[cfg(feature = "proc")]
+            Command::system(..) => json!({"status": 0}),
+            #[cfg(feature = "proc")]
+            Command::popen(..) => json!({"status": 0, "stdout": ""}),
+            #[cfg(feature = "proc")]
+            Command::command(..) => json!({"status": 0, "stdout": "", "stderr": ""}),
+            #[cfg(feature = "proc")]
+            Command::command_pty { .. } => json!({"status": 0, "output": ""}),
+            #[cfg(feature = "proc")]
+            Command::stream_command { .. } => json!({"status": 0}),
+            #[cfg(feature = "proc")]
+            Command::spawn(..) | Command::spawn_detached(..) => 0.into(),
+            #[cfg(feature = "proc")]
+            Command::kill_tree_id { .. } => json!({"signaled": [], "errors": []}),
+            Command::set_umask(_) => 0o022.into(),
+            Command::load_dotenv { .. } => json!({"vars": {}, "warnings": []}),
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_query { .. } => json!({"rows_affected": 0}),
+            #[cfg(all(feature = "net", feature = "fs-write"))]
+            Command::download(_) => json!({"status": 200, "bytes_written": 0, "resumed": false}),
+            _ => Value::Null,
+        })
+    }
+
+    /// This command wrapped in `with_timeout`, unless it cannot run on a helper thread
+    pub fn timed(self, timeout_ms: u64) -> Self {
+        if !self.can_time_out() || matches!(self, Command::with_timeout { .. }) {
+            return self;
+        }
+        Command::with_timeout { timeout_ms, cmd: Box::new(self) }
+    }
+
+    /// Reads the bridge's stdin, which cannot be interrupted
+    fn reads_stdin(&self) -> bool {
+        matches!(
+            self,
+            Command::stdin
+            | Command::stdin_line
+            | Command::prompt { .. }
+            | Command::read_password { .. }
+            | Command::readline { .. }
+            | Command::confirm { .. },
+        )
+    }
+
+    fn can_time_out(&self) -> bool {
+        match self {
+            // parts cannot be sent from the helper thread
+            #[cfg(feature = "proc")]
+            Command::stream_command { .. } => false,
+            Command::exit(_) => false,
+            _ if self.reads_stdin() => false,
+            #[cfg(feature = "proc")]
+            Command::wait_id { .. } => true,
+            _ => !self.uses_context(),
+        }
+    }
+
+    /// Context of `cmd` run by `with_timeout` on a helper thread,
+    /// the child of `wait_id` is taken out and abandoned on timeout
+    fn timeout_context(&self, ctx: &mut Context) -> Result<Context, Error> {
+        if !self.can_time_out() {
+            return Err(Error::NotAllowedInside("with_timeout"));
+        }
+        #[cfg_attr(not(feature = "proc"), allow(unused_mut))]
+        let mut detached = ctx.detached();
+        #[cfg(feature = "proc")]
+        if let &Command::wait_id { id, .. } = self {
+            detached.sub_processors.insert(id, ctx.child(id)?);
+        }
+        Ok(detached)
+    }
+
+    /// Run on a helper thread, abandoned after `timeout_ms` with a cancellation
+    fn run_timeout(&self, ctx: Context, timeout_ms: u64) -> Result<Value, Error> {
+        let mut task = tasks::Task::spawn(self.clone(), ctx);
+        match task.wait(Some(Duration::from_millis(timeout_ms))) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                task.cancel();
+                Err(Error::Timeout(timeout_ms))
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::other("helper thread died").into())
+            },
+        }
+    }
+
+    /// Run without a part sink, parts of multi-part responses are dropped
     pub fn run(&self, ctx: &mut Context) -> Result<Value, Error> {
+        self.run_with(ctx, &mut |_| Ok(()))
+    }
+
+    #[cfg_attr(not(feature = "proc"), allow(clippy::only_used_in_recursion))]
+    pub fn run_with(
+        &self,
+        ctx: &mut Context,
+        part: &mut PartSink<'_>,
+    ) -> Result<Value, Error> {
+        if ctx.dry_run && self.mutates() {
+            let mut out = ctx.error_output.lock();
+            // shown like the log shows it
+            let mut shown = serde_json::to_value(self)?;
+            audit::redact_command(&mut shown);
+            writeln!(out, "dry run: {shown}")?;
+            out.print_done()?;
+            drop(out);
+            return self.dry_run_result(ctx);
+        }
         Ok(match self {
-            Command::read(path) => {
-                fs::read_to_string(path)?.into()
+            Command::read(raw) => {
+                let path = ctx.resolve_path(raw)?;
+                if ctx.binary_safe {
+                    encoding::data_it(fs::read(path).map_err(path_io(raw))?)
+                } else {
+                    fs::read_to_string(path).map_err(path_io(raw))?.into()
+                }
             },
-            Command::write { path, text, must_new } => {
+            #[cfg(feature = "fs-write")]
+            Command::write { path: raw, text, must_new } => {
+                let text = text.bytes()?;
+                let path = ctx.resolve_path(raw)?;
                 OpenOptions::new()
                     .write(true)
                     .create(true)
                     .truncate(true)
                     .create_new(must_new.is_true())
-                    .open(path)?
-                    .write_all(text.as_bytes())?;
+                    .open(path)
+                    .and_then(|mut file| file.write_all(&text))
+                    .map_err(path_io(raw))?;
                 Null
             },
-            Command::append { path, text, must_exist } => {
+            #[cfg(feature = "fs-write")]
+            Command::append { path: raw, text, must_exist } => {
+                let text = text.bytes()?;
+                let path = ctx.resolve_path(raw)?;
                 OpenOptions::new()
                     .append(must_exist.is_false())
                     .create(true)
-                    .open(path)?
-                    .write_all(text.as_bytes())?;
+                    .open(path)
+                    .and_then(|mut file| file.write_all(&text))
+                    .map_err(path_io(raw))?;
                 Null
             },
-            Command::read_dir(path) => {
-                let paths = fs::read_dir(path)?
-                    .map_and(|dir| path_it(dir.path()))
+            Command::read_dir(raw) => {
+                let path = ctx.resolve_path(raw)?;
+                let paths = fs::read_dir(path).map_err(path_io(raw))?
+                    .map_and(|dir| ctx.path_it(&dir.path(), raw))
                     .collect::<Result<Vec<Value>, _>>()?;
                 paths.into()
             },
-            Command::read_link(link) => {
-                path_it(fs::read_link(link)?)?
+            Command::read_link(raw) => {
+                let target = fs::read_link(ctx.resolve_link(raw)?).map_err(path_io(raw))?;
+                match &ctx.root {
+                    // an absolute target is only shown inside the root
+                    Some(_) if target.has_root() => ctx.path_it(&target, raw)?,
+                    _ => ctx.os_it(target)?,
+                }
             },
-            Command::metadata(path) => {
-                let metadata = fs::metadata(path)?;
-                json!({
+            Command::metadata(raw) => {
+                let metadata = fs::metadata(ctx.resolve_path(raw)?).map_err(path_io(raw))?;
+                let mut result = json!({
                     "readonly": metadata.permissions().readonly(),
                     "is_file": metadata.is_file(),
                     "id_dir": metadata.is_dir(),
                     "len": metadata.len(),
-                })
+                });
+                attributes_it(&metadata, &mut result);
+                result
             },
-            Command::metadata_extra(path) => {
-                let metadata = fs::metadata(path)?;
-                json!({
+            Command::metadata_extra(extra) => {
+                let (raw, local) = match extra {
+                    MetadataExtra::Path(path) => (path, None),
+                    MetadataExtra::With { path, local } => (path, *local),
+                };
+                let metadata = fs::metadata(ctx.resolve_path(raw)?).map_err(path_io(raw))?;
+                let mut result = json!({
                     "readonly": metadata.permissions().readonly(),
                     "is_file": metadata.is_file(),
                     "id_dir": metadata.is_dir(),
                     "len": metadata.len(),
-                    "accessed": time_it(metadata.accessed()?),
-                    "modified": time_it(metadata.modified()?),
-                    "created": time_it(metadata.created()?),
-                })
+                });
+                attributes_it(&metadata, &mut result);
+                let times = [
+                    ("accessed", metadata.accessed().map_err(path_io(raw))?),
+                    ("modified", metadata.modified().map_err(path_io(raw))?),
+                    ("created", metadata.created().map_err(path_io(raw))?),
+                ];
+                if local.is_true() {
+                    let (offset, known) = times::local_offset_checked();
+                    for (name, time) in times {
+                        let time = OffsetDateTime::from(time).to_offset(offset);
+                        result[name] = time.format(&Rfc3339)?.into();
+                    }
+                    result["offset"] = times::offset_string(offset).into();
+                    result["utc_fallback"] = (!known).into();
+                } else {
+                    for (name, time) in times {
+                        result[name] = time_it(time)?.into();
+                    }
+                }
+                result
             },
-            Command::exists(path) => {
-                fs::exists(path)?.into()
+            Command::exists(raw) => {
+                fs::exists(ctx.resolve_path(raw)?).map_err(path_io(raw))?.into()
             },
-            Command::is_symlink(path) => {
-                fs::symlink_metadata(path)?.is_symlink().into()
+            Command::is_symlink(raw) => {
+                fs::symlink_metadata(ctx.resolve_link(raw)?).map_err(path_io(raw))?.is_symlink().into()
             },
-            Command::is_dir(path) => {
-                fs::metadata(path)?.is_dir().into()
+            Command::is_dir(raw) => {
+                fs::metadata(ctx.resolve_path(raw)?).map_err(path_io(raw))?.is_dir().into()
             },
-            Command::is_file(path) => {
-                fs::metadata(path)?.is_file().into()
+            Command::is_file(raw) => {
+                fs::metadata(ctx.resolve_path(raw)?).map_err(path_io(raw))?.is_file().into()
             },
-            Command::print(value) => {
-                if let Some(s) = value.as_str() {
-                    stdout().write_all(s.as_bytes())?;
-                } else {
-                    serde_json::to_writer(stdout().lock(), value)?;
-                }
-                Null
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_query { path, sql, params, readonly } => {
+                let connection = ctx.database(path, readonly.unwrap_or(true))?;
+                sqlite::query(connection, sql, params.as_deref().unwrap_or_default())?
             },
-            Command::println(value) => {
-                let mut writer = stdout().lock();
-                if let Some(s) = value.as_str() {
-                    stdout().write_all(s.as_bytes())?;
-                } else {
-                    serde_json::to_writer(&mut writer, value)?;
-                }
-                writer.write_all(b"\n")?;
-                Null
+            #[cfg(feature = "sqlite")]
+            Command::sqlite_close(raw) => {
+                let path = ctx.database_path(raw)?;
+                ctx.databases.remove(&path).is_some().into()
             },
-            Command::pretty(value) => {
-                let mut writer = stdout().lock();
-                serde_json::to_writer_pretty(&mut writer, value)?;
-                writer.write_all(b"\n")?;
-                Null
+            Command::print(value) => print_it(&ctx.output, value, Style::Raw, false)?,
+            Command::println(value) => print_it(&ctx.output, value, Style::Raw, true)?,
+            Command::pretty(value) => print_it(&ctx.output, value, Style::Pretty, true)?,
+            Command::pretty_pipe(value) => print_it(&ctx.output, value, Style::Json, true)?,
+            Command::eprint(value) => print_it(&ctx.error_output, value, Style::Raw, false)?,
+            Command::eprintln(value) => print_it(&ctx.error_output, value, Style::Raw, true)?,
+            Command::eprint_pretty(value) => {
+                print_it(&ctx.error_output, value, Style::Pretty, true)?
             },
-            Command::pretty_pipe(value) => {
-                let mut writer = stdout().lock();
-                serde_json::to_writer(&mut writer, value)?;
-                writer.write_all(b"\n")?;
-                Null
+            Command::set_buffering { enabled, capacity } => {
+                if *capacity == Some(0) {
+                    return Err(Error::InvalidArgument("zero buffer capacity".into()));
+                }
+                ctx.output.set_buffering(*enabled, *capacity)?;
+                Value::Null
+            },
+            Command::flush => {
+                ctx.flush_outputs()?;
+                Value::Null
             },
             Command::stdin => {
+                // a buffered prompt must be visible before blocking
+                ctx.flush_outputs()?;
                 io::read_to_string(stdin().lock())?.into()
             },
             Command::stdin_line => {
+                ctx.flush_outputs()?;
                 let mut buf = String::new();
                 stdin().read_line(&mut buf)?;
                 buf.into()
             },
+            Command::prompt { text, default, trim } => {
+                ctx.flush_outputs()?;
+                let line = term::prompt(&ctx.error_output, &mut stdin().lock(), text, trim.unwrap_or(true))?;
+                line.or_else(|| default.clone()).into()
+            },
+            Command::read_password { prompt } => {
+                ctx.flush_outputs()?;
+                if let Some(prompt) = prompt {
+                    term::write_prompt(&ctx.error_output, prompt)?;
+                }
+                // piped in, e.g. by CI, nothing to hide
+                let is_tty = stdin().is_terminal();
+                let password = term::read_password(&mut stdin().lock(), is_tty)?;
+                if is_tty {
+                    // the enter was not echoed either
+                    term::write_prompt(&ctx.error_output, "\n")?;
+                }
+                match password {
+                    Some(term::Password::Hidden(password)) => password.into(),
+                    Some(term::Password::Echoed(password)) => {
+                        json!({"password": password, "echoed": true})
+                    },
+                    None => Value::Null,
+                }
+            },
+            Command::readline { prompt, history_id } => {
+                ctx.flush_outputs()?;
+                let output = ctx.error_output();
+                let read = term::readline(&output, prompt, ctx.history(history_id))?;
+                readline_it(ctx, history_id, read)
+            },
+            Command::confirm { text, default } => {
+                if ctx.assume_yes {
+                    return Ok(true.into());
+                }
+                ctx.flush_outputs()?;
+                term::confirm(&ctx.error_output, &mut stdin().lock(), text, *default)?.into()
+            },
+            Command::history_add { line, history_id } => {
+                add_history(ctx.history(history_id), line.clone());
+                Value::Null
+            },
+            Command::history_load { path, history_id } => {
+                let entries = term::load_history(&ctx.resolve_path(path)?).map_err(path_io(path))?;
+                *ctx.history(history_id) = entries;
+                Value::Null
+            },
+            #[cfg(feature = "fs-write")]
+            Command::history_save { path, history_id } => {
+                let entries = ctx.history(history_id).clone();
+                term::save_history(&ctx.resolve_path(path)?, &entries).map_err(path_io(path))?;
+                Value::Null
+            },
+            Command::is_tty { stream } => {
+                match &**stream {
+                    "stdin" => stdin().is_terminal(),
+                    "stdout" => stdout().is_terminal(),
+                    "stderr" => io::stderr().is_terminal(),
+                    _ => return Err(Error::InvalidArgument(format!("unknown stream {stream:?}"))),
+                }.into()
+            },
+            Command::terminal_size => {
+                sys::terminal_size()
+                    .map(|(cols, rows)| json!({"cols": cols, "rows": rows}))
+                    .unwrap_or(Null)
+            },
+            Command::progress_start { total, label } => {
+                if let Some((id, _)) = &ctx.progress {
+                    return Err(Error::InvalidArgument(format!(
+                        "progress {id} is not finished, bars do not stack",
+                    )));
+                }
+                let now = Instant::now();
+                let tty = io::stderr().is_terminal();
+                let mut bar = progress::Progress::new(*total, label.clone(), tty, now);
+                if let Some(frame) = bar.frame(now, true) {
+                    draw(&ctx.error_output, &frame)?;
+                }
+                let id = ctx.next_progress;
+                ctx.next_progress += 1;
+                ctx.progress = Some((id, bar));
+                id.into()
+            },
+            Command::progress_update { id, current, delta, label } => {
+                let bar = match &mut ctx.progress {
+                    Some((running, bar)) if running == id => bar,
+                    _ => return Err(Error::InvalidProgressId(*id)),
+                };
+                bar.update(*current, *delta, label.clone());
+                if let Some(frame) = bar.frame(Instant::now(), false) {
+                    draw(&ctx.error_output, &frame)?;
+                }
+                Null
+            },
+            Command::progress_finish { id, clear } => {
+                let mut bar = match ctx.progress.take() {
+                    Some((running, bar)) if running == *id => bar,
+                    other => {
+                        ctx.progress = other;
+                        return Err(Error::InvalidProgressId(*id));
+                    },
+                };
+                let now = Instant::now();
+                draw(&ctx.error_output, &bar.finish(now, clear.is_true()))?;
+                json!({
+                    "current": bar.current(),
+                    "total": bar.total(),
+                    "elapsed_ms": millis_it(bar.elapsed(now)),
+                })
+            },
+            #[cfg(feature = "notify")]
+            Command::notify { summary, body, urgency, timeout_ms } => {
+                notify::send(summary, body.as_deref(), urgency.as_deref(), *timeout_ms)?;
+                Null
+            },
+            #[cfg(feature = "clipboard")]
+            Command::clipboard_get => clipboard::get()?.into(),
+            #[cfg(feature = "clipboard")]
+            Command::clipboard_set(set) => {
+                let (text, wait) = match set {
+                    ClipboardSet::Text(text) => (text, None),
+                    ClipboardSet::With { text, wait } => (text, *wait),
+                };
+                clipboard::set(text.clone(), wait.unwrap_or(false))?;
+                Null
+            },
+            Command::style { text, fg, bg, bold, underline, print, force } => {
+                let style = ansi::Style {
+                    fg: fg.as_deref(),
+                    bg: bg.as_deref(),
+                    bold: bold.is_true(),
+                    underline: underline.is_true(),
+                };
+                let enabled = force.unwrap_or_else(|| {
+                    ansi::enabled(stdout().is_terminal(), |name| env::var(name).ok())
+                });
+                let styled = style.apply(text, enabled)?;
+                if print.is_true() {
+                    print_it(&ctx.output, &styled.into(), Style::Raw, false)?
+                } else {
+                    styled.into()
+                }
+            },
+            Command::current_exe => {
+                ctx.os_it(current_exe()?)?
+            },
+            Command::exe_dir => {
+                let exe = current_exe()?;
+                ctx.os_it(exe.parent().unwrap_or(&exe))?
+            },
             Command::current_dir => {
-                path_it(env::current_dir()?)?
+                ctx.path_it(&env::current_dir()?, ".")?
+            },
+            Command::set_current_dir(raw) => {
+                let old = ctx.path_it(&env::current_dir()?, ".")?;
+                env::set_current_dir(ctx.resolve_path(raw)?).map_err(path_io(raw))?;
+                old
             },
             Command::temp_dir => {
-                path_it(env::temp_dir())?
+                ctx.os_it(env::temp_dir())?
+            },
+            Command::app_dirs { app } => {
+                let dirs = dirs::current(|name| env::var(name).ok());
+                let dirs = match app {
+                    Some(app) => dirs.join(app),
+                    None => dirs,
+                };
+                serde_json::to_value(dirs)?
             },
             Command::get_env(name) => {
                 env::var_os(name)
-                    .map(oss_it)
+                    .map(|value| ctx.os_it(value))
                     .transpose()?
                     .unwrap_or(Value::Null)
             },
+            Command::env_vars { prefix, lossy } => {
+                let mut vars = Map::new();
+                let mut skipped = 0;
+                for (name, value) in env::vars_os() {
+                    if prefix.as_ref().is_some_and(|prefix| {
+                        !name.to_string_lossy().starts_with(prefix)
+                    }) {
+                        continue;
+                    }
+                    let (name, value) = match (name.to_str(), value.to_str()) {
+                        (Some(name), Some(value)) => (name.into(), value.into()),
+                        _ if lossy.is_true() => (
+                            name.to_string_lossy().into_owned(),
+                            value.to_string_lossy().into_owned(),
+                        ),
+                        _ => { skipped += 1; continue },
+                    };
+                    vars.insert(name, Value::String(value));
+                }
+                json!({"vars": vars, "skipped": skipped})
+            },
+            Command::load_dotenv { path, apply, override_existing } => {
+                let raw = path.as_deref().unwrap_or(".env");
+                let text = fs::read_to_string(ctx.resolve_path(raw)?).map_err(path_io(raw))?;
+                let (parsed, warnings) = dotenv::parse(&text);
+                // a repeated key takes its last value
+                let vars: BTreeMap<String, String> = parsed.into_iter().collect();
+                if apply.is_true() {
+                    apply_env(&vars, override_existing.is_true())?;
+                }
+                json!({"vars": vars, "warnings": warnings})
+            },
+            #[cfg(feature = "env-write")]
             Command::set_env(name, value) => {
+                let value = os_string(value.bytes()?.into_owned())?;
+                env_checked(name, Some(&value))?;
                 unsafe { env::set_var(name, value) }
                 Null
             },
+            #[cfg(feature = "env-write")]
             Command::remove_env(name) => {
+                env_checked(name, None)?;
                 unsafe { env::remove_var(name) }
                 Null
             },
+            #[cfg(feature = "proc")]
             Command::system(prog, args) => {
-                process::Command::new(prog)
+                status_it(process::Command::new(prog)
                     .args(args)
-                    .status()?
-                    .code()
-                    .unwrap_or(NONE_EXIT_CODE)
-                    .into()
+                    .status()?).into()
             },
+            #[cfg(feature = "proc")]
             Command::popen(prog, args) => {
                 let output = process::Command::new(prog)
                     .args(args)
                     .stderr(Stdio::inherit())
                     .output()?;
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                json!({
-                    "stdout": stdout,
-                    "status": output.status.code().unwrap_or(NONE_EXIT_CODE),
-                })
+                let mut result = status_it(output.status);
+                result.insert("stdout".into(), ctx.bytes_it(output.stdout));
+                result.into()
             },
+            #[cfg(feature = "proc")]
             Command::command(prog, command_builder) => {
+                let timing = command_builder.timing();
                 let command = process::Command::new(prog);
                 let child = command_builder.apply(command, |mut cmd| {
                     Ok(cmd.spawn()?)
                 })?;
-                let output = child.wait_with_output()?;
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let stderr = String::from_utf8_lossy(&output.stderr);
-
-                json!({
-                    "stdout": stdout,
-                    "stderr": stderr,
-                    "status": output.status.code().unwrap_or(NONE_EXIT_CODE),
-                })
+                let mut result = output_it(ctx, child.wait_with_output()?);
+                if let Some(timing) = timing {
+                    result["timing"] = timing.finish();
+                }
+                result
             },
-            Command::wait_id { id, output } => {
-                if output.is_true() {
+            #[cfg(feature = "proc")]
+            Command::stream_command { prog, builder } => {
+                let command = process::Command::new(prog);
+                let mut child = builder.apply(command, |mut cmd| {
+                    Ok(cmd.spawn()?)
+                })?;
+                let (send, recv) = mpsc::channel();
+                if let Some(stdout) = child.stdout.take() {
+                    stream_lines("stdout", stdout, send.clone());
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    stream_lines("stderr", stderr, send.clone());
+                }
+                drop(send);
+                for (stream, line) in recv {
+                    part(json!({"stream": stream, "line": line}))?;
+                }
+                status_it(child.wait()?).into()
+            },
+            #[cfg(feature = "proc")]
+            Command::spawn(prog, command_builder) => {
+                let command = process::Command::new(prog);
+                let child = command_builder.apply(command, |mut cmd| {
+                    Ok(cmd.spawn()?)
+                })?;
+                let id = child.id();
+                ctx.sub_processors.insert(id, child);
+                id.into()
+            },
+            #[cfg(feature = "proc")]
+            Command::spawn_detached(prog, command_builder) => {
+                let command = process::Command::new(prog);
+                let mut child = command_builder.apply(command, |mut cmd| {
+                    let CommandBuilder { stdin, stdout, stderr, .. } = command_builder;
+                    if stdin.is_none() { cmd.stdin(Stdio::null()); }
+                    if stdout.is_none() { cmd.stdout(Stdio::null()); }
+                    if stderr.is_none() { cmd.stderr(Stdio::null()); }
+                    #[cfg(unix)]
+                    unsafe {
+                        use std::os::unix::process::CommandExt;
+
+                        cmd.pre_exec(|| {
+                            if libc::setsid() == -1 {
+                                return Err(io::Error::last_os_error());
+                            }
+                            Ok(())
+                        });
+                    }
+                    #[cfg(windows)]
+                    {
+                        use std::os::windows::process::CommandExt;
+                        use windows_sys::Win32::System::Threading::{
+                            CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS,
+                        };
+
+                        // on top of those of the builder
+                        let flags = command_builder.creation_flags.unwrap_or_default();
+                        cmd.creation_flags(flags | DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+                    }
+                    Ok(cmd.spawn()?)
+                })?;
+                let id = child.id();
+                // reap it when exited before the bridge
+                spawn(move || child.wait());
+                id.into()
+            },
+            #[cfg(feature = "open")]
+            Command::open_path { path, with } => {
+                launch::open_path(&ctx.resolve_path(path)?, path, with.as_deref())?;
+                Null
+            },
+            #[cfg(feature = "open")]
+            Command::open_url { url, with } => {
+                launch::open_url(url, with.as_deref())?;
+                Null
+            },
+            #[cfg(all(feature = "proc", unix))]
+            Command::command_pty { prog, builder, cols, rows, strip_ansi } => {
+                builder.check_pty()?;
+                let timing = builder.timing();
+                let pty = pty::Pty::open(cols.unwrap_or(80), rows.unwrap_or(24))?;
+                let mut master = None;
+                let command = process::Command::new(prog);
+                let mut child = builder.apply(command, |cmd| {
+                    let (file, child) = pty.spawn(cmd)?;
+                    master = Some(file);
+                    Ok(child)
+                })?;
+                let output = pty::read_master(master.unwrap())?;
+                let mut output = String::from_utf8_lossy(&output).into_owned();
+                if strip_ansi.is_true() {
+                    output = pty::strip_ansi(&output);
+                }
+
+                let mut result = status_it(child.wait()?);
+                result.insert("output".into(), output.into());
+                if let Some(timing) = timing {
+                    result.insert("timing".into(), timing.finish());
+                }
+                result.into()
+            },
+            #[cfg(all(feature = "proc", not(unix)))]
+            Command::command_pty { .. } => {
+                return Err(Error::Unsupported("command_pty"));
+            },
+            #[cfg(feature = "proc")]
+            Command::wait_id { id, output } => {
+                if output.is_true() {
                     let output = ctx.child(*id)?.wait_with_output()?;
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-
-                    json!({
-                        "stdout": stdout,
-                        "stderr": stderr,
-                        "status": output.status.code().unwrap_or(NONE_EXIT_CODE),
-                    })
+                    output_it(ctx, output)
                 } else {
-                    ctx.child(*id)?.wait()?.code().unwrap_or(NONE_EXIT_CODE).into()
+                    status_it(ctx.child(*id)?.wait()?).into()
                 }
             },
+            #[cfg(feature = "proc")]
+            Command::wait_any { ids, timeout_ms } => {
+                let ids = ctx.wait_any_ids(ids)?;
+                wait_any(*timeout_ms, || ctx.try_wait_any(&ids))?
+            },
+            #[cfg(feature = "proc")]
             Command::kill_id { id } => {
                 ctx.child(*id)?.kill()?;
                 Null
             },
+            #[cfg(feature = "proc")]
+            Command::kill_tree_id { id, signal } => {
+                if ctx.has_exited(*id)? {
+                    return Ok(json!({"signaled": [], "errors": []}));
+                }
+                let root = ctx.child_ref(*id)?.id();
+                let procs::KillTree { signaled, errors }
+                    = procs::kill_tree(root, signal.as_deref())?;
+                let errors = errors.into_iter()
+                    .map(|(pid, e)| json!({"pid": pid, "error": e.to_string()}))
+                    .collect::<Vec<_>>();
+                json!({
+                    "signaled": signaled,
+                    "errors": errors,
+                })
+            },
+            Command::user_info => serde_json::to_value(users::current_user()?)?,
+            Command::lookup_user(key) => serde_json::to_value(users::lookup_user(key)?)?,
+            Command::lookup_group(key) => serde_json::to_value(users::lookup_group(key)?)?,
+            Command::groups => serde_json::to_value(users::groups()?)?,
+            Command::locale_info => serde_json::to_value(locale::current())?,
+            Command::hello => json!({
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol": PROTOCOL_VERSION,
+                "commands": ctx.policy.allowed_commands(),
+                "platform": {"os": env::consts::OS, "arch": env::consts::ARCH},
+            }),
+            Command::echo(value) => value.clone(),
+            Command::ping => {
+                let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+                json!({"pong": true, "time": time})
+            },
+            Command::platform => {
+                let (distro, os_version) = sys::os_version();
+                json!({
+                    "os": env::consts::OS,
+                    "arch": env::consts::ARCH,
+                    "family": env::consts::FAMILY,
+                    "exe_suffix": env::consts::EXE_SUFFIX,
+                    "dll_suffix": env::consts::DLL_SUFFIX,
+                    "kernel_version": sys::kernel_version(),
+                    "distro": distro,
+                    "os_version": os_version,
+                })
+            },
+            Command::cpu_count => {
+                json!({
+                    "logical": std::thread::available_parallelism()?.get(),
+                    "physical": sys::physical_cpus(),
+                })
+            },
+            Command::memory_info => serde_json::to_value(sys::memory_info()?)?,
+            Command::load_average => {
+                let [one, five, fifteen] = sys::load_average()
+                    .map_or([None; 3], |loads| loads.map(Some));
+                json!({"one": one, "five": five, "fifteen": fifteen})
+            },
+            Command::uptime => sys::uptime()?.into(),
+            Command::hostname => oss_it(sys::hostname()?)?,
+            #[cfg(feature = "net")]
+            Command::fqdn => sys::fqdn()?.into(),
+            Command::now => {
+                let now = SystemTime::now();
+                let since_epoch = now.duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                let rfc3339 = UtcDateTime::from(now).format(&Rfc3339)?;
+                json!({
+                    "epoch": since_epoch.as_secs_f64(),
+                    "epoch_ms": since_epoch.as_millis() as u64,
+                    "epoch_ns": since_epoch.as_nanos().to_string(),
+                    "rfc3339": rfc3339,
+                    "offset": "+00:00",
+                })
+            },
+            Command::now_monotonic => {
+                (ctx.created.elapsed().as_nanos() as u64).into()
+            },
+            Command::timer_start(name) => {
+                ctx.timers.insert(name.clone(), Instant::now());
+                Null
+            },
+            Command::timer_elapsed(name) => {
+                let start = ctx.timers.get(name)
+                    .ok_or_else(|| Error::TimerNotFound(name.clone()))?;
+                millis_it(start.elapsed()).into()
+            },
+            Command::timer_stop(name) => {
+                let start = ctx.timers.remove(name)
+                    .ok_or_else(|| Error::TimerNotFound(name.clone()))?;
+                millis_it(start.elapsed()).into()
+            },
+            Command::timer_list => {
+                ctx.timers.iter()
+                    .map(|(name, start)| (name.clone(), millis_it(start.elapsed()).into()))
+                    .collect::<Map<_, _>>()
+                    .into()
+            },
+            Command::sleep(ms) => {
+                ctx.sleep(Duration::from_millis(*ms))?;
+                Null
+            },
+            Command::sleep_until { epoch_ms } => {
+                let until = SystemTime::UNIX_EPOCH + Duration::from_millis(*epoch_ms);
+                if let Ok(duration) = until.duration_since(SystemTime::now()) {
+                    ctx.sleep(duration)?;
+                }
+                Null
+            },
+            Command::local_now => {
+                let (offset, known) = times::local_offset_checked();
+                let now = OffsetDateTime::now_utc().to_offset(offset);
+                json!({
+                    "epoch": times::epoch_of(now),
+                    "rfc3339": now.format(&Rfc3339)?,
+                    "offset": times::offset_string(offset),
+                    "utc_fallback": !known,
+                })
+            },
+            Command::timezone => {
+                let (offset, known) = times::local_offset_checked();
+                json!({
+                    "offset": times::offset_string(offset),
+                    "name": times::timezone_name(),
+                    "utc_fallback": !known,
+                })
+            },
+            Command::format_time { epoch, format, utc } => {
+                let mut datetime = epoch.to_datetime()?;
+                if *utc == Some(false) {
+                    datetime = datetime.to_offset(times::local_offset());
+                }
+                times::format(datetime, format)?.into()
+            },
+            Command::parse_time { text, format } => {
+                times::epoch_of(times::parse(text, format)?).into()
+            },
             Command::process_id => process::id().into(),
+            #[cfg(unix)]
+            Command::parent_process_id => std::os::unix::process::parent_id().into(),
+            #[cfg(windows)]
+            Command::parent_process_id => procs::parent_pid(process::id())?.into(),
+            Command::process_info(pid) => {
+                let pid = pid.unwrap_or_else(process::id);
+                serde_json::to_value(procs::ProcessInfo::of(pid)?)?
+            },
+            Command::list_processes { name_filter } => {
+                let mut processes = procs::list_processes()?;
+                if let Some(filter) = name_filter {
+                    processes.retain(|info| {
+                        info.name.as_ref().is_some_and(|name| name.contains(filter))
+                    });
+                }
+                serde_json::to_value(processes)?
+            },
+            #[cfg(unix)]
+            Command::get_umask => {
+                let old = unsafe { libc::umask(0) };
+                unsafe { libc::umask(old) };
+                old.into()
+            },
+            #[cfg(unix)]
+            Command::set_umask(mask) => {
+                unsafe { libc::umask(*mask as libc::mode_t) }.into()
+            },
+            #[cfg(not(unix))]
+            Command::get_umask | Command::set_umask(_) => {
+                return Err(Error::Unsupported("umask"));
+            },
+            Command::pid_alive(pid) => procs::pid_alive(*pid)?.into(),
+            Command::pid_start_time(pid) => procs::pid_start_time(*pid)?.into(),
             Command::random => {
-                ctx.thread_rng.random::<u64>().into()
+                ctx.rng.random::<u64>().into()
             },
             Command::random_float => {
-                ctx.thread_rng.random::<f64>().into()
+                ctx.rng.random::<f64>().into()
+            },
+            Command::random_normal { mean, std_dev, count } => {
+                if std_dev.is_nan() || *std_dev < 0.0 {
+                    return Err(Error::InvalidArgument("std_dev must not be negative".into()));
+                }
+                random::sample(&mut ctx.rng, Normal::new(*mean, *std_dev), *count)?
+            },
+            Command::random_exp { lambda, count } => {
+                if lambda.is_nan() || *lambda <= 0.0 {
+                    return Err(Error::InvalidArgument("lambda must be positive".into()));
+                }
+                random::sample(&mut ctx.rng, Exp::new(*lambda), *count)?
+            },
+            Command::set_seed(seed) => {
+                ctx.set_seed(Some(*seed));
+                Null
+            },
+            Command::clear_seed => {
+                ctx.set_seed(None);
+                Null
+            },
+            Command::random_range { min, max } => {
+                if min > max {
+                    return Err(Error::InvalidArgument(format!("empty range {min}..={max}")));
+                }
+                ctx.rng.random_range(*min..=*max).into()
+            },
+            Command::random_float_range { min, max } => {
+                if !(min < max && (max - min).is_finite()) {
+                    return Err(Error::InvalidArgument(format!("invalid range {min:?}..{max:?}")));
+                }
+                ctx.rng.random_range(*min..*max).into()
+            },
+            Command::random_below(n) => {
+                if *n == 0 {
+                    return Err(Error::InvalidArgument("empty range 0..0".into()));
+                }
+                ctx.rng.random_range(0..*n).into()
+            },
+            Command::random_bytes { len, encoding, secure } => {
+                random::check_len(*len)?;
+                let mut buf = vec![0; *len];
+                if secure.is_true() {
+                    OsRng.try_fill_bytes(&mut buf).map_err(io::Error::other)?;
+                } else {
+                    ctx.rng.fill_bytes(&mut buf);
+                }
+                encoding::encode(&buf, encoding.as_deref().unwrap_or("base64"))?.into()
+            },
+            Command::random_string { len, charset } => {
+                random::check_len(*len)?;
+                let charset = random::charset(charset.as_deref().unwrap_or("alnum"))?;
+                iter::repeat_with(|| charset[ctx.rng.random_range(0..charset.len())])
+                    .take(*len)
+                    .collect::<String>()
+                    .into()
+            },
+            Command::base64_encode { text, data_hex, url_safe, no_pad } => {
+                let bytes = encoding::input(text, data_hex, "data_hex", encoding::hex_decode)?;
+                encoding::base64_encode(&bytes, url_safe.is_true(), no_pad.is_true()).into()
+            },
+            Command::base64_decode { data, as_text } => {
+                encoding::output(encoding::base64_decode(data)?, as_text.is_true(), encoding::hex)?.into()
+            },
+            Command::base32_encode { text, data_base64, extended_hex, no_pad } => {
+                let bytes = encoding::input(text, data_base64, "data_base64", encoding::base64_decode)?;
+                encoding::base32_encode(&bytes, extended_hex.is_true(), no_pad.is_true()).into()
+            },
+            Command::base32_decode { data, as_text, extended_hex } => {
+                let bytes = encoding::base32_decode(data, extended_hex.is_true())?;
+                encoding::output(bytes, as_text.is_true(), encoding::base64)?.into()
+            },
+            Command::hex_encode { text, data_base64, upper } => {
+                let bytes = encoding::input(text, data_base64, "data_base64", encoding::base64_decode)?;
+                if upper.is_true() { encoding::hex_upper(&bytes) } else { encoding::hex(&bytes) }.into()
+            },
+            Command::hex_decode { data, as_text, lenient } => {
+                let bytes = encoding::hex_decode_with(data, lenient.is_true())?;
+                encoding::output(bytes, as_text.is_true(), encoding::base64)?.into()
+            },
+            Command::url_encode { text, component } => {
+                urls::encode(text, component.is_true()).into()
+            },
+            Command::url_decode(text) => urls::decode(text)?.into(),
+            Command::url_parse(text) => serde_json::to_value(urls::parse(text)?)?,
+            Command::url_build(parts) => urls::build(parts)?.into(),
+            Command::compress { text, data_base64, format, level } => {
+                let data = encoding::input(text, data_base64, "data_base64", encoding::base64_decode)?;
+                encoding::base64(&compress::compress(&data, format, *level)?).into()
+            },
+            Command::decompress { data_base64, format, as_text, max_size } => {
+                let data = encoding::base64_decode(data_base64)?;
+                let max_size = max_size.unwrap_or(compress::DEFAULT_MAX_SIZE);
+                let data = compress::decompress(&data, format, max_size)?;
+                encoding::output(data, as_text.is_true(), encoding::base64)?.into()
+            },
+            #[cfg(feature = "net")]
+            Command::http_get { url, headers, timeout_ms } => {
+                http::HttpRequest::get(url.clone(), headers.clone(), *timeout_ms).send()?
+            },
+            #[cfg(feature = "net")]
+            Command::http_request(request) => request.send()?,
+            #[cfg(all(feature = "net", feature = "fs-write"))]
+            Command::download(download) => {
+                let path = ctx.resolve_path(download.path())?;
+                download.run(&path, &|| ctx.is_cancelled())?
+            },
+            #[cfg(feature = "net")]
+            Command::resolve { host, port } => net::resolve(host, port.unwrap_or(0))?,
+            #[cfg(feature = "net")]
+            Command::reverse_resolve(ip) => {
+                let ip = ip.parse()
+                    .map_err(|_| Error::InvalidArgument(format!("invalid ip address {ip:?}")))?;
+                net::reverse_resolve(ip)?.into()
+            },
+            #[cfg(feature = "net")]
+            Command::tcp_request(request) => request.run()?,
+            #[cfg(feature = "net")]
+            Command::unix_request(request) => request.run()?,
+            #[cfg(feature = "net")]
+            Command::udp_send(send) => send.run()?,
+            #[cfg(feature = "net")]
+            Command::http_unix(request) => request.send()?,
+            #[cfg(feature = "net")]
+            Command::interfaces => serde_json::to_value(ifaces::interfaces()?)?,
+            #[cfg(feature = "net")]
+            Command::primary_ip { family } => {
+                ifaces::primary_ip(family.as_deref())?.to_string().into()
+            },
+            #[cfg(feature = "net")]
+            Command::tcp_connect_check { host, port, timeout_ms } => {
+                let timeout = timeout_ms.map(Duration::from_millis);
+                match net::tcp_connect(host, *port, timeout) {
+                    Ok(_) => true.into(),
+                    Err(Error::Connect { .. }) => false.into(),
+                    Err(e) => return Err(e),
+                }
+            },
+            Command::hash { text, algo } => hash::hash(algo, text.as_bytes())?.into(),
+            Command::hash_bytes { data, algo } => {
+                hash::hash(algo, &encoding::base64_decode(data)?)?.into()
+            },
+            Command::crc32 { text, data_base64, path, variant } => {
+                let mut checksum = hash::Checksum::new(variant.as_deref().unwrap_or("ieee"))?;
+                match (text, data_base64, path) {
+                    (Some(text), None, None) => checksum.update(text.as_bytes()),
+                    (None, Some(data), None) => checksum.update(&encoding::base64_decode(data)?),
+                    (None, None, Some(path)) => checksum.update_file(ctx.resolve_path(path)?)?,
+                    _ => return Err(Error::InvalidArgument(
+                        "expected exactly one of text, data_base64 and path".into(),
+                    )),
+                }
+                let value = checksum.finish();
+                json!({"value": value, "hex": format!("{value:08x}")})
+            },
+            Command::hmac(input) => input.hmac()?.into(),
+            Command::hmac_verify { input, expected } => input.verify(expected)?.into(),
+            Command::uuid(count) => {
+                random::repeat(*count, || random::uuid_v4(&mut ctx.rng))?
+            },
+            Command::uuid_v7(count) => {
+                random::repeat(*count, || {
+                    let millis = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+                    ctx.uuid_v7.next(&mut ctx.rng, millis)
+                })?
+            },
+            Command::uuid_parse(text) => random::uuid_parse(text)?,
+            Command::shuffle(array) => {
+                let mut array = random::array(array)?.clone();
+                array.shuffle(&mut ctx.rng);
+                array.into()
+            },
+            Command::choice(array) => {
+                random::array(array)?
+                    .choose(&mut ctx.rng)
+                    .cloned()
+                    .unwrap_or(Null)
+            },
+            Command::sample { array, count, replace } => {
+                let array = random::array(array)?;
+                if replace.is_true() {
+                    if array.is_empty() && *count != 0 {
+                        return Err(Error::InvalidArgument("sample from an empty array".into()));
+                    }
+                    random::check_len(*count)?;
+                    iter::repeat_with(|| array[ctx.rng.random_range(0..array.len())].clone())
+                        .take(*count)
+                        .collect::<Vec<_>>()
+                        .into()
+                } else {
+                    if *count > array.len() {
+                        return Err(Error::InvalidArgument(format!(
+                            "sample {count} from {} elements without replacement",
+                            array.len(),
+                        )));
+                    }
+                    array.choose_multiple(&mut ctx.rng, *count)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .into()
+                }
+            },
+            Command::retry { cmd, attempts, delay_ms, backoff, retry_on } => {
+                if matches!(**cmd, Command::exit(_)) || cmd.reads_stdin() {
+                    return Err(Error::NotAllowedInside("retry"));
+                }
+                let mut delay = Duration::from_millis(*delay_ms);
+                let mut attempts_used = 0;
+                let result = loop {
+                    attempts_used += 1;
+                    let result = cmd.run_with(ctx, part);
+                    let failed = match &result {
+                        Ok(value) => match process_code(cmd, value) {
+                            Some(code) => match retry_on {
+                                Some(retry_on) => code.is_some_and(|code| {
+                                    retry_on.iter().any(|&c| i64::from(c) == code)
+                                }),
+                                None => code != Some(0),
+                            },
+                            None => false,
+                        },
+                        Err(_) => true,
+                    };
+                    if !failed || attempts_used >= *attempts {
+                        break result;
+                    }
+                    ctx.sleep(delay)?;
+                    delay = delay.mul_f64(backoff.unwrap_or(1.0));
+                };
+                match result {
+                    Ok(value) => json!({"ok": value, "attempts_used": attempts_used}),
+                    Err(e) => json!({"err": ctx.error_it(Some(cmd), &e), "attempts_used": attempts_used}),
+                }
+            },
+            Command::batch(batch) => {
+                let (cmds, stop_on_error) = match batch {
+                    Batch::Cmds(cmds) => (cmds, None),
+                    Batch::With { cmds, stop_on_error } => (cmds, *stop_on_error),
+                };
+                if cmds.iter().any(|cmd| matches!(cmd, Command::exit(_))) {
+                    return Err(Error::NotAllowedInside("batch"));
+                }
+                let mut failed = false;
+                let results = cmds.iter().map(|cmd| {
+                    if failed {
+                        return json!({"skipped": true});
+                    }
+                    match cmd.run_with(ctx, part) {
+                        Ok(value) => json!({"ok": value}),
+                        Err(e) => {
+                            failed = stop_on_error.is_true();
+                            json!({"err": ctx.error_it(Some(cmd), &e)})
+                        },
+                    }
+                }).collect::<Vec<_>>();
+                results.into()
+            },
+            Command::spawn_task(cmd) => {
+                if matches!(**cmd, Command::exit(_)) || cmd.uses_context() {
+                    return Err(Error::NotAllowedInside("spawn_task"));
+                }
+                let id = ctx.next_task;
+                ctx.next_task += 1;
+                ctx.tasks.insert(id, tasks::Task::spawn((**cmd).clone(), ctx.detached()));
+                id.into()
+            },
+            Command::task_status(id) => {
+                let task = ctx.tasks.get_mut(id).ok_or(Error::InvalidTaskId(*id))?;
+                if task.is_done() { "done" } else { "pending" }.into()
+            },
+            Command::task_result { id, timeout_ms } => {
+                let mut task = ctx.task(*id)?;
+                let result = task.wait(timeout_ms.map(Duration::from_millis));
+                let value = task_result(ctx, *id, &task, result);
+                if value.is_err() {
+                    ctx.tasks.insert(*id, task);
+                }
+                value?
+            },
+            Command::with_timeout { timeout_ms, cmd } => {
+                let detached = cmd.timeout_context(ctx)?;
+                cmd.run_timeout(detached, *timeout_ms)?
+            },
+            Command::cancel_task(id) => {
+                let task = ctx.tasks.get_mut(id).ok_or(Error::InvalidTaskId(*id))?;
+                task.cancel();
+                (!task.is_done()).into()
+            },
+            Command::exit(code) => {
+                ctx.exit = Some(*code);
+                Value::Null
+            },
+            Command::stats => {
+                let memory = sys::self_memory();
+                json!({
+                    "commands": ctx.stats.commands,
+                    "errors": ctx.stats.errors,
+                    "by_command": ctx.stats.by_command,
+                    "printed_bytes": ctx.output.written(),
+                    "eprinted_bytes": ctx.error_output.written(),
+                    "uptime_ms": ctx.created.elapsed().as_millis() as u64,
+                    "children": ctx.sub_processors.len(),
+                    "tasks": ctx.tasks.len(),
+                    "timers": ctx.timers.len(),
+                    "histories": ctx.histories.len(),
+                    "rss_bytes": memory.rss,
+                    "peak_rss_bytes": memory.peak_rss,
+                })
+            },
+            Command::stats_reset => {
+                ctx.stats = Stats::default();
+                ctx.output.reset_written();
+                ctx.error_output.reset_written();
+                Value::Null
             },
-            Command::exit(code) => exit(*code),
         })
     }
 }
 
+/// Counters of `stats`, zeroed by `stats_reset`
 #[derive(Debug, Default)]
+struct Stats {
+    commands: u64,
+    errors: u64,
+    by_command: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug)]
 pub struct Context {
     sub_processors: HashMap<u32, Child>,
-    thread_rng: ThreadRng,
+    /// Children seen exited by [`Context::reap_children`], oldest first
+    finished: VecDeque<u32>,
+    rng: random::ContextRng,
+    uuid_v7: random::UuidV7,
+    created: Instant,
+    timers: HashMap<String, Instant>,
+    tasks: HashMap<u32, tasks::Task>,
+    next_task: u32,
+    /// The running bar, there is one at a time
+    progress: Option<(u32, progress::Progress)>,
+    next_progress: u32,
+    /// Entries of `readline` by history id
+    histories: HashMap<String, Vec<String>>,
+    /// Connections of `sqlite_query` by absolute path, with whether they are read-only
+    #[cfg(feature = "sqlite")]
+    databases: HashMap<PathBuf, (bool, rusqlite::Connection)>,
+    /// Cancellation of the task running on this context
+    cancel: Option<Arc<AtomicBool>>,
+    legacy_errors: bool,
+    /// `confirm` answers yes without asking
+    assume_yes: bool,
+    /// Mutating commands are not run, see [`Command::mutates`]
+    dry_run: bool,
+    /// Bytes are answered as `{"text": ..}` or `{"base64": ..}`
+    binary_safe: bool,
+    /// Code of a run `exit`
+    exit: Option<i32>,
+    stats: Stats,
+    root: Option<PathBuf>,
+    policy: Arc<policy::Policy>,
+    output: Output,
+    error_output: Output,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            sub_processors: Default::default(),
+            finished: Default::default(),
+            rng: Default::default(),
+            uuid_v7: Default::default(),
+            created: Instant::now(),
+            timers: Default::default(),
+            tasks: Default::default(),
+            next_task: 1,
+            progress: None,
+            next_progress: 1,
+            histories: Default::default(),
+            #[cfg(feature = "sqlite")]
+            databases: Default::default(),
+            cancel: None,
+            legacy_errors: false,
+            assume_yes: false,
+            dry_run: false,
+            binary_safe: false,
+            exit: None,
+            stats: Stats::default(),
+            root: None,
+            policy: Default::default(),
+            output: Output::new(stdout()),
+            error_output: Output::new(io::stderr()),
+        }
+    }
 }
 
 impl Context {
     pub fn child(&mut self, id: u32) -> Result<Child, Error> {
         self.sub_processors.remove(&id).ok_or(Error::InvalidProcessorId(id))
     }
+
+    pub fn child_ref(&self, id: u32) -> Result<&Child, Error> {
+        self.sub_processors.get(&id).ok_or(Error::InvalidProcessorId(id))
+    }
+
+    /// The children `ids` of `wait_any`, all of them by default
+    #[cfg(feature = "proc")]
+    fn wait_any_ids(&self, ids: &Option<Vec<u32>>) -> Result<Vec<u32>, Error> {
+        let ids = match ids {
+            Some(ids) => {
+                for &id in ids {
+                    self.child_ref(id)?;
+                }
+                ids.clone()
+            },
+            None => self.sub_processors.keys().copied().collect(),
+        };
+        if ids.is_empty() {
+            return Err(Error::NoChildProcess);
+        }
+        Ok(ids)
+    }
+
+    /// Status and id of the first exited child of `ids`, which is unregistered
+    #[cfg(feature = "proc")]
+    fn try_wait_any(&mut self, ids: &[u32]) -> Result<Option<Value>, Error> {
+        for &id in ids {
+            // a concurrent command may have waited it
+            let child = self.sub_processors.get_mut(&id).ok_or(Error::InvalidProcessorId(id))?;
+            if let Some(status) = child.try_wait()? {
+                self.sub_processors.remove(&id);
+                let mut result = status_it(status);
+                result.insert("id".into(), id.into());
+                return Ok(Some(result.into()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reap the exited children, which stay registered with their status and piped output
+    /// for `wait_id`, up to [`MAX_FINISHED`] of them
+    pub fn reap_children(&mut self) {
+        for (&id, child) in &mut self.sub_processors {
+            if !self.finished.contains(&id) && matches!(child.try_wait(), Ok(Some(_))) {
+                self.finished.push_back(id);
+            }
+        }
+        self.finished.retain(|id| self.sub_processors.contains_key(id));
+        while self.finished.len() > MAX_FINISHED {
+            let id = self.finished.pop_front().unwrap();
+            self.sub_processors.remove(&id);
+        }
+    }
+
+    /// Count an answered command for `stats`, no name when it did not parse
+    pub(crate) fn count(&mut self, name: Option<&'static str>, failed: bool) {
+        self.stats.commands += 1;
+        self.stats.errors += u64::from(failed);
+        if let Some(name) = name {
+            *self.stats.by_command.entry(name).or_default() += 1;
+        }
+    }
+
+    /// End the session with `code`, unless it is already ending
+    pub(crate) fn fail(&mut self, code: i32) {
+        self.exit.get_or_insert(code);
+    }
+
+    /// Stop the spawned children still registered, asked with SIGTERM first on unix
+    /// and killed once `grace` is over
+    pub fn terminate_children(&mut self, grace: Duration) {
+        let finished = std::mem::take(&mut self.finished);
+        let mut children = std::mem::take(&mut self.sub_processors)
+            .into_iter()
+            .filter(|(id, _)| !finished.contains(id))
+            .map(|(_, child)| child)
+            .collect::<Vec<_>>();
+        // the pid of a reaped child may be of another process by now
+        children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        #[cfg(unix)]
+        for child in &children {
+            _ = procs::send_signal(child.id(), libc::SIGTERM);
+        }
+        let deadline = Instant::now() + grace;
+        while cfg!(unix) && Instant::now() < deadline {
+            children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+            if children.is_empty() {
+                return;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        for mut child in children {
+            _ = child.kill();
+            _ = child.wait();
+        }
+    }
+
+    /// Whether the child is reaped, its pid may then be of another process
+    #[cfg(feature = "proc")]
+    fn has_exited(&mut self, id: u32) -> Result<bool, Error> {
+        let child = self.sub_processors.get_mut(&id).ok_or(Error::InvalidProcessorId(id))?;
+        Ok(self.finished.contains(&id) || matches!(child.try_wait(), Ok(Some(_))))
+    }
+
+    fn history(&mut self, id: &Option<String>) -> &mut Vec<String> {
+        self.histories.entry(id.clone().unwrap_or_default()).or_default()
+    }
+
+    fn task(&mut self, id: u32) -> Result<tasks::Task, Error> {
+        self.tasks.remove(&id).ok_or(Error::InvalidTaskId(id))
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Sleep, woken early by the cancellation of the task
+    fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        if self.cancel.is_none() {
+            sleep(duration);
+            return Ok(());
+        }
+        let end = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            sleep(left.min(Duration::from_millis(10)));
+        }
+    }
+
+    /// Context of a command not using the state, only the options are kept
+    pub fn detached(&self) -> Self {
+        Context {
+            legacy_errors: self.legacy_errors,
+            assume_yes: self.assume_yes,
+            dry_run: self.dry_run,
+            binary_safe: self.binary_safe,
+            root: self.root.clone(),
+            policy: self.policy.clone(),
+            output: self.output.clone(),
+            error_output: self.error_output.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Confine the path arguments to `root`, which becomes the current directory
+    pub fn set_root(&mut self, root: impl AsRef<Path>) -> Result<(), Error> {
+        let root = root.as_ref();
+        let root = root.canonicalize()
+            .map_err(path_io(&root.to_string_lossy()))?;
+        env::set_current_dir(&root)?;
+        self.root = Some(root);
+        Ok(())
+    }
+
+    /// Path of a path argument, inside the root with `--root`
+    pub fn resolve_path(&self, raw: &str) -> Result<PathBuf, Error> {
+        self.resolve(raw, true)
+    }
+
+    /// Like [`Context::resolve_path`], a final symlink is not followed
+    fn resolve_link(&self, raw: &str) -> Result<PathBuf, Error> {
+        self.resolve(raw, false)
+    }
+
+    fn resolve(&self, raw: &str, follow: bool) -> Result<PathBuf, Error> {
+        match &self.root {
+            Some(root) => jail::resolve(root, &env::current_dir()?, raw, follow),
+            None => Ok(raw.into()),
+        }
+    }
+
+    /// Key of a database in the connections, absolute so that `set_current_dir` keeps it
+    #[cfg(feature = "sqlite")]
+    fn database_path(&self, raw: &str) -> Result<PathBuf, Error> {
+        Ok(std::path::absolute(self.resolve_path(raw)?)?)
+    }
+
+    /// The connection to `raw`, opened unless open with the same `readonly`
+    #[cfg(feature = "sqlite")]
+    fn database(&mut self, raw: &str, readonly: bool) -> Result<&rusqlite::Connection, Error> {
+        if !readonly && !cfg!(feature = "fs-write") {
+            return Err(Error::InvalidArgument("writing needs the fs-write feature".into()));
+        }
+        let path = self.database_path(raw)?;
+        if self.databases.get(&path).is_none_or(|(open, _)| *open != readonly) {
+            let connection = sqlite::open(&path, readonly)?;
+            self.databases.insert(path.clone(), (readonly, connection));
+        }
+        Ok(&self.databases[&path].1)
+    }
+
+    /// Path of a result, relative to the root with `--root`
+    fn path_it(&self, path: &Path, raw: &str) -> Result<Value, Error> {
+        match &self.root {
+            Some(root) => self.os_it(jail::relative(root, path, raw)?),
+            None => self.os_it(path),
+        }
+    }
+
+    /// A path or an environment value, an error when not UTF-8 unless `--binary-safe`
+    fn os_it(&self, s: impl AsRef<OsStr>) -> Result<Value, Error> {
+        let s = s.as_ref();
+        if self.binary_safe {
+            return Ok(encoding::data_it(s.as_encoded_bytes().to_vec()));
+        }
+        oss_it(s)
+    }
+
+    /// Output of a child, made valid UTF-8 unless `--binary-safe`
+    #[cfg(feature = "proc")]
+    fn bytes_it(&self, bytes: Vec<u8>) -> Value {
+        if self.binary_safe {
+            return encoding::data_it(bytes);
+        }
+        String::from_utf8_lossy(&bytes).into()
+    }
+
+    /// Commands to refuse, also reported by `hello`
+    pub fn set_policy(&mut self, policy: policy::Policy) {
+        self.policy = Arc::new(policy);
+    }
+
+    pub fn policy(&self) -> Arc<policy::Policy> {
+        self.policy.clone()
+    }
+
+    /// Write the print commands to `output` instead of stdout
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn output(&self) -> Output {
+        self.output.clone()
+    }
+
+    /// Write the eprint commands to `output` instead of stderr
+    pub fn set_error_output(&mut self, output: Output) {
+        self.error_output = output;
+    }
+
+    pub fn error_output(&self) -> Output {
+        self.error_output.clone()
+    }
+
+    /// Write out what the print commands kept, see `set_buffering`
+    pub fn flush_outputs(&self) -> io::Result<()> {
+        self.output.flush()?;
+        self.error_output.flush()
+    }
+
+    /// Answer `confirm` with yes without asking
+    pub fn set_assume_yes(&mut self, assume_yes: bool) {
+        self.assume_yes = assume_yes;
+    }
+
+    /// Answer the mutating commands with a stand-in result instead of running them
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Answer file contents, paths, environment values and child output as
+    /// `{"text": ..}` when UTF-8 and `{"base64": ..}` otherwise, instead of failing or lossy text
+    pub fn set_binary_safe(&mut self, binary_safe: bool) {
+        self.binary_safe = binary_safe;
+    }
+
+    /// Code of the `exit` command that ended the bridge loop, to exit with once cleaned up
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Answer errors as the plain message instead of the structured object
+    pub fn set_legacy_errors(&mut self, legacy: bool) {
+        self.legacy_errors = legacy;
+    }
+
+    /// Value of an err answer of `cmd`,
+    /// `{message, kind, os_code, path, command}` or the legacy message
+    pub fn error_it(&self, cmd: Option<&Command>, e: &Error) -> Value {
+        if self.legacy_errors {
+            return e.to_string().into();
+        }
+        let mut value = e.to_value();
+        value["command"] = cmd.map(Command::name).into();
+        if let (Some(root), Some(path)) = (&self.root, e.path())
+            && let Ok(relative) = jail::relative(root, Path::new(path), path)
+        {
+            // never report the host path of the root
+            let message = e.to_string().replace(&format!("{path:?}"), &format!("{relative:?}"));
+            value["message"] = message.into();
+            value["path"] = relative.into();
+        }
+        value
+    }
+
+    /// Make the random commands deterministic, `None` restores the thread RNG
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.rng = match seed {
+            Some(seed) => random::ContextRng::Seeded(Box::new(StdRng::seed_from_u64(seed))),
+            None => random::ContextRng::default(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(line: &str) -> Result<Value, Error> {
+        Command::parse(line)?.run(&mut Context::default())
+    }
+
+    #[test]
+    fn platform() {
+        let platform = run(r#""platform""#).unwrap();
+        let os = if cfg!(target_os = "linux") {
+            "linux"
+        } else if cfg!(target_os = "macos") {
+            "macos"
+        } else if cfg!(windows) {
+            "windows"
+        } else {
+            env::consts::OS
+        };
+        assert_eq!(platform["os"], os);
+        let arch = if cfg!(target_arch = "x86_64") {
+            "x86_64"
+        } else if cfg!(target_arch = "aarch64") {
+            "aarch64"
+        } else {
+            env::consts::ARCH
+        };
+        assert_eq!(platform["arch"], arch);
+        assert_eq!(platform["family"], if cfg!(unix) { "unix" } else { "windows" });
+    }
+
+    #[test]
+    fn cpu_count() {
+        let count = run(r#""cpu_count""#).unwrap();
+        assert!(count["logical"].as_u64().unwrap() >= 1);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    #[test]
+    fn memory_info() {
+        let info = run(r#""memory_info""#).unwrap();
+        let total = info["total"].as_u64().unwrap();
+        assert!(total > 0);
+        assert!(info["available"].as_u64().unwrap() <= total);
+    }
+
+    #[test]
+    fn load_average() {
+        let loads = run(r#""load_average""#).unwrap();
+        for field in ["one", "five", "fifteen"] {
+            match loads[field].as_f64() {
+                Some(load) => assert!(load >= 0.0),
+                None => assert!(cfg!(not(unix)) && loads[field].is_null()),
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    #[test]
+    fn uptime() {
+        let first = run(r#""uptime""#).unwrap().as_f64().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let second = run(r#""uptime""#).unwrap().as_f64().unwrap();
+        assert!(first > 0.0);
+        assert!(second >= first);
+    }
+
+    #[test]
+    fn confirm_assume_yes() {
+        let mut ctx = Context::default();
+        ctx.set_assume_yes(true);
+        // answered without reading stdin
+        let confirmed = Command::parse(r#"{"confirm": {"text": "go on?", "default": false}}"#)
+            .and_then(|cmd| cmd.run(&mut ctx));
+        assert_eq!(confirmed.unwrap(), true);
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn exited_children_are_not_signaled() {
+        let mut ctx = Context::default();
+        let mut run = |line: &str| Command::parse(line).and_then(|cmd| cmd.run(&mut ctx));
+        let id = run(r#"{"spawn": ["true", {}]}"#).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let killed = run(&format!(r#"{{"kill_tree_id": {{"id": {id}}}}}"#)).unwrap();
+        assert_eq!(killed, json!({"signaled": [], "errors": []}));
+        // still registered for wait_id
+        assert_eq!(run(&format!(r#"{{"wait_id": {{"id": {id}}}}}"#)).unwrap(), json!({"status": 0}));
+    }
+
+    #[test]
+    fn compiled_commands() {
+        let hello = run(r#""hello""#).unwrap();
+        let commands = hello["commands"].as_array().unwrap();
+        for (enabled, name, line) in [
+            (cfg!(feature = "proc"), "spawn", r#"{"spawn": ["true", {}]}"#),
+            (cfg!(feature = "net"), "http_get", r#"{"http_get": {"url": "http://localhost"}}"#),
+            (cfg!(feature = "env-write"), "set_env", r#"{"set_env": ["JQ_BRIDGE_TEST", "1"]}"#),
+            (cfg!(feature = "fs-write"), "write", r#"{"write": {"path": "out", "text": ""}}"#),
+        ] {
+            assert_eq!(commands.contains(&name.into()), enabled, "{name}");
+            if !enabled {
+                // as for a command that never was
+                let Err(Error::InvalidCommand(message)) = Command::parse(line) else {
+                    panic!("{name} parsed without its feature");
+                };
+                assert!(message.starts_with(&format!("unknown command {name:?}")), "{message}");
+            }
+        }
+    }
+
+    #[cfg(feature = "env-write")]
+    #[test]
+    fn load_dotenv_get_env() {
+        let path = env::temp_dir().join(format!("jq-bridge-dotenv-{}", process::id()));
+        fs::write(&path, "JQ_BRIDGE_DOTENV_TEST=\"a # b\" # comment\r\nJQ_BRIDGE_DOTENV_EMPTY=\r\n").unwrap();
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let loaded = run(json!({"load_dotenv": {"path": path, "apply": true}})).unwrap();
+        assert_eq!(loaded, json!({
+            "vars": {"JQ_BRIDGE_DOTENV_EMPTY": "", "JQ_BRIDGE_DOTENV_TEST": "a # b"},
+            "warnings": [],
+        }));
+        assert_eq!(run(json!({"get_env": "JQ_BRIDGE_DOTENV_TEST"})).unwrap(), "a # b");
+        assert_eq!(run(json!({"get_env": "JQ_BRIDGE_DOTENV_EMPTY"})).unwrap(), "");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn signaled_status() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let id = run(json!({"spawn": ["sleep", {"args": ["60"]}]})).unwrap();
+        run(json!({"kill_tree_id": {"id": id, "signal": "TERM"}})).unwrap();
+        let status = run(json!({"wait_id": {"id": id}})).unwrap();
+        assert_eq!(status, json!({"status": null, "signal": 15, "core_dumped": false}));
+        assert_eq!(run(json!({"system": ["true", []]})).unwrap(), json!({"status": 0}));
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn wait_any_order() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let ids = ["0.3", "0.1", "0.2"].map(|secs| run(json!({"spawn": ["sleep", {"args": [secs]}]})).unwrap());
+        let exited = [(); 3].map(|()| run(json!({"wait_any": {}})).unwrap());
+        // the shortest first
+        for (exited, id) in exited.iter().zip([&ids[1], &ids[2], &ids[0]]) {
+            assert_eq!(&exited["id"], id);
+            assert_eq!(exited["status"], 0);
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    #[test]
+    fn list_processes() {
+        let has_self = |processes: &Value| {
+            processes.as_array().unwrap().iter().any(|info| info["pid"] == process::id())
+        };
+        let all = run(r#"{"list_processes": {}}"#).unwrap();
+        assert!(has_self(&all));
+        let name = procs::ProcessInfo::of(process::id()).unwrap().name.unwrap();
+        let named = run(&json!({"list_processes": {"name_filter": name}}).to_string()).unwrap();
+        assert!(has_self(&named));
+        assert!(named.as_array().unwrap().len() < all.as_array().unwrap().len());
+        assert!(named.as_array().unwrap().iter().all(|info| info["name"].as_str().unwrap().contains(&name)));
+    }
+
+    #[cfg(all(unix, feature = "proc", feature = "env-write"))]
+    #[test]
+    fn pass_envs() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        run(json!({"set_env": ["JQ_BRIDGE_PASS_SET", "passed"]})).unwrap();
+        run(json!({"set_env": ["JQ_BRIDGE_PASS_OVER", "passed"]})).unwrap();
+        let builder = json!({
+            "pass_envs": ["JQ_BRIDGE_PASS_SET", "JQ_BRIDGE_PASS_OVER", "JQ_BRIDGE_PASS_UNSET"],
+            "envs": {"JQ_BRIDGE_PASS_OVER": "explicit", "JQ_BRIDGE_EXPLICIT": "explicit"},
+        });
+        let output = run(json!({"command": ["env", builder]})).unwrap();
+        let mut vars = output["stdout"].as_str().unwrap().lines().collect::<Vec<_>>();
+        vars.sort();
+        // the explicit envs win
+        assert_eq!(vars, [
+            "JQ_BRIDGE_EXPLICIT=explicit",
+            "JQ_BRIDGE_PASS_OVER=explicit",
+            "JQ_BRIDGE_PASS_SET=passed",
+        ]);
+
+        let strict = json!({"pass_envs": ["JQ_BRIDGE_PASS_UNSET"], "strict_pass_envs": true});
+        let unset = run(json!({"command": ["env", strict]}));
+        assert!(matches!(unset, Err(Error::EnvNotFound(name)) if name == "JQ_BRIDGE_PASS_UNSET"));
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn detached_outlives_context() {
+        let mut ctx = Context::default();
+        let pid = Command::parse(r#"{"spawn_detached": ["sleep", {"args": ["5"]}]}"#)
+            .and_then(|cmd| cmd.run(&mut ctx))
+            .unwrap();
+        let pid = pid.as_u64().unwrap() as u32;
+        ctx.terminate_children(Duration::ZERO);
+        drop(ctx);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(procs::pid_alive(pid).unwrap());
+        procs::send_signal(pid, libc::SIGKILL).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn stream_command() {
+        let script = "i=1; while [ $i -le 100 ]; do
+            echo $i; [ $((i % 25)) = 0 ] && sleep 0.05; i=$((i + 1))
+        done; exit 3";
+        let builder = json!({"args": ["-c", script]});
+        let cmd = Command::parse(&json!({"stream_command": {"prog": "sh", "builder": builder}}).to_string())
+            .unwrap();
+        let mut parts = vec![];
+        let status = cmd.run_with(&mut Context::default(), &mut |part| {
+            parts.push(part);
+            Ok(())
+        });
+        assert_eq!(status.unwrap(), json!({"status": 3}));
+        let lines = parts.iter()
+            .map(|part| {
+                assert_eq!(part["stream"], "stdout");
+                part["line"].as_str().unwrap().parse().unwrap()
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(lines, (1..=100).collect::<Vec<_>>());
+    }
+
+    #[cfg(all(unix, feature = "proc"))]
+    #[test]
+    fn retry_until_success() {
+        let counter = env::temp_dir().join(format!("jq-bridge-retry-{}", process::id()));
+        _ = fs::remove_file(&counter);
+        // fails twice, then succeeds
+        let script = r#"n=$(($(cat "$0" 2>/dev/null || echo 0) + 1)); echo $n > "$0"; [ $n -ge 3 ]"#;
+        let system = json!({"system": ["sh", ["-c", script, counter]]});
+        let retried = run(&json!({"retry": {"cmd": system, "attempts": 5, "delay_ms": 10}}).to_string());
+        assert_eq!(retried.unwrap(), json!({"ok": {"status": 0}, "attempts_used": 3}));
+        fs::remove_file(&counter).unwrap();
+    }
+
+    #[test]
+    fn retry_cancelled() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let read = json!({"read": "/nonexistent/jq-bridge"});
+        let retry = json!({"retry": {"cmd": read, "attempts": 2, "delay_ms": 60_000}});
+        let id = run(json!({"spawn_task": retry})).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        assert_eq!(run(json!({"cancel_task": id})).unwrap(), true);
+        let result = run(json!({"task_result": {"id": id, "timeout_ms": 5000}})).unwrap();
+        assert_eq!(result["err"]["kind"], "cancelled");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn umask() {
+        #[cfg(feature = "proc")]
+        for (umask, printed) in [(0o022, "0022"), (0o077, "0077")] {
+            let builder = json!({"args": ["-c", "umask"], "umask": umask});
+            let output = run(&json!({"command": ["sh", builder]}).to_string()).unwrap();
+            assert_eq!(output["stdout"].as_str().unwrap().trim(), printed);
+        }
+        let old = run(r#""get_umask""#).unwrap();
+        assert_eq!(run(&json!({"set_umask": 0o027}).to_string()).unwrap(), old);
+        assert_eq!(run(r#""get_umask""#).unwrap(), 0o027);
+        assert_eq!(run(&json!({"set_umask": old}).to_string()).unwrap(), 0o027);
+    }
+
+    #[cfg(all(unix, feature = "env-write"))]
+    #[test]
+    fn env_vars() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        run(json!({"set_env": ["JQ_BRIDGE_VARS_B", "2"]})).unwrap();
+        run(json!({"set_env": ["JQ_BRIDGE_VARS_A", "1"]})).unwrap();
+        // 0xFF
+        run(json!({"set_env": ["JQ_BRIDGE_VARS_C", {"base64": "/w=="}]})).unwrap();
+
+        let all = run(json!({"env_vars": {}})).unwrap();
+        assert_eq!(all["vars"]["JQ_BRIDGE_VARS_A"], "1");
+        assert!(all["skipped"].as_u64().unwrap() >= 1);
+        let vars = run(json!({"env_vars": {"prefix": "JQ_BRIDGE_VARS_"}})).unwrap();
+        assert_eq!(vars, json!({"vars": {"JQ_BRIDGE_VARS_A": "1", "JQ_BRIDGE_VARS_B": "2"}, "skipped": 1}));
+        let lossy = run(json!({"env_vars": {"prefix": "JQ_BRIDGE_VARS_C", "lossy": true}})).unwrap();
+        assert_eq!(lossy, json!({"vars": {"JQ_BRIDGE_VARS_C": "\u{fffd}"}, "skipped": 0}));
+    }
+
+    #[test]
+    fn now() {
+        let ns = |now: &Value| now["epoch_ns"].as_str().unwrap().parse::<u128>().unwrap();
+        let first = run(r#""now""#).unwrap();
+        let second = run(r#""now""#).unwrap();
+        assert!(ns(&second) >= ns(&first));
+        assert!(second["epoch_ms"].as_u64() >= first["epoch_ms"].as_u64());
+        let parsed = times::parse(first["rfc3339"].as_str().unwrap(), "rfc3339").unwrap();
+        assert!((times::epoch_of(parsed) - first["epoch"].as_f64().unwrap()).abs() < 1.0);
+
+        // anchored at the creation of the context
+        let mut ctx = Context::default();
+        let mut monotonic = || Command::now_monotonic.run(&mut ctx).unwrap().as_u64().unwrap();
+        let first = monotonic();
+        assert!(monotonic() >= first);
+    }
+
+    #[test]
+    fn sleep() {
+        let started = Instant::now();
+        assert_eq!(run(r#"{"sleep": 50}"#).unwrap(), Null);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    #[test]
+    fn timers() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        run(json!({"timer_start": "section"})).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        let elapsed = run(json!({"timer_elapsed": "section"})).unwrap().as_f64().unwrap();
+        assert!((30.0..500.0).contains(&elapsed), "{elapsed}");
+        assert!(run(json!("timer_list")).unwrap()["section"].as_f64().unwrap() >= elapsed);
+        let stopped = run(json!({"timer_stop": "section"})).unwrap().as_f64().unwrap();
+        assert!(stopped >= elapsed);
+        let again = run(json!({"timer_stop": "section"}));
+        assert!(matches!(again, Err(Error::TimerNotFound(name)) if name == "section"));
+    }
+
+    #[test]
+    fn set_current_dir() {
+        let dir = env::temp_dir().join(format!("jq-bridge-cwd-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("inside"), "inside").unwrap();
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        // the other tests use absolute paths
+        let original = env::current_dir().unwrap();
+        let old = run(json!({"set_current_dir": dir})).unwrap();
+        assert_eq!(old.as_str().map(Path::new), Some(&*original));
+        let current = run(json!("current_dir")).unwrap();
+        assert_eq!(fs::canonicalize(current.as_str().unwrap()).unwrap(), fs::canonicalize(&dir).unwrap());
+        assert_eq!(run(json!({"read": "inside"})).unwrap(), "inside");
+
+        let missing = run(json!({"set_current_dir": "missing"})).unwrap_err();
+        assert!(missing.to_string().contains("missing"), "{missing}");
+        assert_eq!(run(json!("current_dir")).unwrap(), current);
+
+        assert_eq!(run(json!({"set_current_dir": old})).unwrap(), current);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Run by [`no_tty_when_piped`] with every stdio redirected
+    #[test]
+    #[ignore]
+    fn piped_tty() {
+        if env::var_os("JQ_BRIDGE_PIPED").is_none() {
+            return;
+        }
+        for stream in ["stdin", "stdout", "stderr"] {
+            assert_eq!(run(&json!({"is_tty": {"stream": stream}}).to_string()).unwrap(), false, "{stream}");
+        }
+        assert_eq!(run(r#""terminal_size""#).unwrap(), Null);
+        assert!(run(r#"{"is_tty": {"stream": "tty"}}"#).is_err());
+    }
+
+    #[test]
+    fn no_tty_when_piped() {
+        let output = process::Command::new(env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "tests::piped_tty", "--test-threads=1"])
+            .env("JQ_BRIDGE_PIPED", "1")
+            .stdin(process::Stdio::null())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success() && stdout.contains(" 1 passed"), "{stdout}");
+    }
+
+    #[test]
+    fn current_exe() {
+        let exe = run(r#""current_exe""#).unwrap();
+        let exe = Path::new(exe.as_str().unwrap());
+        assert!(exe.is_file(), "{exe:?}");
+        let name = exe.file_name().unwrap().to_str().unwrap();
+        assert!(name.contains(env!("CARGO_CRATE_NAME")), "{name}");
+        assert_eq!(run(r#""exe_dir""#).unwrap(), exe.parent().unwrap().to_str().unwrap());
+    }
+
+    #[test]
+    fn random_range() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let mut counts = [0; 6];
+        for _ in 0..10_000 {
+            let n = run(json!({"random_range": {"min": 1, "max": 6}})).unwrap().as_i64().unwrap();
+            assert!((1..=6).contains(&n), "{n}");
+            counts[n as usize - 1] += 1;
+        }
+        // each face is expected 1667 times
+        assert!(counts.iter().all(|&count| count > 1000), "{counts:?}");
+        assert_eq!(run(json!({"random_range": {"min": 3, "max": 3}})).unwrap(), 3);
+        let empty = run(json!({"random_range": {"min": 6, "max": 1}}));
+        assert!(matches!(empty, Err(Error::InvalidArgument(msg)) if msg == "empty range 6..=1"));
+    }
+
+    #[test]
+    fn random_bytes() {
+        for len in [0, 1, 16, 1000] {
+            let data = run(&json!({"random_bytes": {"len": len}}).to_string()).unwrap();
+            assert_eq!(encoding::base64_decode(data.as_str().unwrap()).unwrap().len(), len);
+            let secure = run(&json!({"random_bytes": {"len": len, "secure": true}}).to_string()).unwrap();
+            assert_eq!(encoding::base64_decode(secure.as_str().unwrap()).unwrap().len(), len);
+            let hex = run(&json!({"random_bytes": {"len": len, "encoding": "hex"}}).to_string()).unwrap();
+            let hex = hex.as_str().unwrap();
+            assert_eq!(hex.len(), 2 * len);
+            assert!(hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')), "{hex}");
+        }
+        let line = r#"{"random_bytes": {"len": 32}}"#;
+        assert_ne!(run(line).unwrap(), run(line).unwrap());
+        assert!(run(r#"{"random_bytes": {"len": 4, "encoding": "utf8"}}"#).is_err());
+    }
+
+    #[test]
+    fn random_string() {
+        for (charset, len) in [("abc", 200), ("digits", 17), ("ü", 3), ("alnum", 0)] {
+            let allowed = random::charset(charset).unwrap();
+            let line = json!({"random_string": {"len": len, "charset": charset}}).to_string();
+            let string = run(&line).unwrap();
+            let string = string.as_str().unwrap();
+            assert_eq!(string.chars().count(), len);
+            assert!(string.chars().all(|ch| allowed.contains(&ch)), "{string}");
+        }
+        let string = run(r#"{"random_string": {"len": 100}}"#).unwrap();
+        assert!(string.as_str().unwrap().chars().all(|ch| ch.is_ascii_alphanumeric()));
+        assert!(run(r#"{"random_string": {"len": 4, "charset": ""}}"#).is_err());
+    }
+
+    #[test]
+    fn shuffle_choice_sample() {
+        let sorted = |value: Value| {
+            let mut items: Vec<_> = value.as_array().unwrap().iter().map(Value::to_string).collect();
+            items.sort();
+            items
+        };
+        let array = json!([1, 1, 2, "a", null, [3]]);
+        let shuffled = run(&json!({"shuffle": array}).to_string()).unwrap();
+        assert_eq!(sorted(shuffled), sorted(array.clone()));
+        assert_eq!(run(r#"{"shuffle": []}"#).unwrap(), json!([]));
+
+        assert_eq!(run(r#"{"choice": ["only"]}"#).unwrap(), "only");
+        assert_eq!(run(r#"{"choice": []}"#).unwrap(), Null);
+        let choice = run(&json!({"choice": array}).to_string()).unwrap();
+        assert!(array.as_array().unwrap().contains(&choice));
+        assert!(run(r#"{"choice": {"a": 1}}"#).is_err());
+
+        let all = run(&json!({"sample": {"array": array, "count": 6}}).to_string()).unwrap();
+        assert_eq!(sorted(all), sorted(array.clone()));
+        let some = run(&json!({"sample": {"array": [1, 2, 3, 4], "count": 2}}).to_string()).unwrap();
+        let some = some.as_array().unwrap();
+        assert!(some.len() == 2 && some[0] != some[1], "{some:?}");
+        let many = run(r#"{"sample": {"array": ["x"], "count": 5, "replace": true}}"#).unwrap();
+        assert_eq!(many, json!(["x", "x", "x", "x", "x"]));
+        let too_many = run(r#"{"sample": {"array": [1, 2], "count": 3}}"#);
+        assert!(matches!(too_many, Err(Error::InvalidArgument(msg))
+            if msg == "sample 3 from 2 elements without replacement"));
+        assert!(run(r#"{"sample": {"array": [], "count": 1, "replace": true}}"#).is_err());
+    }
+
+    #[test]
+    fn seeded_random() {
+        let lines = [
+            json!("random"),
+            json!("random_float"),
+            json!({"random_range": {"min": -1000, "max": 1000}}),
+            json!({"random_string": {"len": 16}}),
+            json!({"shuffle": [1, 2, 3, 4, 5, 6, 7, 8]}),
+            json!({"uuid": null}),
+        ];
+        let draw = |seed: u64| {
+            let mut ctx = Context::default();
+            Command::parse(&json!({"set_seed": seed}).to_string()).unwrap().run(&mut ctx).unwrap();
+            lines.iter()
+                .map(|line| Command::parse(&line.to_string()).unwrap().run(&mut ctx).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        run(json!({"set_seed": 42})).unwrap();
+        let first = run(json!("random")).unwrap();
+        run(json!({"set_seed": 42})).unwrap();
+        assert_eq!(run(json!("random")).unwrap(), first);
+        run(json!("clear_seed")).unwrap();
+        assert_ne!(run(json!({"random_string": {"len": 16}})).unwrap(), draw(42)[3]);
+    }
+
+    #[test]
+    fn base64_binary_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let hex = encoding::hex(&bytes);
+        for (url_safe, no_pad) in [(false, false), (false, true), (true, false), (true, true)] {
+            let line = json!({"base64_encode": {"data_hex": hex, "url_safe": url_safe, "no_pad": no_pad}});
+            let encoded = run(&line.to_string()).unwrap();
+            assert_eq!(encoded.as_str().unwrap().ends_with('='), !no_pad);
+            let decoded = run(&json!({"base64_decode": {"data": encoded}}).to_string()).unwrap();
+            assert_eq!(decoded, hex);
+        }
+        let text = run(r#"{"base64_decode": {"data": "aMOk", "as_text": true}}"#).unwrap();
+        assert_eq!(text, "hä");
+        assert!(run(r#"{"base64_decode": {"data": "/w==", "as_text": true}}"#).is_err());
+        assert!(run(r#"{"base64_encode": {"text": "a", "data_hex": "61"}}"#).is_err());
+    }
+
+    #[test]
+    fn crc32_check_values() {
+        let path = env::temp_dir().join(format!("jq-bridge-crc32-{}", process::id()));
+        fs::write(&path, "123456789").unwrap();
+        for (variant, hex) in [("ieee", "cbf43926"), ("castagnoli", "e3069283"), ("adler32", "091e01de")] {
+            let expected = json!({"value": u32::from_str_radix(hex, 16).unwrap(), "hex": hex});
+            let text = json!({"crc32": {"text": "123456789", "variant": variant}});
+            assert_eq!(run(&text.to_string()).unwrap(), expected, "{variant}");
+            let data = json!({"crc32": {"data_base64": "MTIzNDU2Nzg5", "variant": variant}});
+            assert_eq!(run(&data.to_string()).unwrap(), expected, "{variant}");
+            let file = json!({"crc32": {"path": path, "variant": variant}});
+            assert_eq!(run(&file.to_string()).unwrap(), expected, "{variant}");
+        }
+        assert_eq!(run(r#"{"crc32": {"text": ""}}"#).unwrap(), json!({"value": 0, "hex": "00000000"}));
+        assert!(run(r#"{"crc32": {"text": "", "variant": "crc64"}}"#).is_err());
+        assert!(run(&json!({"crc32": {"text": "", "path": path}}).to_string()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn random_distributions() {
+        let stats = |line: Value| {
+            let samples = run(&line.to_string()).unwrap();
+            let samples: Vec<f64> = samples.as_array().unwrap().iter().map(|n| n.as_f64().unwrap()).collect();
+            assert_eq!(samples.len(), 50_000);
+            let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+            let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+            (mean, var.sqrt(), samples)
+        };
+        let (mean, std_dev, _) = stats(json!({"random_normal": {"mean": 10.0, "std_dev": 2.0, "count": 50_000}}));
+        assert!((mean - 10.0).abs() < 0.1 && (std_dev - 2.0).abs() < 0.1, "{mean} {std_dev}");
+        let (mean, std_dev, samples) = stats(json!({"random_exp": {"lambda": 4.0, "count": 50_000}}));
+        assert!((mean - 0.25).abs() < 0.01 && (std_dev - 0.25).abs() < 0.01, "{mean} {std_dev}");
+        assert!(samples.iter().all(|&x| x >= 0.0));
+        assert!(run(r#"{"random_normal": {"mean": 0, "std_dev": 0}}"#).unwrap().is_f64());
+
+        for line in [
+            r#"{"random_normal": {"mean": 0, "std_dev": -1}}"#,
+            r#"{"random_exp": {"lambda": 0}}"#,
+            r#"{"random_exp": {"lambda": -2}}"#,
+        ] {
+            assert!(matches!(run(line), Err(Error::InvalidArgument(_))), "{line}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn error_values() {
+        let dir = env::temp_dir().join(format!("jq-bridge-errors-{}", process::id()));
+        let missing = dir.join("missing");
+        let missing = missing.to_str().unwrap();
+        let not_found = |e: Error| {
+            let value = e.to_value();
+            assert_eq!(value["kind"], "not_found", "{value}");
+            assert_eq!(value["os_code"], libc::ENOENT, "{value}");
+            value["path"].clone()
+        };
+        assert_eq!(not_found(run(&json!({"read": missing}).to_string()).unwrap_err()), missing);
+        assert_eq!(not_found(run(&json!({"metadata": missing}).to_string()).unwrap_err()), missing);
+        #[cfg(feature = "fs-write")]
+        {
+            let nested = format!("{missing}/file");
+            let write = run(&json!({"write": {"path": nested, "text": "x"}}).to_string()).unwrap_err();
+            assert_eq!(not_found(write), nested);
+        }
+
+        let denied = Error::PathIo { path: "/root/secret".into(), source: io::Error::from_raw_os_error(libc::EACCES) };
+        let value = denied.to_value();
+        assert_eq!(value["kind"], "permission_denied");
+        assert_eq!(value["os_code"], libc::EACCES);
+        assert_eq!(value["path"], "/root/secret");
+        assert!(value["message"].as_str().unwrap().contains("/root/secret"), "{value}");
+
+        let plain = Error::InvalidArgument("bad".into()).to_value();
+        assert_eq!((&plain["os_code"], &plain["path"]), (&Null, &Null));
+    }
+
+    #[test]
+    fn batch() {
+        let missing = env::temp_dir().join(format!("jq-bridge-batch-missing-{}", process::id()));
+        let cmds = json!([{"echo": 1}, {"read": missing}, {"echo": 2}]);
+        let answers = run(&json!({"batch": cmds}).to_string()).unwrap();
+        let answers = answers.as_array().unwrap();
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0], json!({"ok": 1}));
+        assert_eq!(answers[1]["err"]["kind"], "not_found");
+        assert_eq!(answers[1]["err"]["command"], "read");
+        assert_eq!(answers[2], json!({"ok": 2}));
+
+        let stopped = run(&json!({"batch": {"cmds": cmds, "stop_on_error": true}}).to_string()).unwrap();
+        assert_eq!(stopped[0], json!({"ok": 1}));
+        assert_eq!(stopped[1]["err"]["kind"], "not_found");
+        assert_eq!(stopped[2], json!({"skipped": true}));
+        let going = run(&json!({"batch": {"cmds": cmds, "stop_on_error": false}}).to_string()).unwrap();
+        assert_eq!(going[2], json!({"ok": 2}));
+
+        assert_eq!(run(r#"{"batch": []}"#).unwrap(), json!([]));
+        let exit = run(r#"{"batch": [{"echo": 1}, {"exit": 0}]}"#);
+        assert!(matches!(exit, Err(Error::NotAllowedInside("batch"))));
+    }
+
+    #[test]
+    fn hello() {
+        let hello = run(r#""hello""#).unwrap();
+        assert_eq!(hello["name"], "jq-bridge");
+        assert_eq!(hello["protocol"], PROTOCOL_VERSION);
+        let commands: Vec<_> = hello["commands"].as_array().unwrap().iter()
+            .map(|name| name.as_str().unwrap())
+            .collect();
+        assert_eq!(commands.len(), policy::command_names().len());
+        assert!(commands.contains(&"read"));
+        #[cfg(feature = "proc")]
+        assert!(commands.contains(&"command"));
+
+        let version = hello["version"].as_str().unwrap();
+        let (core, _pre) = version.split_once('-').unwrap_or((version, ""));
+        let parts: Vec<_> = core.split('.').collect();
+        assert_eq!(parts.len(), 3, "{version}");
+        assert!(parts.iter().all(|part| part.parse::<u64>().is_ok()), "{version}");
+    }
+
+    #[test]
+    fn echo_and_ping() {
+        // serde_json stops at 128 levels, the command object is one of them
+        let nest = |levels| (0..levels).fold(json!({"leaf": [1.5, "two", null, true]}), |inner, depth| {
+            json!({"depth": depth, "inner": [inner]})
+        });
+        let nested = nest(60);
+        assert_eq!(run(&json!({"echo": nested}).to_string()).unwrap(), nested);
+        let too_deep = run(&json!({"echo": nest(100)}).to_string());
+        assert!(matches!(too_deep, Err(Error::InvalidCommand(msg)) if msg.contains("recursion limit")));
+
+        let before = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
+        let pong = run(r#""ping""#).unwrap();
+        assert_eq!(pong["pong"], true);
+        assert_eq!(pong.as_object().unwrap().len(), 2);
+        let time = pong["time"].as_u64().unwrap();
+        assert!(time >= before && time < before + 60_000, "{time} {before}");
+    }
+
+    #[test]
+    fn tasks() {
+        let mut ctx = Context::default();
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let path = env::temp_dir().join(format!("jq-bridge-tasks-{}", process::id()));
+        fs::write(&path, "served").unwrap();
+
+        // still hashing when the read is answered
+        let text = "jq-bridge ".repeat(1 << 20);
+        let id = run(json!({"spawn_task": {"hash": {"text": text, "algo": "sha512"}}})).unwrap();
+        assert_eq!(run(json!({"read": path})).unwrap(), "served");
+        assert_eq!(run(json!({"task_status": id})).unwrap(), "pending");
+        let result = run(json!({"task_result": {"id": id, "timeout_ms": 60_000}})).unwrap();
+        assert_eq!(result, json!({"ok": hash::hash("sha512", text.as_bytes()).unwrap()}));
+        // the result is taken
+        assert!(matches!(run(json!({"task_status": id})), Err(Error::InvalidTaskId(_))));
+
+        let id = run(json!({"spawn_task": {"sleep": 60_000}})).unwrap();
+        let pending = run(json!({"task_result": {"id": id, "timeout_ms": 10}}));
+        assert!(matches!(pending, Err(Error::TaskPending(_))));
+        let started = Instant::now();
+        assert_eq!(run(json!({"cancel_task": id})).unwrap(), true);
+        let result = run(json!({"task_result": {"id": id, "timeout_ms": 5000}})).unwrap();
+        assert_eq!(result["err"]["kind"], "cancelled");
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn with_timeout() {
+        let started = Instant::now();
+        let slept = run(r#"{"with_timeout": {"timeout_ms": 100, "cmd": {"sleep": 5000}}}"#);
+        let elapsed = started.elapsed();
+        assert!(matches!(slept, Err(Error::Timeout(100))), "{slept:?}");
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_secs(1), "{elapsed:?}");
+
+        let value = json!({"nested": [1, "two", {"three": null}]});
+        let started = Instant::now();
+        let echoed = run(&json!({"with_timeout": {"timeout_ms": 5000, "cmd": {"echo": value}}}).to_string());
+        assert_eq!(echoed.unwrap(), value);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let read = run(r#"{"with_timeout": {"timeout_ms": 5000, "cmd": {"read": "/nonexistent/jq-bridge"}}}"#);
+        assert!(matches!(read, Err(Error::PathIo { .. })), "{read:?}");
+
+        let exit = run(r#"{"with_timeout": {"timeout_ms": 10, "cmd": {"exit": 0}}}"#);
+        assert!(matches!(exit, Err(Error::NotAllowedInside("with_timeout"))));
+    }
+
+    #[cfg(feature = "fs-write")]
+    #[test]
+    fn history_round_trip() {
+        let mut ctx = Context::default();
+        let path = env::temp_dir().join(format!("jq-bridge-history-{}", process::id()));
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        for line in ["first", "", "second", "second", "with \\ and \"quotes\""] {
+            run(json!({"history_add": {"line": line, "history_id": "saved"}})).unwrap();
+        }
+        run(json!({"history_save": {"path": path, "history_id": "saved"}})).unwrap();
+        run(json!({"history_load": {"path": path, "history_id": "loaded"}})).unwrap();
+        let expected = ["first", "second", "with \\ and \"quotes\""];
+        assert_eq!(ctx.history(&Some("loaded".into())), &expected);
+        assert_eq!(ctx.history(&Some("saved".into())), &expected);
+        assert!(ctx.history(&None).is_empty());
+
+        // a plain file of lines
+        fs::write(&path, "one\ntwo\n").unwrap();
+        Command::parse(&json!({"history_load": {"path": path}}).to_string()).unwrap().run(&mut ctx).unwrap();
+        assert_eq!(ctx.history(&None), &["one", "two"]);
+        fs::remove_file(&path).unwrap();
+        let missing = Command::parse(&json!({"history_load": {"path": path}}).to_string()).unwrap().run(&mut ctx);
+        assert!(matches!(missing, Err(Error::PathIo { .. })), "{missing:?}");
+    }
+
+    #[cfg(feature = "proc")]
+    #[test]
+    fn creation_flags() {
+        // CREATE_NO_WINDOW
+        let flags = 0x0800_0000;
+        #[cfg(windows)]
+        {
+            let line = json!({"command": ["cmd", {"args": ["/c", "exit 7"], "creation_flags": flags}]});
+            assert_eq!(run(&line.to_string()).unwrap()["status"], 7);
+        }
+        #[cfg(not(windows))]
+        {
+            let line = json!({"command": ["true", {"creation_flags": flags}]});
+            assert!(matches!(run(&line.to_string()), Err(Error::Unsupported("creation_flags"))));
+        }
+    }
+
+    #[test]
+    fn file_attributes() {
+        let path = env::temp_dir().join(format!("jq-bridge-attributes-{}", process::id()));
+        fs::write(&path, "attributes").unwrap();
+        let metadata = || run(&json!({"metadata": path}).to_string()).unwrap();
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::OsStrExt;
+
+            use windows_sys::Win32::Storage::FileSystem::{
+                SetFileAttributesW, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL,
+            };
+
+            let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+            let set = |attributes| assert_ne!(unsafe { SetFileAttributesW(wide.as_ptr(), attributes) }, 0);
+            set(FILE_ATTRIBUTE_NORMAL);
+            let plain = metadata();
+            assert_eq!((&plain["hidden"], &plain["system"]), (&json!(false), &json!(false)));
+            set(FILE_ATTRIBUTE_HIDDEN);
+            let hidden = metadata();
+            assert_eq!(hidden["hidden"], true);
+            assert_eq!(hidden["file_attributes"], FILE_ATTRIBUTE_HIDDEN);
+            let extra = run(&json!({"metadata_extra": path}).to_string()).unwrap();
+            assert_eq!(extra["hidden"], true);
+            set(FILE_ATTRIBUTE_NORMAL);
+        }
+        #[cfg(not(windows))]
+        {
+            let metadata = metadata();
+            assert!(metadata.get("file_attributes").is_none() && metadata.get("hidden").is_none(), "{metadata}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn binary_safe_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut ctx = Context::default();
+        ctx.set_binary_safe(true);
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let name = format!("JQ_BRIDGE_BINARY_{}", process::id());
+        unsafe { env::set_var(&name, OsStr::from_bytes(b"a\xffb")) };
+        assert_eq!(run(json!({"get_env": name})).unwrap(), json!({"base64": "Yf9i"}));
+        unsafe { env::set_var(&name, "ascii") };
+        assert_eq!(run(json!({"get_env": name})).unwrap(), json!({"text": "ascii"}));
+        #[cfg(feature = "env-write")]
+        {
+            // a NUL cannot be in the environment
+            let nul = run(json!({"set_env": [name, {"base64": "/wA="}]}));
+            assert!(matches!(nul, Err(Error::InvalidArgument(_))), "{nul:?}");
+            assert!(matches!(run(json!({"set_env": ["A=B", "x"]})), Err(Error::InvalidArgument(_))));
+            assert!(matches!(run(json!({"remove_env": ""})), Err(Error::InvalidArgument(_))));
+            run(json!({"set_env": [name, {"base64": "/w=="}]})).unwrap();
+            assert_eq!(env::var_os(&name).unwrap().as_bytes(), b"\xff");
+            assert_eq!(run(json!({"get_env": name})).unwrap(), json!({"base64": "/w=="}));
+        }
+        unsafe { env::set_var(&name, OsStr::from_bytes(b"\xff")) };
+        // an error without --binary-safe
+        let plain = Command::parse(&json!({"get_env": name}).to_string()).unwrap().run(&mut Context::default());
+        assert!(matches!(plain, Err(Error::InvalidString(_))), "{plain:?}");
+        unsafe { env::remove_var(&name) };
+
+        #[cfg(feature = "proc")]
+        {
+            let printf = json!({"command": ["printf", {"args": ["a\\377b"]}]});
+            let output = run(printf.clone()).unwrap();
+            assert_eq!((&output["stdout"], &output["stderr"]), (&json!({"base64": "Yf9i"}), &json!({"text": ""})));
+            let lossy = Command::parse(&printf.to_string()).unwrap().run(&mut Context::default()).unwrap();
+            assert_eq!(lossy["stdout"], "a\u{fffd}b");
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_round_trip() {
+        let path = env::temp_dir().join(format!("jq-bridge-sqlite-{}.db", process::id()));
+        _ = fs::remove_file(&path);
+        let mut ctx = Context::default();
+        let mut run = |sql: &str, params: Value, readonly: bool| {
+            let line = json!({"sqlite_query": {"path": path, "sql": sql, "params": params, "readonly": readonly}});
+            Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx))
+        };
+        let created = run("create table t (id integer primary key, name text, score real, data blob)", json!([]), false);
+        assert_eq!(created.unwrap(), json!({"rows_affected": 0}));
+        let insert = "insert into t (name, score, data) values (?, ?, ?)";
+        let inserted = run(insert, json!(["one", 1.5, {"base64": "AP8B"}]), false).unwrap();
+        assert_eq!(inserted, json!({"rows_affected": 1}));
+        run(insert, json!([null, true, null]), false).unwrap();
+
+        let rows = run("select * from t where id >= ? order by id", json!([1]), false).unwrap();
+        assert_eq!(rows, json!([
+            {"id": 1, "name": "one", "score": 1.5, "data": {"base64": "AP8B"}},
+            {"id": 2, "name": null, "score": 1.0, "data": null},
+        ]));
+        let blob = run("select length(data) as len, hex(data) as hex from t where id = 1", json!([]), false).unwrap();
+        assert_eq!(blob, json!([{"len": 3, "hex": "00FF01"}]));
+        assert_eq!(run("select 1 where 0", json!([]), false).unwrap(), json!([]));
+
+        let err = run("selec * from t", json!([]), false).unwrap_err();
+        assert_eq!(err.kind(), "sqlite");
+        assert!(err.to_string().contains("near \"selec\": syntax error"), "{err}");
+        let err = run("select ?", json!([[1]]), false).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)), "{err:?}");
+        assert_eq!(run("select ? as n", json!([2]), false).unwrap(), json!([{"n": 2}]));
+
+        let closed = |ctx: &mut Context| {
+            let line = json!({"sqlite_close": path});
+            Command::parse(&line.to_string()).and_then(|cmd| cmd.run(ctx)).unwrap()
+        };
+        assert_eq!(closed(&mut ctx), true);
+        assert_eq!(closed(&mut ctx), false);
+        fs::remove_file(&path).unwrap();
+    }
 }
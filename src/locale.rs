@@ -0,0 +1,121 @@
+//! Locale from the environment
+
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LocaleInfo {
+    pub lang: Option<String>,
+    pub lc_all: Option<String>,
+    pub lc_messages: String,
+    pub lc_numeric: String,
+    pub language: Option<String>,
+    pub encoding: Option<String>,
+}
+
+/// Effective locale of `category` (e.g. `LC_NUMERIC`),
+/// `LC_ALL` overrides the category, which overrides `LANG`,
+/// empty variables are unset and the default is `C`
+pub fn resolve<F>(get: &F, category: &str) -> String
+where F: Fn(&str) -> Option<String>,
+{
+    ["LC_ALL", category, "LANG"].into_iter()
+        .filter_map(get)
+        .find(|value| !value.is_empty())
+        .unwrap_or_else(|| "C".into())
+}
+
+/// Split `language[.codeset][@modifier]`, the modifier is dropped
+pub fn split(locale: &str) -> (&str, Option<&str>) {
+    let locale = locale.split_once('@').map_or(locale, |(locale, _)| locale);
+    match locale.split_once('.') {
+        Some((language, codeset)) => (language, Some(codeset)),
+        None => (locale, None),
+    }
+}
+
+pub fn info<F>(get: F) -> LocaleInfo
+where F: Fn(&str) -> Option<String>,
+{
+    let lc_messages = resolve(&get, "LC_MESSAGES");
+    let lc_numeric = resolve(&get, "LC_NUMERIC");
+    let lc_ctype = resolve(&get, "LC_CTYPE");
+    let language = match split(&lc_messages).0 {
+        "C" | "POSIX" => None,
+        language => Some(language.to_owned()),
+    };
+    LocaleInfo {
+        lang: get("LANG"),
+        lc_all: get("LC_ALL"),
+        language,
+        encoding: split(&lc_ctype).1.map(Into::into),
+        lc_messages,
+        lc_numeric,
+    }
+}
+
+/// User default locale name, e.g. `en-US`
+#[cfg(windows)]
+pub fn user_default() -> Option<String> {
+    use windows_sys::Win32::Globalization::GetUserDefaultLocaleName;
+
+    let mut buf = [0u16; 85];
+    let len = unsafe { GetUserDefaultLocaleName(buf.as_mut_ptr(), buf.len() as i32) };
+    (len > 1).then(|| String::from_utf16_lossy(&buf[..len as usize - 1]))
+}
+
+#[cfg(not(windows))]
+pub fn user_default() -> Option<String> {
+    None
+}
+
+pub fn current() -> LocaleInfo {
+    let mut info = info(|name| std::env::var(name).ok());
+    if info.language.is_none() {
+        info.language = user_default();
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn lc_all_overrides() {
+        let get = env(&[("LC_ALL", "de_DE.UTF-8"), ("LC_NUMERIC", "fr_FR"), ("LANG", "en_US")]);
+        assert_eq!(resolve(&get, "LC_NUMERIC"), "de_DE.UTF-8");
+        assert_eq!(resolve(&get, "LC_MESSAGES"), "de_DE.UTF-8");
+    }
+
+    #[test]
+    fn category_then_lang() {
+        let get = env(&[("LC_ALL", ""), ("LC_NUMERIC", "fr_FR"), ("LANG", "en_US.UTF-8")]);
+        assert_eq!(resolve(&get, "LC_NUMERIC"), "fr_FR");
+        assert_eq!(resolve(&get, "LC_MESSAGES"), "en_US.UTF-8");
+    }
+
+    #[test]
+    fn posix_default() {
+        assert_eq!(info(env(&[])), LocaleInfo {
+            lang: None,
+            lc_all: None,
+            lc_messages: "C".into(),
+            lc_numeric: "C".into(),
+            language: None,
+            encoding: None,
+        });
+        assert_eq!(info(env(&[("LANG", "POSIX")])).language, None);
+    }
+
+    #[test]
+    fn language_and_encoding() {
+        let info = info(env(&[("LANG", "en_US.UTF-8"), ("LC_MESSAGES", "sr_RS@latin")]));
+        assert_eq!(info.language.as_deref(), Some("sr_RS"));
+        assert_eq!(info.encoding.as_deref(), Some("UTF-8"));
+        assert_eq!(split("en_US.ISO-8859-1@euro"), ("en_US", Some("ISO-8859-1")));
+    }
+}
@@ -1,18 +1,36 @@
-use std::{env::args, io::{BufRead, BufReader, BufWriter, Write}, process::{self, exit, Stdio}};
+use std::{
+    env::{self, args},
+    ffi::{OsStr, OsString},
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::{self, exit},
+    sync::{Arc, Mutex, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
 
-use getopts_macro::getopts_options;
-use jq_bridge::{Command, Context};
-use serde_json::json;
+use getopts_macro::{
+    getopts::{Fail, Matches, Options},
+    getopts_options,
+};
+use serde_json::Value;
+use jq_bridge::{
+    audit::{self, AuditLog},
+    bridge::{run_bridge_shared, BridgeOptions},
+    channel::Channel,
+    framing,
+    policy::Policy,
+    Context,
+};
 
 const DESC: &str = "JQ's child processes and file operation etc backend";
 
 fn main() {
-    let options = getopts_options! {
-        -v, --version           "show version";
-        -h, --help*             "show help message";
-        .parsing_style(getopts_macro::getopts::ParsingStyle::StopAtFirstFree)
-    };
-    let matched = match options.parse(args().skip(1)) {
+    jq_bridge::times::init_local_offset();
+
+    let options = options();
+    let matched = match parse_args(&options, &args().skip(1).collect::<Vec<_>>()) {
         Ok(matched) => matched,
         Err(e) => {
             eprintln!("{e}");
@@ -21,7 +39,7 @@ fn main() {
     };
     if matched.opt_present("help") {
         print!(
-            "Usage: {} [Options] <jq> [args]..\n{}",
+            "Usage: {} [Options] [<jq>] [args]..\n{}",
             env!("CARGO_PKG_NAME"),
             options.usage(DESC),
         );
@@ -31,42 +49,613 @@ fn main() {
         println!("{}", env!("CARGO_PKG_VERSION"));
         exit(0)
     }
-    let Some(program) = matched.free.first() else {
-        eprintln!("Expected <jq> argument!");
+    if matched.opt_present("emit-jq-module") {
+        // `--emit-jq-module PATH` would be a jq command, PATH is only taken as `=PATH`
+        if let Some(arg) = matched.free.first() {
+            eprintln!("unexpected argument {arg:?} of --emit-jq-module, use --emit-jq-module=PATH");
+            exit(2)
+        }
+        let module = jq_bridge::jq_module::generate();
+        match matched.opt_str("emit-jq-module") {
+            Some(path) => fs::write(&path, module).unwrap_or_else(|e| {
+                eprintln!("cannot write {path:?}: {e}");
+                exit(2)
+            }),
+            None => print!("{module}"),
+        }
+        exit(0)
+    }
+    let seed = matched.opt_str("seed").map(|seed| {
+        seed.parse::<u64>().unwrap_or_else(|e| {
+            eprintln!("invalid seed {seed:?}: {e}");
+            exit(2)
+        })
+    });
+    let policy = Policy::new(matched.opt_strs("allow"), matched.opt_strs("deny"))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(2)
+        });
+    // jq runs where started, the bridge moves into the root
+    let jq_dir = env::current_dir().unwrap_or_else(|e| {
+        eprintln!("cannot get current directory: {e}");
         exit(2)
+    });
+    let channel = match (matched.opt_str("channel-fd"), matched.opt_str("channel-fifo")) {
+        (Some(_), Some(_)) => {
+            eprintln!("--channel-fd and --channel-fifo are exclusive");
+            exit(2)
+        },
+        (Some(fd), None) => Channel::Fd(fd.parse().unwrap_or_else(|e| {
+            eprintln!("invalid channel fd {fd:?}: {e}");
+            exit(2)
+        })),
+        (None, Some(dir)) => Channel::Fifo(dir.into()),
+        (None, None) => Channel::Stdio,
+    };
+    let framing = |name: &str| {
+        let mode = matched.opt_str(name).unwrap_or_else(|| "line".into());
+        framing::by_name(&mode).unwrap_or_else(|e| {
+            eprintln!("--{name}: {e}");
+            exit(2)
+        })
+    };
+    let mut opts = BridgeOptions {
+        exit_closes: false,
+        commands: framing("command-framing"),
+        answers: framing("framing"),
+        marker: matched.opt_str("marker"),
+        default_timeout: matched.opt_str("default-timeout").map(|ms| {
+            ms.parse().unwrap_or_else(|e| {
+                eprintln!("invalid default timeout {ms:?}: {e}");
+                exit(2)
+            })
+        }),
+        verbose_responses: matched.opt_present("verbose-responses"),
+        strict: matched.opt_present("strict").then(|| {
+            matched.opt_str("strict-exit-code").map_or(STRICT_EXIT_CODE, |code| {
+                code.parse().unwrap_or_else(|e| {
+                    eprintln!("invalid strict exit code {code:?}: {e}");
+                    exit(2)
+                })
+            })
+        }),
+        max_output_size: matched.opt_str("max-output-size")
+            .or_else(|| env::var("JQ_BRIDGE_MAX_OUTPUT_SIZE").ok().filter(|size| !size.is_empty()))
+            .map(|size| {
+                size.parse().unwrap_or_else(|e| {
+                    eprintln!("invalid max output size {size:?}: {e}");
+                    exit(2)
+                })
+            }),
+        log: matched.opt_str("log").map(|path| {
+            let level = matched.opt_str("log-level").unwrap_or_else(|| "full".into());
+            let level = audit::Level::by_name(&level).unwrap_or_else(|e| {
+                eprintln!("--log-level: {e}");
+                exit(2)
+            });
+            AuditLog::open(&path, level).unwrap_or_else(|e| {
+                eprintln!("cannot open log {path:?}: {e}");
+                exit(2)
+            })
+        }),
+    };
+    jq_bridge::diagnose::set_raw_errors(matched.opt_present("raw-parse-errors"));
+    let mut ctx = Context::default();
+    ctx.set_seed(seed);
+    ctx.set_legacy_errors(matched.opt_present("legacy-errors"));
+    ctx.set_policy(policy);
+    let assume_yes = env::var_os("JQ_BRIDGE_ASSUME_YES").is_some_and(|v| !v.is_empty() && v != "0");
+    ctx.set_dry_run(matched.opt_present("dry-run"));
+    ctx.set_binary_safe(matched.opt_present("binary-safe"));
+    ctx.set_assume_yes(matched.opt_present("yes") || assume_yes);
+    if let Some(root) = matched.opt_str("root") {
+        ctx.set_root(&root).unwrap_or_else(|e| {
+            eprintln!("invalid root: {e}");
+            exit(2)
+        });
+    }
+    if matched.opt_present("buffer-stdout") {
+        _ = ctx.output().set_buffering(true, None);
+    }
+    let (output, error_output) = (ctx.output(), ctx.error_output());
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        output.try_flush();
+        error_output.try_flush();
+        hook(info);
+    }));
+    if let Some(path) = matched.opt_str("listen") {
+        if !matched.free.is_empty() {
+            eprintln!("--listen does not run jq, unexpected argument {:?}", matched.free[0]);
+            exit(2)
+        }
+        if opts.strict.is_some() {
+            eprintln!("--strict ends a jq session, not a --listen server");
+            exit(2)
+        }
+        let shared = matched.opt_present("shared-context");
+        opts.exit_closes = true;
+        listen(&path, matched.opt_present("force"), shared, seed, ctx, opts)
+    }
+    let (program, free) = jq_command(matched.opt_str("jq"), &matched.free, find_jq);
+    let mut args = input_args(&matched);
+    args.extend_from_slice(free);
+    run_jq(&program, &args, &jq_dir, &channel, ctx, opts)
+}
+
+fn options() -> Options {
+    getopts_options! {
+        -v, --version           "show version";
+        -h, --help*             "show help message";
+        -s, --seed=SEED         "seed the random commands for reproducible runs";
+        --jq=PROG               "run PROG with all the arguments, default $JQ_BRIDGE_JQ or jq, gojq, jaq";
+        --legacy-errors         "answer errors as plain message strings";
+        --raw-parse-errors      "answer the plain serde message of an invalid command";
+        --allow*=NAME           "only run the allowed commands or groups";
+        --deny*=NAME            "do not run the denied commands or groups";
+        --root=DIR              "confine the path arguments to DIR";
+        --channel-fd=FD         "commands on the inherited fd FD and answers on FD+1, not stdio";
+        --channel-fifo=DIR      "commands and answers on FIFOs created in DIR, not stdio";
+        --listen=PATH           "serve the connections of a unix socket instead of running jq";
+        --force                 "replace an existing file at the --listen path";
+        --shared-context        "connections of --listen share the processes, timers and RNG";
+        --framing=MODE          "framing of the answers, line (default) or length";
+        --command-framing=MODE  "framing of the commands, line (default) or length";
+        --default-timeout=MS    "run each command with_timeout MS when possible";
+        --max-output-size=BYTES "answer response_too_large for a larger answer, also by JQ_BRIDGE_MAX_OUTPUT_SIZE";
+        --verbose-responses     "add the command name as \"cmd\" to each answer";
+        --marker=PREFIX         "only lines starting with PREFIX are commands, others go to stdout";
+        --buffer-stdout         "keep the prints until a flush command or the end of jq";
+        --strict                "end the session on the first err answer, exiting with 249";
+        --strict-exit-code=CODE "exit code of --strict instead of 249";
+        --dry-run               "do not run the mutating commands, answer with a stand-in result";
+        --binary-safe           "answer file contents, paths, env values and child output as {text} or {base64}";
+        -y, --yes               "answer confirm with yes, also by JQ_BRIDGE_ASSUME_YES";
+        -i, --input*=FILE       "pass the JSON of FILE (- for stdin) as $bridge_input<N> and in $bridge_inputs";
+        --raw-input-file*=FILE  "like --input, the content of FILE as a string";
+        --log=FILE              "append each command and its answer to FILE as JSON lines";
+        --log-level=LEVEL       "full (default), commands (no long results) or errors";
+        --emit-jq-module?=PATH  "write the jq library of the commands to stdout, or to PATH as --emit-jq-module=PATH, and exit";
+        .parsing_style(getopts_macro::getopts::ParsingStyle::StopAtFirstFree)
+    }
+}
+
+/// Parse the options of the bridge up to the first unrecognized one, which begins the free
+/// arguments, e.g. `-n` of `jq-bridge -n -f prog.jq` is given to the jq
+fn parse_args(options: &Options, args: &[String]) -> Result<Matches, Fail> {
+    let Err(Fail::UnrecognizedOption(_)) = options.parse(args) else {
+        return options.parse(args);
     };
-    run_jq(program, &matched.free[1..])
+    // the shortest failing prefix ends at the unrecognized option, not at a value of an option
+    let end = (1..=args.len())
+        .find(|&end| matches!(options.parse(&args[..end]), Err(Fail::UnrecognizedOption(_))))
+        .map_or(args.len(), |end| end - 1);
+    let mut matched = options.parse(&args[..end])?;
+    matched.free.extend_from_slice(&args[end..]);
+    Ok(matched)
 }
 
-fn run_jq(program: &str, args: &[String]) -> ! {
-    let ctx = &mut Context::default();
+/// The jq and its arguments, `--jq`, otherwise the first free argument unless it is an option
+fn jq_command(jq: Option<String>, free: &[String], find: impl FnOnce() -> String) -> (String, &[String]) {
+    match (jq, free.split_first()) {
+        (Some(program), _) => (program, free),
+        (None, Some((program, rest))) if !program.starts_with('-') => (program.clone(), rest),
+        (None, _) => (find(), free),
+    }
+}
 
-    let mut jq_coproc = process::Command::new(program)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .args(args)
-        .spawn()
-        .expect("cannot start jq coproc");
+/// `$JQ_BRIDGE_JQ` or the first jq compatible found in `PATH`
+fn find_jq() -> String {
+    let found = discover_jq(env::var_os("JQ_BRIDGE_JQ"), &env::var_os("PATH").unwrap_or_default());
+    found.unwrap_or_else(|| {
+        eprintln!("no jq-compatible binary found; set JQ_BRIDGE_JQ");
+        exit(2)
+    })
+}
 
-    let from = BufReader::new(jq_coproc.stdout.take().unwrap());
-    let mut to = BufWriter::new(jq_coproc.stdin.take().unwrap());
+fn discover_jq(env_jq: Option<OsString>, paths: &OsStr) -> Option<String> {
+    if let Some(program) = env_jq.filter(|program| !program.is_empty()) {
+        return Some(program.to_string_lossy().into_owned());
+    }
+    ["jq", "gojq", "jaq"].into_iter()
+        .flat_map(|name| exe_suffixes().into_iter().map(move |suffix| format!("{name}{suffix}")))
+        .find(|file| env::split_paths(paths).any(|dir| is_executable(&dir.join(file))))
+}
+
+/// Suffixes of the executable files, those of `%PATHEXT%` on Windows
+fn exe_suffixes() -> Vec<String> {
+    if cfg!(windows) {
+        let pathext = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+        pathext.split(';').filter(|ext| !ext.is_empty()).map(str::to_lowercase).collect()
+    } else {
+        vec![env::consts::EXE_SUFFIX.into()]
+    }
+}
 
-    for line in from.lines() {
-        let buf = line.unwrap();
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
 
-        let cmd: Command = serde_json::from_str(&buf)
-            .expect("invalid command");
-        let value = cmd.run(ctx)
-            .map(|value| json!({"ok": value}))
-            .unwrap_or_else(|err| json!({"err": err.to_string()}));
-        serde_json::to_writer(&mut to, &value).unwrap();
-        writeln!(to).unwrap();
-        to.flush().unwrap();
+    let Ok(meta) = fs::metadata(path) else { return false };
+    #[cfg(unix)]
+    return meta.is_file() && meta.permissions().mode() & 0o111 != 0;
+    #[cfg(not(unix))]
+    return meta.is_file();
+}
+
+/// `--argjson` of each `--input` and `--raw-input-file` in order, read before starting jq
+fn input_args(matched: &Matches) -> Vec<String> {
+    let mut files = matched.opt_strs_pos("input")
+        .into_iter()
+        .map(|(pos, path)| (pos, path, false))
+        .chain(matched.opt_strs_pos("raw-input-file").into_iter().map(|(pos, path)| (pos, path, true)))
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return vec![];
+    }
+    files.sort_by_key(|&(pos, ..)| pos);
+
+    let mut stdin = None;
+    let mut read = |path: &str| -> std::io::Result<Vec<u8>> {
+        if path != "-" {
+            return fs::read(path);
+        }
+        // read once, `-` may be given twice
+        if stdin.is_none() {
+            let mut buf = vec![];
+            std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut buf)?;
+            stdin = Some(buf);
+        }
+        Ok(stdin.clone().unwrap())
+    };
+    let mut args = vec![];
+    let mut inputs = vec![];
+    for (i, (_, path, raw)) in files.into_iter().enumerate() {
+        let content = read(&path).unwrap_or_else(|e| {
+            eprintln!("cannot read input {path:?}: {e}");
+            exit(2)
+        });
+        let value = if raw {
+            String::from_utf8(content).map(Value::String).unwrap_or_else(|e| {
+                eprintln!("input {path:?} is not UTF-8: {e}");
+                exit(2)
+            })
+        } else {
+            serde_json::from_slice(&content).unwrap_or_else(|e| {
+                eprintln!("invalid JSON in input {path:?}: {e}");
+                exit(2)
+            })
+        };
+        args.extend(["--argjson".into(), format!("bridge_input{i}"), value.to_string()]);
+        inputs.push(value);
+    }
+    args.extend(["--argjson".into(), "bridge_inputs".into(), Value::from(inputs).to_string()]);
+    args
+}
+
+fn run_jq(
+    program: &str,
+    args: &[String],
+    jq_dir: &Path,
+    channel: &Channel,
+    ctx: Context,
+    opts: BridgeOptions,
+) -> ! {
+    let mut command = process::Command::new(program);
+    command.args(args).current_dir(jq_dir);
+    let (mut jq_coproc, transport) = channel.spawn(command).unwrap_or_else(|e| {
+        eprintln!("cannot start jq coproc: {e}");
+        exit(2)
+    });
+
+    let ctx = Arc::new(Mutex::new(ctx));
+    let hook_ctx = ctx.clone();
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // the panicking thread may hold the context
+        if let Ok(mut ctx) = hook_ctx.try_lock() {
+            ctx.terminate_children(SHUTDOWN_GRACE);
+        }
+        hook(info);
+    }));
+
+    if let Some(transport) = transport {
+        on_signal(jq_coproc.id(), ctx.clone(), transport.fifos.clone());
+        // a read or write error means that jq is done, std already ignores SIGPIPE
+        _ = run_bridge_shared(BufReader::new(transport.reader), transport.writer, &ctx, &opts);
+        // the channel is closed, jq sees EOF
+        let exit_code = jq_bridge::lock(&ctx).exit_code();
+        if let Some(code) = exit_code {
+            wait_or_kill(&mut jq_coproc, SHUTDOWN_GRACE);
+            shutdown(&ctx);
+            for fifo in transport.fifos {
+                _ = fs::remove_file(fifo);
+            }
+            exit_unless_signaled(code)
+        }
+        shutdown(&ctx);
+        for fifo in transport.fifos {
+            _ = fs::remove_file(fifo);
+        }
+    } else {
+        shutdown(&ctx);
     }
 
     let code = jq_coproc.wait()
-        .unwrap()
-        .code()
+        .ok()
+        .and_then(|status| status.code())
         .unwrap_or(jq_bridge::NONE_EXIT_CODE);
+    exit_unless_signaled(code)
+}
+
+/// Signal being handled by [`on_signal`], which exits the process
+#[cfg(unix)]
+static SIGNALED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Exit with `code`, or let the signal thread exit after stopping the children,
+/// jq killed by the forwarded signal ends the session first
+fn exit_unless_signaled(code: i32) -> ! {
+    #[cfg(unix)]
+    if SIGNALED.load(std::sync::atomic::Ordering::Relaxed) {
+        loop {
+            thread::park();
+        }
+    }
     exit(code)
 }
+
+/// Reap jq, killed when it does not exit within `timeout`
+fn wait_or_kill(jq: &mut process::Child, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if !matches!(jq.try_wait(), Ok(None)) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    _ = jq.kill();
+    _ = jq.wait();
+}
+
+/// Time given to the children to exit after SIGTERM, before being killed
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Default exit code of a session ended by `--strict`
+const STRICT_EXIT_CODE: i32 = 249;
+
+/// Stop the children still registered and write out the prints,
+/// without the context when it stays busy
+fn shutdown(ctx: &Mutex<Context>) {
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    loop {
+        match ctx.try_lock() {
+            Ok(mut ctx) => {
+                ctx.terminate_children(SHUTDOWN_GRACE);
+                _ = ctx.flush_outputs();
+                return;
+            },
+            Err(TryLockError::Poisoned(e)) => {
+                e.into_inner().terminate_children(SHUTDOWN_GRACE);
+                return;
+            },
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            },
+            Err(TryLockError::WouldBlock) => return,
+        }
+    }
+}
+
+/// On SIGINT, SIGTERM or SIGHUP forward it to jq, shut down and exit with 128 + signal
+#[cfg(unix)]
+fn on_signal(jq: u32, ctx: Arc<Mutex<Context>>, fifos: Vec<PathBuf>) {
+    use std::{
+        io::Read,
+        os::fd::IntoRawFd,
+        sync::atomic::{AtomicI32, Ordering},
+    };
+
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    // only async-signal-safe calls here, the thread below does the work
+    extern "C" fn forward(signal: libc::c_int) {
+        let byte = signal as u8;
+        unsafe { libc::write(PIPE.load(Ordering::Relaxed), (&raw const byte).cast(), 1) };
+    }
+
+    let Ok((mut reader, writer)) = std::io::pipe() else { return };
+    PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(signal, forward as *const () as libc::sighandler_t) };
+    }
+    thread::spawn(move || {
+        let mut signal = [0];
+        if reader.read_exact(&mut signal).is_err() {
+            return;
+        }
+        let signal = signal[0].into();
+        SIGNALED.store(true, Ordering::Relaxed);
+        unsafe { libc::kill(jq as libc::pid_t, signal) };
+        shutdown(&ctx);
+        for fifo in fifos {
+            _ = fs::remove_file(fifo);
+        }
+        exit(128 + signal)
+    });
+}
+
+/// On Ctrl-C or the close of the console shut down, then exit as by default,
+/// jq of the same console gets the event too
+#[cfg(windows)]
+fn on_signal(_jq: u32, ctx: Arc<Mutex<Context>>, _fifos: Vec<PathBuf>) {
+    use std::sync::OnceLock;
+
+    use windows_sys::Win32::{Foundation::BOOL, System::Console::SetConsoleCtrlHandler};
+
+    static CTX: OnceLock<Arc<Mutex<Context>>> = OnceLock::new();
+
+    unsafe extern "system" fn handler(_event: u32) -> BOOL {
+        if let Some(ctx) = CTX.get() {
+            shutdown(ctx);
+        }
+        0
+    }
+
+    if CTX.set(ctx).is_ok() {
+        unsafe { SetConsoleCtrlHandler(Some(handler), 1) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn on_signal(_: u32, _: Arc<Mutex<Context>>, _: Vec<PathBuf>) {}
+
+#[cfg(unix)]
+fn listen(
+    path: &str,
+    force: bool,
+    shared: bool,
+    seed: Option<u64>,
+    ctx: Context,
+    opts: BridgeOptions,
+) -> ! {
+    use std::{
+        ffi::CString,
+        os::unix::net::UnixListener,
+        sync::OnceLock,
+    };
+
+    static SOCKET: OnceLock<CString> = OnceLock::new();
+
+    extern "C" fn cleanup(signal: libc::c_int) {
+        if let Some(path) = SOCKET.get() {
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    if fs::symlink_metadata(path).is_ok() {
+        if !force {
+            eprintln!("{path:?} exists, use --force to replace it");
+            exit(2)
+        }
+        if let Err(e) = fs::remove_file(path) {
+            eprintln!("cannot remove {path:?}: {e}");
+            exit(2)
+        }
+    }
+    let listener = UnixListener::bind(path).unwrap_or_else(|e| {
+        eprintln!("cannot listen on {path:?}: {e}");
+        exit(2)
+    });
+    // a relative path must still name the socket after set_current_dir
+    let socket = std::path::absolute(path).unwrap_or_else(|_| path.into());
+    if let Ok(socket) = CString::new(socket.into_os_string().into_encoded_bytes()) {
+        _ = SOCKET.set(socket);
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(signal, cleanup as *const () as libc::sighandler_t) };
+    }
+
+    let ctx = Arc::new(Mutex::new(ctx));
+    let opts = Arc::new(opts);
+    for stream in listener.incoming() {
+        let stream = match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("cannot accept connection: {e}");
+                continue;
+            },
+        };
+        let ctx = if shared {
+            ctx.clone()
+        } else {
+            let mut fresh = jq_bridge::lock(&ctx).detached();
+            fresh.set_seed(seed);
+            Arc::new(Mutex::new(fresh))
+        };
+        let opts = opts.clone();
+        thread::spawn(move || {
+            let (reader, writer) = stream;
+            _ = run_bridge_shared(BufReader::new(reader), writer, &ctx, &opts);
+        });
+    }
+    unreachable!("incoming never ends")
+}
+
+#[cfg(not(unix))]
+fn listen(_: &str, _: bool, _: bool, _: Option<u64>, _: Context, _: BridgeOptions) -> ! {
+    eprintln!("--listen is unix only");
+    exit(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.into()).collect()
+    }
+
+    #[test]
+    fn unrecognized_options_are_free() {
+        let matched = parse_args(&options(), &args(&["-n", "-f", "prog.jq"])).unwrap();
+        assert_eq!(matched.free, ["-n", "-f", "prog.jq"]);
+
+        let matched = parse_args(&options(), &args(&["--strict", "-y", "-n", "-s", "."])).unwrap();
+        assert!(matched.opt_present("strict") && matched.opt_present("yes"));
+        assert!(!matched.opt_present("seed"));
+        assert_eq!(matched.free, ["-n", "-s", "."]);
+
+        let matched = parse_args(&options(), &args(&["--root", "-n", "--foo=1"])).unwrap();
+        assert_eq!(matched.opt_str("root").as_deref(), Some("-n"));
+        assert_eq!(matched.free, ["--foo=1"]);
+
+        let matched = parse_args(&options(), &args(&["gojq", "--strict"])).unwrap();
+        assert!(!matched.opt_present("strict"));
+        assert_eq!(matched.free, ["gojq", "--strict"]);
+
+        assert!(parse_args(&options(), &args(&["--seed"])).is_err());
+    }
+
+    #[test]
+    fn jq_option_first() {
+        let free = args(&["gojq", "-n"]);
+        assert_eq!(jq_command(Some("jaq".into()), &free, || unreachable!()), ("jaq".into(), &free[..]));
+        assert_eq!(jq_command(None, &free, || unreachable!()), ("gojq".into(), &free[1..]));
+        let free = args(&["-n"]);
+        assert_eq!(jq_command(None, &free, || "jq".into()), ("jq".into(), &free[..]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn discovery() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("jq-bridge-discovery-{}", process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        let fake = |path: PathBuf, mode| {
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        let paths = env::join_paths([&first, &second]).unwrap();
+        let found = |env_jq: Option<&str>| discover_jq(env_jq.map(Into::into), &paths);
+
+        assert_eq!(found(None), None);
+        fake(second.join("jaq"), 0o755);
+        fake(first.join("jq"), 0o644);
+        assert_eq!(found(None).as_deref(), Some("jaq"));
+        fake(first.join("gojq"), 0o755);
+        assert_eq!(found(None).as_deref(), Some("gojq"));
+        fake(second.join("jq"), 0o755);
+        assert_eq!(found(None).as_deref(), Some("jq"));
+        assert_eq!(found(Some("")).as_deref(), Some("jq"));
+        assert_eq!(found(Some("/opt/jq")).as_deref(), Some("/opt/jq"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
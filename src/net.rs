@@ -0,0 +1,559 @@
+//! Name resolution and sockets
+
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{encoding, Error};
+
+/// Default limit of the received data held in memory
+pub const MAX_READ: u64 = 64 << 20;
+
+/// `{addrs, v4, v6}` deduplicated in the resolver order
+pub fn resolve(host: &str, port: u16) -> Result<Value, Error> {
+    let mut addrs: Vec<IpAddr> = vec![];
+    let resolved = (host, port).to_socket_addrs()
+        .map_err(|e| lookup_error(host, e))?;
+    for addr in resolved {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    let strings = |filter: fn(&IpAddr) -> bool| addrs.iter()
+        .filter(|addr| filter(addr))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "addrs": strings(|_| true),
+        "v4": strings(IpAddr::is_ipv4),
+        "v6": strings(IpAddr::is_ipv6),
+    }))
+}
+
+/// std hides the resolver error code on unix, ask getaddrinfo again for it
+#[cfg(unix)]
+fn lookup_error(host: &str, e: io::Error) -> Error {
+    use std::{ffi::CString, mem::zeroed, ptr::{null, null_mut}};
+
+    let Ok(name) = CString::new(host) else { return e.into() };
+    let mut hints: libc::addrinfo = unsafe { zeroed() };
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut res = null_mut();
+    let code = unsafe { libc::getaddrinfo(name.as_ptr(), null(), &hints, &mut res) };
+    if code == 0 {
+        unsafe { libc::freeaddrinfo(res) };
+    }
+    #[cfg(target_os = "linux")]
+    let no_data = code == libc::EAI_NODATA;
+    #[cfg(not(target_os = "linux"))]
+    let no_data = false;
+    if code == libc::EAI_NONAME || no_data {
+        return Error::NameNotFound(host.into());
+    }
+    e.into()
+}
+
+#[cfg(windows)]
+fn lookup_error(host: &str, e: io::Error) -> Error {
+    use windows_sys::Win32::Networking::WinSock::{WSAHOST_NOT_FOUND, WSANO_DATA};
+
+    match e.raw_os_error() {
+        Some(WSAHOST_NOT_FOUND | WSANO_DATA) => Error::NameNotFound(host.into()),
+        _ => e.into(),
+    }
+}
+
+/// PTR name of `ip`, `None` when there is no PTR record
+#[cfg(unix)]
+pub fn reverse_resolve(ip: IpAddr) -> Result<Option<String>, Error> {
+    use std::{ffi::{c_char, CStr}, mem::{size_of, zeroed}, ptr::null_mut};
+
+    let mut storage: libc::sockaddr_storage = unsafe { zeroed() };
+    let len = match ip {
+        IpAddr::V4(ip) => {
+            let addr = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in>() };
+            addr.sin_family = libc::AF_INET as _;
+            addr.sin_addr.s_addr = u32::from(ip).to_be();
+            size_of::<libc::sockaddr_in>()
+        },
+        IpAddr::V6(ip) => {
+            let addr = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in6>() };
+            addr.sin6_family = libc::AF_INET6 as _;
+            addr.sin6_addr.s6_addr = ip.octets();
+            size_of::<libc::sockaddr_in6>()
+        },
+    };
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    {
+        storage.ss_len = len as u8;
+    }
+    let mut host = [0 as c_char; 1025];
+    let code = unsafe {
+        libc::getnameinfo(
+            (&raw const storage).cast(), len as libc::socklen_t,
+            host.as_mut_ptr(), host.len() as _,
+            null_mut(), 0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    match code {
+        0 => Ok(Some(unsafe { CStr::from_ptr(host.as_ptr()) }.to_string_lossy().into_owned())),
+        libc::EAI_NONAME => Ok(None),
+        libc::EAI_SYSTEM => Err(io::Error::last_os_error().into()),
+        code => {
+            let message = unsafe { CStr::from_ptr(libc::gai_strerror(code)) };
+            Err(Error::Resolve(message.to_string_lossy().into_owned()))
+        },
+    }
+}
+
+#[cfg(windows)]
+pub fn reverse_resolve(ip: IpAddr) -> Result<Option<String>, Error> {
+    use std::mem::{size_of, zeroed};
+
+    use windows_sys::Win32::Networking::WinSock::{
+        GetNameInfoW, WSACleanup, WSAStartup, AF_INET, AF_INET6, NI_MAXHOST, NI_NAMEREQD,
+        SOCKADDR_IN, SOCKADDR_IN6, SOCKADDR_STORAGE, WSADATA, WSAHOST_NOT_FOUND, WSANO_DATA,
+    };
+
+    let mut data: WSADATA = unsafe { zeroed() };
+    let code = unsafe { WSAStartup(0x202, &mut data) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code).into());
+    }
+    let mut storage: SOCKADDR_STORAGE = unsafe { zeroed() };
+    let len = match ip {
+        IpAddr::V4(ip) => {
+            let addr = unsafe { &mut *(&raw mut storage).cast::<SOCKADDR_IN>() };
+            addr.sin_family = AF_INET;
+            addr.sin_addr.S_un.S_addr = u32::from(ip).to_be();
+            size_of::<SOCKADDR_IN>()
+        },
+        IpAddr::V6(ip) => {
+            let addr = unsafe { &mut *(&raw mut storage).cast::<SOCKADDR_IN6>() };
+            addr.sin6_family = AF_INET6;
+            addr.sin6_addr.u.Byte = ip.octets();
+            size_of::<SOCKADDR_IN6>()
+        },
+    };
+    let mut host = [0u16; NI_MAXHOST as usize];
+    let code = unsafe {
+        GetNameInfoW(
+            (&raw const storage).cast(), len as i32,
+            host.as_mut_ptr(), host.len() as u32,
+            std::ptr::null_mut(), 0,
+            NI_NAMEREQD as i32,
+        )
+    };
+    let error = io::Error::last_os_error();
+    unsafe { WSACleanup() };
+    if code == 0 {
+        let len = host.iter().position(|&c| c == 0).unwrap_or(host.len());
+        return Ok(Some(String::from_utf16_lossy(&host[..len])));
+    }
+    match error.raw_os_error() {
+        Some(WSAHOST_NOT_FOUND | WSANO_DATA) => Ok(None),
+        _ => Err(error.into()),
+    }
+}
+
+/// Bytes of at most one of `text` and the base64 `data`
+fn payload(text: &Option<String>, data: &Option<String>, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    match (text, data) {
+        (None, None) => Ok(None),
+        (Some(text), None) => Ok(Some(text.as_bytes().to_vec())),
+        (None, Some(data)) => Ok(Some(encoding::base64_decode(data)?)),
+        _ => Err(Error::InvalidArgument(format!(
+            "expected at most one of {name} and {name}_base64",
+        ))),
+    }
+}
+
+/// `{data, data_base64}`, data that is not UTF-8 is null and carried in `data_base64`
+fn data_it(buf: Vec<u8>) -> (Option<String>, Option<String>) {
+    match String::from_utf8(buf) {
+        Ok(data) => (Some(data), None),
+        Err(e) => (None, Some(encoding::base64(e.as_bytes()))),
+    }
+}
+
+/// Streams whose blocking operations can time out
+pub trait Timeouts {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Timeouts for TcpStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Timeouts for UnixStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+/// Payload and read termination of a request over a stream socket
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Exchange {
+    send: Option<String>,
+    send_base64: Option<String>,
+    read_until: Option<String>,
+    max_bytes: Option<u64>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl Exchange {
+    /// Write the payload, then read until the delimiter (included),
+    /// `max_bytes`, EOF or the timeout counted from `start`
+    ///
+    /// `{data, data_base64, terminated}`, data that is not UTF-8
+    /// is null and carried in `data_base64` instead
+    pub fn run<S>(&self, stream: &mut S, start: Instant) -> Result<Value, Error>
+    where S: Read + Write + Timeouts,
+    {
+        let deadline = self.timeout_ms.map(|ms| start + Duration::from_millis(ms));
+        // zero durations are rejected by set_read_timeout
+        let remaining = || deadline.map(|deadline| {
+            deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1))
+        });
+        let timed_out = |e: &io::Error| matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut,
+        );
+
+        let payload = payload(&self.send, &self.send_base64, "send")?;
+        if let Some(payload) = payload {
+            stream.set_timeouts(remaining())?;
+            stream.write_all(&payload)?;
+            stream.flush()?;
+        }
+
+        let max_bytes = self.max_bytes.unwrap_or(MAX_READ);
+        let delimiter = self.read_until.as_deref().map(str::as_bytes)
+            .filter(|delimiter| !delimiter.is_empty());
+        let mut buf = vec![];
+        let mut chunk = vec![0; 64 * 1024];
+        let terminated = loop {
+            if buf.len() as u64 >= max_bytes {
+                break "max_bytes";
+            }
+            stream.set_timeouts(remaining())?;
+            let len = chunk.len().min((max_bytes - buf.len() as u64) as usize);
+            let n = match stream.read(&mut chunk[..len]) {
+                Ok(0) => break "eof",
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if timed_out(&e) => break "timeout",
+                Err(e) => return Err(e.into()),
+            };
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(delimiter) = delimiter {
+                // the delimiter may straddle the previous chunk
+                let from = (buf.len() - n).saturating_sub(delimiter.len() - 1);
+                if let Some(at) = buf[from..].windows(delimiter.len())
+                    .position(|window| window == delimiter)
+                {
+                    buf.truncate(from + at + delimiter.len());
+                    break "delimiter";
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break "timeout";
+            }
+        };
+
+        let (data, data_base64) = data_it(buf);
+        Ok(json!({
+            "data": data,
+            "data_base64": data_base64,
+            "terminated": terminated,
+        }))
+    }
+}
+
+/// Connect to the first reachable address of `host`
+pub fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream, Error> {
+    let addrs = (host, port).to_socket_addrs()
+        .map_err(|e| lookup_error(host, e))?;
+    let mut last = None;
+    for addr in addrs {
+        let result = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(source) => last = Some(Error::Connect { addr: addr.to_string(), source }),
+        }
+    }
+    Err(last.unwrap_or_else(|| Error::NameNotFound(host.into())))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TcpRequest {
+    host: String,
+    port: u16,
+    #[serde(flatten)]
+    exchange: Exchange,
+}
+
+impl TcpRequest {
+    pub fn run(&self) -> Result<Value, Error> {
+        let start = Instant::now();
+        let timeout = self.exchange.timeout_ms.map(Duration::from_millis);
+        let mut stream = tcp_connect(&self.host, self.port, timeout)?;
+        self.exchange.run(&mut stream, start)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UdpSend {
+    host: String,
+    port: u16,
+    data: Option<String>,
+    data_base64: Option<String>,
+    wait_reply_ms: Option<u64>,
+    max_reply_bytes: Option<u64>,
+}
+
+impl UdpSend {
+    /// Send one datagram, the sent length, or `{sent, reply}` with `wait_reply_ms`,
+    /// `reply` is `{data, data_base64, peer, truncated}` or null without a reply in time
+    pub fn run(&self) -> Result<Value, Error> {
+        let payload = payload(&self.data, &self.data_base64, "data")?.unwrap_or_default();
+        let addr = (&*self.host, self.port).to_socket_addrs()
+            .map_err(|e| lookup_error(&self.host, e))?
+            .next()
+            .ok_or_else(|| Error::NameNotFound(self.host.clone()))?;
+        let bind: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((bind, 0))?;
+        if addr.is_ipv4() {
+            // a subnet broadcast address is not recognizable from here,
+            // and the option is harmless for unicast
+            socket.set_broadcast(true)?;
+        }
+        let sent = socket.send_to(&payload, addr)?;
+        let Some(wait) = self.wait_reply_ms else { return Ok(sent.into()) };
+
+        socket.set_read_timeout(Some(Duration::from_millis(wait.max(1))))?;
+        let max = self.max_reply_bytes.unwrap_or(u16::MAX.into()).min(u16::MAX.into()) as usize;
+        // one more byte to tell a truncated datagram
+        let mut buf = vec![0; max + 1];
+        let reply = loop {
+            match socket.recv_from(&mut buf) {
+                Ok((n, peer)) => {
+                    buf.truncate(n.min(max));
+                    let (data, data_base64) = data_it(buf);
+                    break json!({
+                        "data": data,
+                        "data_base64": data_base64,
+                        "peer": peer.to_string(),
+                        "truncated": n > max,
+                    });
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    break Value::Null;
+                },
+                Err(e) => return Err(e.into()),
+            }
+        };
+        Ok(json!({ "sent": sent, "reply": reply }))
+    }
+}
+
+/// Connect to the unix socket at `path`, a missing socket, a permission
+/// error and a socket without listener are reported with the path
+#[cfg(unix)]
+pub fn unix_connect(path: &str) -> Result<UnixStream, Error> {
+    UnixStream::connect(path)
+        .map_err(|source| Error::PathIo { path: path.into(), source })
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnixRequest {
+    path: String,
+    #[serde(flatten)]
+    exchange: Exchange,
+}
+
+impl UnixRequest {
+    #[cfg(unix)]
+    pub fn run(&self) -> Result<Value, Error> {
+        let start = Instant::now();
+        let mut stream = unix_connect(&self.path)?;
+        self.exchange.run(&mut stream, start)
+    }
+
+    #[cfg(not(unix))]
+    pub fn run(&self) -> Result<Value, Error> {
+        Err(Error::Unsupported("unix_request"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_names() {
+        let localhost = resolve("localhost", 0).unwrap();
+        let addrs = localhost["addrs"].as_array().unwrap();
+        assert!(!addrs.is_empty());
+        for addr in addrs {
+            let ip: IpAddr = addr.as_str().unwrap().parse().unwrap();
+            assert!(ip.is_loopback(), "{ip}");
+        }
+
+        // the reserved TLD never resolves
+        let invalid = resolve("no-such-host.invalid", 80);
+        assert!(invalid.is_err(), "{invalid:?}");
+
+        assert_eq!(resolve("192.0.2.1", 53).unwrap(), json!({
+            "addrs": ["192.0.2.1"],
+            "v4": ["192.0.2.1"],
+            "v6": [],
+        }));
+        assert_eq!(resolve("::1", 0).unwrap()["v6"], json!(["::1"]));
+    }
+
+    /// Port of a listener handing its first connection to `handler`
+    fn serve_once(handler: impl FnOnce(TcpStream) + Send + 'static) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || handler(listener.accept().unwrap().0));
+        port
+    }
+
+    /// Write `chunks` with a pause between them, then wait for the client to close
+    fn writes(chunks: &'static [&'static str]) -> u16 {
+        serve_once(move |mut stream| {
+            for chunk in chunks {
+                stream.write_all(chunk.as_bytes()).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            let _ = io::copy(&mut stream, &mut io::sink());
+        })
+    }
+
+    fn tcp_request(port: u16, exchange: Value) -> Value {
+        let mut request = json!({"host": "127.0.0.1", "port": port});
+        request.as_object_mut().unwrap().extend(exchange.as_object().unwrap().clone());
+        serde_json::from_value::<TcpRequest>(request).unwrap().run().unwrap()
+    }
+
+    #[test]
+    fn exchange_termination() {
+        let delimiter = tcp_request(writes(&["one\ntwo\n"]), json!({"read_until": "\n"}));
+        assert_eq!(delimiter, json!({"data": "one\n", "data_base64": null, "terminated": "delimiter"}));
+        let straddled = tcp_request(writes(&["ab\r", "\ncd"]), json!({"read_until": "\r\n"}));
+        assert_eq!((&straddled["data"], &straddled["terminated"]), (&json!("ab\r\n"), &json!("delimiter")));
+
+        let max_bytes = tcp_request(writes(&["abc", "def"]), json!({"max_bytes": 4}));
+        assert_eq!((&max_bytes["data"], &max_bytes["terminated"]), (&json!("abcd"), &json!("max_bytes")));
+
+        let echo = serve_once(|mut stream| {
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+        let eof = tcp_request(echo, json!({"send_base64": "cGluZw=="}));
+        assert_eq!((&eof["data"], &eof["terminated"]), (&json!("ping"), &json!("eof")));
+
+        let start = Instant::now();
+        let timeout = tcp_request(writes(&["partial"]), json!({"read_until": "\n", "timeout_ms": 300}));
+        assert_eq!((&timeout["data"], &timeout["terminated"]), (&json!("partial"), &json!("timeout")));
+        let elapsed = start.elapsed();
+        assert!((Duration::from_millis(250)..Duration::from_millis(1500)).contains(&elapsed), "{elapsed:?}");
+
+        let binary = tcp_request(writes(&["\u{ff}"]), json!({"max_bytes": 1}));
+        assert_eq!((&binary["data"], &binary["data_base64"]), (&Value::Null, &json!("ww==")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_request() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("jq-bridge-echo-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            use std::io::BufRead;
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = vec![];
+            io::BufReader::new(&stream).read_until(b'\n', &mut line).unwrap();
+            stream.write_all(&line).unwrap();
+        });
+        let request = json!({"path": path, "send": "hello\n", "read_until": "\n", "timeout_ms": 5000});
+        let echo = serde_json::from_value::<UnixRequest>(request).unwrap().run().unwrap();
+        assert_eq!(echo, json!({"data": "hello\n", "data_base64": null, "terminated": "delimiter"}));
+
+        let missing = json!({"path": path.with_extension("missing")});
+        let missing = serde_json::from_value::<UnixRequest>(missing).unwrap().run();
+        assert!(matches!(missing, Err(Error::PathIo { path, .. }) if path.ends_with(".missing")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn udp_send(send: Value) -> Result<Value, Error> {
+        serde_json::from_value::<UdpSend>(send)?.run()
+    }
+
+    #[test]
+    fn udp_echo() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], peer).unwrap();
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&[&buf[..n], b" and more"].concat(), peer).unwrap();
+        });
+
+        let echo = udp_send(json!({"host": "127.0.0.1", "port": port, "data": "ping", "wait_reply_ms": 5000})).unwrap();
+        assert_eq!(echo["sent"], 4);
+        assert_eq!(echo["reply"]["data"], "ping");
+        assert_eq!(echo["reply"]["peer"], format!("127.0.0.1:{port}"));
+        assert_eq!(echo["reply"]["truncated"], false);
+        let truncated = udp_send(json!({
+            "host": "127.0.0.1",
+            "port": port,
+            "data_base64": "/w==",
+            "wait_reply_ms": 5000,
+            "max_reply_bytes": 3,
+        })).unwrap();
+        assert_eq!((&truncated["reply"]["data_base64"], &truncated["reply"]["truncated"]), (&json!("/yBh"), &json!(true)));
+    }
+
+    #[test]
+    fn udp_fire_and_forget() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        assert_eq!(udp_send(json!({"host": "127.0.0.1", "port": port, "data": "hello"})).unwrap(), 5);
+        let mut buf = [0; 16];
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (n, _) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        let silent = udp_send(json!({"host": "127.0.0.1", "port": port, "wait_reply_ms": 50})).unwrap();
+        assert_eq!(silent, json!({"sent": 0, "reply": null}));
+        assert!(udp_send(json!({"host": "127.0.0.1", "port": port, "data": "a", "data_base64": "YQ=="})).is_err());
+    }
+}
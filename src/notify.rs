@@ -0,0 +1,71 @@
+//! Desktop notifications
+
+use notify_rust::{Notification, Timeout, Urgency};
+
+use crate::Error;
+
+fn urgency(name: &str) -> Result<Urgency, Error> {
+    match name {
+        "low" => Ok(Urgency::Low),
+        "normal" => Ok(Urgency::Normal),
+        "critical" => Ok(Urgency::Critical),
+        _ => Err(Error::InvalidArgument(format!(
+            "unknown urgency {name:?}, expected low, normal or critical",
+        ))),
+    }
+}
+
+/// Show a notification, the urgency is ignored on macOS
+pub fn send(
+    summary: &str,
+    body: Option<&str>,
+    urgency_name: Option<&str>,
+    timeout_ms: Option<u64>,
+) -> Result<(), Error> {
+    let urgency = urgency_name.map(urgency).transpose()?;
+    let mut notification = Notification::new();
+    notification.appname(env!("CARGO_PKG_NAME")).summary(summary);
+    if let Some(body) = body {
+        notification.body(body);
+    }
+    if let Some(timeout_ms) = timeout_ms {
+        let timeout_ms = u32::try_from(timeout_ms).unwrap_or(u32::MAX);
+        notification.timeout(Timeout::Milliseconds(timeout_ms));
+    }
+    match urgency {
+        #[cfg(not(target_os = "macos"))]
+        Some(urgency) => _ = notification.urgency(urgency),
+        _ => (),
+    }
+    notification.show().map_err(|e| Error::NotifyUnavailable(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urgencies() {
+        assert!(matches!(urgency("low"), Ok(Urgency::Low)));
+        assert!(matches!(urgency("normal"), Ok(Urgency::Normal)));
+        assert!(matches!(urgency("critical"), Ok(Urgency::Critical)));
+        for name in ["", "Low", "urgent"] {
+            let Err(Error::InvalidArgument(message)) = urgency(name) else { panic!("{name:?}") };
+            assert!(message.contains("expected low, normal or critical"), "{message}");
+        }
+    }
+
+    #[test]
+    fn checked_before_sending() {
+        // refused without a notification server being asked
+        let err = send("summary", None, Some("loud"), None).unwrap_err();
+        assert_eq!(err.kind(), "invalid_argument");
+    }
+
+    #[test]
+    #[ignore = "shows a notification, needs a desktop session"]
+    fn shown() {
+        send("jq-bridge", Some("test notification"), Some("low"), Some(u64::MAX)).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Sinks of the print commands
+
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    sync::{Arc, Mutex, TryLockError},
+};
+
+use serde_json::Value;
+
+use crate::{lock, Error};
+
+/// Capacity of a buffered output without an explicit one
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+pub struct Sink {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    buffered: bool,
+    /// Bytes printed, reported by `stats`
+    written: u64,
+}
+
+impl Sink {
+    /// End of a print, written out unless buffered
+    pub fn print_done(&mut self) -> io::Result<()> {
+        if self.buffered { Ok(()) } else { self.writer.flush() }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Sink of the print commands, the stdout or stderr of the bridge by default,
+/// shared by the clones
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<Sink>>);
+
+impl Output {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Output(Arc::new(Mutex::new(Sink {
+            writer: BufWriter::with_capacity(DEFAULT_CAPACITY, writer),
+            buffered: false,
+            written: 0,
+        })))
+    }
+
+    pub fn lock(&self) -> std::sync::MutexGuard<'_, Sink> {
+        lock(&self.0)
+    }
+
+    /// Keep the prints until `capacity` bytes or a flush,
+    /// disabling writes out what is kept
+    pub fn set_buffering(&self, enabled: bool, capacity: Option<usize>) -> io::Result<()> {
+        let mut sink = self.lock();
+        sink.writer.flush()?;
+        if let Some(capacity) = capacity
+            && capacity != sink.writer.capacity()
+        {
+            let writer = std::mem::replace(&mut sink.writer, BufWriter::new(Box::new(io::sink())));
+            let writer = writer.into_inner().map_err(|e| e.into_error())?;
+            sink.writer = BufWriter::with_capacity(capacity, writer);
+        }
+        sink.buffered = enabled;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.lock().flush()
+    }
+
+    /// Bytes printed since the creation or the last reset
+    pub fn written(&self) -> u64 {
+        self.lock().written
+    }
+
+    pub fn reset_written(&self) {
+        self.lock().written = 0;
+    }
+
+    /// Flush unless in use, e.g. from a panic hook
+    pub fn try_flush(&self) {
+        match self.0.try_lock() {
+            Ok(mut sink) => _ = sink.flush(),
+            Err(TryLockError::Poisoned(e)) => _ = e.into_inner().flush(),
+            Err(TryLockError::WouldBlock) => (),
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Style {
+    /// Strings as is, other values as JSON
+    Raw,
+    Json,
+    Pretty,
+}
+
+/// Write `value`, flushed unless buffered so that the outputs are ordered on a terminal
+pub(crate) fn print_it(
+    output: &Output,
+    value: &Value,
+    style: Style,
+    newline: bool,
+) -> Result<Value, Error> {
+    let mut sink = output.lock();
+    match (style, value) {
+        (Style::Raw, Value::String(s)) => sink.write_all(s.as_bytes())?,
+        (Style::Raw | Style::Json, _) => serde_json::to_writer(&mut *sink, value)?,
+        (Style::Pretty, _) => serde_json::to_writer_pretty(&mut *sink, value)?,
+    }
+    if newline {
+        sink.write_all(b"\n")?;
+    }
+    sink.print_done()?;
+    Ok(Value::Null)
+}
+
+/// Writer keeping the bytes for a test to read, shared by the clones
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Captured(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8(lock(&self.0).clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.0).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Command, Context};
+
+    /// Context printing to the returned stdout and stderr
+    fn captured() -> (Context, Captured, Captured) {
+        let (stdout, stderr) = (Captured::default(), Captured::default());
+        let mut ctx = Context::default();
+        ctx.set_output(Output::new(stdout.clone()));
+        ctx.set_error_output(Output::new(stderr.clone()));
+        (ctx, stdout, stderr)
+    }
+
+    fn run(ctx: &mut Context, cmd: serde_json::Value) {
+        let value = Command::parse(&cmd.to_string()).and_then(|cmd| cmd.run(ctx)).unwrap();
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn print_styles() {
+        let value = json!({"a": [1, "x"]});
+        let printed = |name: &str, value: &Value| {
+            let (mut ctx, stdout, stderr) = captured();
+            run(&mut ctx, json!({name: value}));
+            assert_eq!(stderr.text(), "");
+            stdout.text()
+        };
+        assert_eq!(printed("print", &json!("raw \"text\"")), "raw \"text\"");
+        assert_eq!(printed("print", &value), r#"{"a":[1,"x"]}"#);
+        assert_eq!(printed("println", &json!("line")), "line\n");
+        assert_eq!(printed("println", &json!(null)), "null\n");
+        assert_eq!(printed("pretty", &value), "{\n  \"a\": [\n    1,\n    \"x\"\n  ]\n}\n");
+        assert_eq!(printed("pretty", &json!("quoted")), "\"quoted\"\n");
+        assert_eq!(printed("pretty_pipe", &value), "{\"a\":[1,\"x\"]}\n");
+        assert_eq!(printed("pretty_pipe", &json!("quoted")), "\"quoted\"\n");
+
+        let (mut ctx, stdout, _) = captured();
+        run(&mut ctx, json!({"print": "a"}));
+        run(&mut ctx, json!({"print": "b"}));
+        run(&mut ctx, json!({"println": "c"}));
+        assert_eq!(stdout.text(), "abc\n");
+        assert_eq!(ctx.output().written(), 4);
+    }
+
+    #[test]
+    fn error_output() {
+        let (mut ctx, stdout, stderr) = captured();
+        run(&mut ctx, json!({"eprint": "e1 "}));
+        run(&mut ctx, json!({"print": "o1 "}));
+        run(&mut ctx, json!({"eprintln": "e2"}));
+        run(&mut ctx, json!({"println": "o2"}));
+        run(&mut ctx, json!({"eprint_pretty": {"k": true}}));
+        run(&mut ctx, json!({"eprint": 7}));
+        assert_eq!(stdout.text(), "o1 o2\n");
+        assert_eq!(stderr.text(), "e1 e2\n{\n  \"k\": true\n}\n7");
+        // counted apart in stats
+        assert_eq!((ctx.output().written(), ctx.error_output().written()), (6, 23));
+    }
+
+    #[test]
+    fn buffering() {
+        let (mut ctx, stdout, _) = captured();
+        run(&mut ctx, json!({"set_buffering": {"enabled": true}}));
+        run(&mut ctx, json!({"print": "kept "}));
+        run(&mut ctx, json!({"println": "until flushed"}));
+        assert_eq!(stdout.text(), "");
+        run(&mut ctx, json!("flush"));
+        assert_eq!(stdout.text(), "kept until flushed\n");
+
+        // written out when full
+        run(&mut ctx, json!({"set_buffering": {"enabled": true, "capacity": 8}}));
+        run(&mut ctx, json!({"print": "1234"}));
+        assert_eq!(stdout.text(), "kept until flushed\n");
+        run(&mut ctx, json!({"print": "56789"}));
+        assert!(stdout.text().ends_with("1234"), "{:?}", stdout.text());
+
+        // disabling writes out what is kept
+        run(&mut ctx, json!({"set_buffering": {"enabled": false}}));
+        assert_eq!(stdout.text(), "kept until flushed\n123456789");
+        run(&mut ctx, json!({"print": "!"}));
+        assert_eq!(stdout.text(), "kept until flushed\n123456789!");
+
+        let zero = Command::parse(r#"{"set_buffering": {"enabled": true, "capacity": 0}}"#)
+            .and_then(|cmd| cmd.run(&mut ctx));
+        assert!(matches!(zero, Err(Error::InvalidArgument(_))));
+    }
+}
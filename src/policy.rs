@@ -0,0 +1,169 @@
+//! Allow and deny lists of commands
+
+use serde::{de::Visitor, forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::{Command, Error};
+
+pub const GROUPS: &[&str] = &[
+    "fs-read", "fs-write", "io", "env", "proc", "sys", "time", "random", "encoding", "net", "control",
+];
+
+/// Names of all the commands, taken from the derived deserializer
+pub fn command_names() -> &'static [&'static str] {
+    #[derive(Debug)]
+    struct Variants(&'static [&'static str]);
+
+    impl std::fmt::Display for Variants {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("variants probe")
+        }
+    }
+
+    impl std::error::Error for Variants {}
+
+    impl serde::de::Error for Variants {
+        fn custom<T: std::fmt::Display>(_msg: T) -> Self {
+            Variants(&[])
+        }
+    }
+
+    struct Probe;
+
+    impl<'de> Deserializer<'de> for Probe {
+        type Error = Variants;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Variants> {
+            Err(Variants(&[]))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Variants> {
+            Err(Variants(variants))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    Command::deserialize(Probe).map_or_else(|Variants(names)| names, |_| &[])
+}
+
+/// Command filter, a command name is more specific than a group,
+/// and allow wins over deny at the same specificity
+///
+/// With any allow rule only the allowed commands run
+#[derive(Debug, Default)]
+pub struct Policy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl Policy {
+    /// Error on unknown command or group names
+    pub fn new(allow: Vec<String>, deny: Vec<String>) -> Result<Self, Error> {
+        let names = command_names();
+        for name in allow.iter().chain(&deny) {
+            if !GROUPS.contains(&&**name) && !names.contains(&&**name) {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown command or group {name:?}, groups are {}",
+                    GROUPS.join(", "),
+                )));
+            }
+        }
+        Ok(Policy { allow, deny })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn allows(&self, name: &str, groups: &[&str]) -> bool {
+        let allowed = |name: &str| self.allow.iter().any(|allow| allow == name);
+        let denied = |name: &str| self.deny.iter().any(|deny| deny == name);
+        if allowed(name) {
+            return true;
+        }
+        if denied(name) {
+            return false;
+        }
+        if groups.iter().any(|group| denied(group) && !allowed(group)) {
+            return false;
+        }
+        self.allow.is_empty() || groups.iter().all(|group| allowed(group))
+    }
+
+    /// Names of the commands that may run,
+    /// wrappers are listed as their wrapped commands are checked on their own
+    pub fn allowed_commands(&self) -> Vec<&'static str> {
+        command_names().iter().copied().filter(|name| {
+            let groups = Command::groups_of(name).unwrap_or_default();
+            groups.is_empty() || self.allows(name, groups)
+        }).collect()
+    }
+
+    /// Check the command and the commands wrapped by it
+    pub fn check(&self, cmd: &Command) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let name = cmd.name();
+        // wrappers and the protocol commands have no group,
+        // wrappers are as allowed as their commands
+        if !cmd.groups().is_empty() && !self.allows(name, cmd.groups()) {
+            return Err(Error::Denied(name.into()));
+        }
+        cmd.inner().iter().try_for_each(|cmd| self.check(cmd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "proc")]
+    #[test]
+    fn deny_proc() {
+        use serde_json::{json, Value};
+
+        let check = |policy: &Policy, line: Value| policy.check(&Command::parse(&line.to_string()).unwrap());
+        let policy = Policy::new(vec![], vec!["proc".into()]).unwrap();
+        let denied = |line: Value, name: &str| {
+            let result = check(&policy, line.clone());
+            assert!(matches!(&result, Err(Error::Denied(denied)) if denied == name), "{line}: {result:?}");
+        };
+        let system = json!({"system": ["true", []]});
+        denied(system.clone(), "system");
+        denied(json!({"popen": ["true", []]}), "popen");
+        denied(json!({"command": ["true", {}]}), "command");
+        denied(json!({"spawn": ["true", {}]}), "spawn");
+        denied(json!({"batch": [{"echo": 1}, system]}), "system");
+        denied(json!({"batch": {"cmds": [system], "stop_on_error": true}}), "system");
+        denied(json!({"retry": {"cmd": system, "attempts": 2, "delay_ms": 0}}), "system");
+        denied(json!({"spawn_task": system}), "system");
+        denied(json!({"with_timeout": {"timeout_ms": 10, "cmd": {"retry": {"cmd": system, "attempts": 1, "delay_ms": 0}}}}), "system");
+
+        check(&policy, json!({"read": "/etc/hostname"})).unwrap();
+        check(&policy, json!({"batch": [{"read": "/etc/hostname"}, "ping"]})).unwrap();
+        let allowed = policy.allowed_commands();
+        assert!(allowed.contains(&"read") && allowed.contains(&"batch"));
+        assert!(!allowed.contains(&"system") && !allowed.contains(&"spawn"));
+
+        // a command name is more specific than its group
+        let policy = Policy::new(vec!["system".into()], vec!["proc".into()]).unwrap();
+        check(&policy, json!({"system": ["true", []]})).unwrap();
+        assert!(check(&policy, json!({"spawn": ["true", {}]})).is_err());
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert!(Policy::new(vec!["nope".into()], vec![]).is_err());
+        assert!(Policy::new(vec![], vec!["read".into(), "fs-read".into()]).is_ok());
+    }
+}
@@ -0,0 +1,610 @@
+//! Platform specific process operations
+
+use crate::Error;
+
+#[cfg(unix)]
+pub fn parse_signal(name: &str) -> Result<i32, Error> {
+    if let Ok(num) = name.parse() {
+        return Ok(num);
+    }
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    Ok(match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "ABRT" => libc::SIGABRT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "WINCH" => libc::SIGWINCH,
+        _ => return Err(Error::InvalidSignal(name.into())),
+    })
+}
+
+/// The `pid_t` of `pid`, 0 and those past `i32::MAX` would be of a process group or all
+#[cfg(unix)]
+fn pid_t(pid: u32) -> Result<libc::pid_t, Error> {
+    match libc::pid_t::try_from(pid) {
+        Ok(pid_t) if pid_t > 0 => Ok(pid_t),
+        _ => Err(Error::InvalidArgument(format!("invalid pid {pid}"))),
+    }
+}
+
+/// Send `signal` to `pid`
+#[cfg(unix)]
+pub fn send_signal(pid: u32, signal: i32) -> Result<(), Error> {
+    if unsafe { libc::kill(pid_t(pid)?, signal) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// All `(pid, ppid)` pairs of the running processes
+#[cfg(target_os = "linux")]
+pub fn process_parents() -> Result<Vec<(u32, u32)>, Error> {
+    let mut parents = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let Some(pid) = entry?.file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else { continue };
+        // process may have exited during the scan
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        else { continue };
+        if let Some(ppid) = stat_field(&stat, 1).and_then(|s| s.parse().ok()) {
+            parents.push((pid, ppid));
+        }
+    }
+    Ok(parents)
+}
+
+/// Get field of `/proc/<pid>/stat` after the command name,
+/// index 0 is the state field
+#[cfg(target_os = "linux")]
+pub fn stat_field(stat: &str, index: usize) -> Option<&str> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(index)
+}
+
+/// `(pid, ppid, exe name)` of the running processes
+#[cfg(windows)]
+pub fn snapshot() -> Result<Vec<(u32, u32, String)>, Error> {
+    use std::mem::{size_of, zeroed};
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, INVALID_HANDLE_VALUE},
+        System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW,
+            PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+        },
+    };
+
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut processes = vec![];
+    let mut entry: PROCESSENTRY32W = unsafe { zeroed() };
+    entry.dwSize = size_of::<PROCESSENTRY32W>() as u32;
+    let mut ok = unsafe { Process32FirstW(snapshot, &mut entry) };
+    while ok != 0 {
+        let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+        let name = String::from_utf16_lossy(&entry.szExeFile[..len]);
+        processes.push((entry.th32ProcessID, entry.th32ParentProcessID, name));
+        ok = unsafe { Process32NextW(snapshot, &mut entry) };
+    }
+    unsafe { CloseHandle(snapshot) };
+    Ok(processes)
+}
+
+#[cfg(windows)]
+pub fn process_parents() -> Result<Vec<(u32, u32)>, Error> {
+    Ok(snapshot()?.into_iter()
+        .map(|(pid, ppid, _)| (pid, ppid))
+        .collect())
+}
+
+#[cfg(windows)]
+pub fn terminate(pid: u32) -> Result<(), Error> {
+    use windows_sys::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE},
+    };
+
+    let handle = unsafe { OpenProcess(PROCESS_TERMINATE, 0, pid) };
+    if handle.is_null() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let ok = unsafe { TerminateProcess(handle, 1) };
+    let err = std::io::Error::last_os_error();
+    unsafe { CloseHandle(handle) };
+    if ok == 0 {
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Descendants of `root` in children-first order, `root` is not included
+#[cfg(any(target_os = "linux", windows))]
+pub fn descendants(root: u32) -> Result<Vec<u32>, Error> {
+    fn walk(pid: u32, parents: &[(u32, u32)], out: &mut Vec<u32>) {
+        for &(child, ppid) in parents {
+            // pid 0 is its own parent on windows
+            if ppid == pid && child != pid && !out.contains(&child) {
+                walk(child, parents, out);
+                out.push(child);
+            }
+        }
+    }
+
+    let parents = process_parents()?;
+    let mut out = vec![];
+    walk(root, &parents, &mut out);
+    Ok(out)
+}
+
+/// Without a portable process table only the process group can be found,
+/// it is signaled together with `root` when `root` leads it
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn descendants(_root: u32) -> Result<Vec<u32>, Error> {
+    Ok(vec![])
+}
+
+#[cfg(target_os = "linux")]
+fn signal_one(_root: u32, pid: u32, signal: i32) -> Result<(), Error> {
+    send_signal(pid, signal)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn signal_one(root: u32, pid: u32, signal: i32) -> Result<(), Error> {
+    let pgid = pid_t(pid)?;
+    if pid == root && unsafe { libc::getpgid(pgid) } == pgid {
+        if unsafe { libc::killpg(pgid, signal) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        return Ok(());
+    }
+    send_signal(pid, signal)
+}
+
+#[cfg(windows)]
+fn signal_one(_root: u32, pid: u32, _signal: i32) -> Result<(), Error> {
+    terminate(pid)
+}
+
+#[derive(Debug, Default)]
+pub struct KillTree {
+    pub signaled: Vec<u32>,
+    pub errors: Vec<(u32, Error)>,
+}
+
+/// Signal `root` and its descendants, children-first,
+/// windows always terminate, only `TERM` or `KILL` may be given
+pub fn kill_tree(root: u32, signal: Option<&str>) -> Result<KillTree, Error> {
+    #[cfg(unix)]
+    let signal = signal.map_or(Ok(libc::SIGTERM), parse_signal)?;
+    #[cfg(windows)]
+    let signal = match signal.map(|name| name.strip_prefix("SIG").unwrap_or(name)) {
+        None | Some("TERM" | "KILL") => 0,
+        Some(_) => return Err(Error::Unsupported("signals")),
+    };
+
+    let mut pids = descendants(root)?;
+    pids.push(root);
+
+    let mut result = KillTree::default();
+    for pid in pids {
+        match signal_one(root, pid, signal) {
+            Ok(()) => result.signaled.push(pid),
+            Err(e) => result.errors.push((pid, e)),
+        }
+    }
+    Ok(result)
+}
+
+/// Process exists, the process without permission is also alive
+#[cfg(unix)]
+pub fn pid_alive(pid: u32) -> Result<bool, Error> {
+    let res = unsafe { libc::kill(pid_t(pid)?, 0) };
+    Ok(res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(windows)]
+pub fn pid_alive(pid: u32) -> Result<bool, Error> {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, STILL_ACTIVE},
+        System::Threading::{
+            GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+    };
+
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        return Ok(std::io::Error::last_os_error().kind()
+            == std::io::ErrorKind::PermissionDenied);
+    }
+    let mut code = 0;
+    let ok = unsafe { GetExitCodeProcess(handle, &mut code) };
+    unsafe { CloseHandle(handle) };
+    Ok(ok != 0 && code == STILL_ACTIVE as u32)
+}
+
+/// Process start time in seconds since the unix epoch
+#[cfg(target_os = "linux")]
+pub fn pid_start_time(pid: u32) -> Result<f64, Error> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    let invalid = || Error::InvalidString(stat.clone());
+    let ticks: u64 = stat_field(&stat, 19)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(invalid)?;
+    let boot_time: u64 = std::fs::read_to_string("/proc/stat")?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(invalid)?;
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    Ok(boot_time as f64 + ticks as f64 / hz as f64)
+}
+
+#[cfg(target_os = "macos")]
+fn bsd_info(pid: u32) -> Result<libc::proc_bsdinfo, Error> {
+    use std::mem::{size_of, zeroed};
+
+    let mut info: libc::proc_bsdinfo = unsafe { zeroed() };
+    let size = size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let res = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            (&raw mut info).cast(),
+            size,
+        )
+    };
+    if res != size {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(info)
+}
+
+#[cfg(target_os = "macos")]
+pub fn pid_start_time(pid: u32) -> Result<f64, Error> {
+    let info = bsd_info(pid)?;
+    Ok(info.pbi_start_tvsec as f64 + info.pbi_start_tvusec as f64 / 1e6)
+}
+
+#[cfg(windows)]
+pub fn pid_start_time(pid: u32) -> Result<f64, Error> {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, FILETIME},
+        System::Threading::{
+            GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+    };
+    const EPOCH_DIFF_100NS: u64 = 116_444_736_000_000_000;
+
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let zero = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+    let [mut created, mut exited, mut kernel, mut user] = [zero; 4];
+    let ok = unsafe {
+        GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user)
+    };
+    let err = std::io::Error::last_os_error();
+    unsafe { CloseHandle(handle) };
+    if ok == 0 {
+        return Err(err.into());
+    }
+    let created = (created.dwHighDateTime as u64) << 32 | created.dwLowDateTime as u64;
+    Ok(created.saturating_sub(EPOCH_DIFF_100NS) as f64 / 1e7)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn pid_start_time(_pid: u32) -> Result<f64, Error> {
+    Err(Error::Unsupported("pid_start_time"))
+}
+
+/// Parent pid of `pid`
+#[cfg(windows)]
+pub fn parent_pid(pid: u32) -> Result<Option<u32>, Error> {
+    Ok(process_parents()?.into_iter()
+        .find(|&(child, _)| child == pid)
+        .map(|(_, ppid)| ppid))
+}
+
+/// Pid of all processes
+#[cfg(target_os = "linux")]
+pub fn list_pids() -> Result<Vec<u32>, Error> {
+    Ok(process_parents()?.into_iter().map(|(pid, _)| pid).collect())
+}
+
+#[cfg(target_os = "macos")]
+pub fn list_pids() -> Result<Vec<u32>, Error> {
+    let count = unsafe { libc::proc_listallpids(std::ptr::null_mut(), 0) };
+    if count < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // more processes may be created before the second call
+    let mut pids = vec![0 as libc::c_int; count as usize + 64];
+    let size = (pids.len() * size_of::<libc::c_int>()) as libc::c_int;
+    let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr().cast(), size) };
+    if count < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    pids.truncate(count as usize);
+    Ok(pids.into_iter().map(|pid| pid as u32).collect())
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub name: Option<String>,
+    pub cmdline: Option<String>,
+    pub cwd: Option<String>,
+    pub rss_bytes: Option<u64>,
+    pub start_time: Option<f64>,
+}
+
+#[cfg(target_os = "linux")]
+impl ProcessInfo {
+    /// Fields that cannot be read (e.g. EACCES) are null
+    pub fn of(pid: u32) -> Result<Self, Error> {
+        let read = |name| std::fs::read(format!("/proc/{pid}/{name}")).ok();
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+        Ok(ProcessInfo {
+            pid,
+            ppid: stat_field(&stat, 1).and_then(|s| s.parse().ok()),
+            name: read("comm").map(|comm| {
+                String::from_utf8_lossy(&comm).trim_end().into()
+            }),
+            cmdline: read("cmdline").map(|cmdline| {
+                let cmdline = cmdline.strip_suffix(b"\0").unwrap_or(&cmdline);
+                String::from_utf8_lossy(cmdline).replace('\0', " ")
+            }),
+            cwd: std::fs::read_link(format!("/proc/{pid}/cwd")).ok()
+                .map(|cwd| cwd.to_string_lossy().into()),
+            rss_bytes: read("statm").and_then(|statm| {
+                String::from_utf8_lossy(&statm)
+                    .split_whitespace()
+                    .nth(1)?
+                    .parse::<u64>().ok()
+            }).map(|pages| pages * page_size),
+            start_time: pid_start_time(pid).ok(),
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl ProcessInfo {
+    /// Only the current process has all fields, others are best-effort
+    pub fn of(pid: u32) -> Result<Self, Error> {
+        use std::env;
+
+        let mut info = ProcessInfo {
+            pid,
+            start_time: pid_start_time(pid).ok(),
+            ..Default::default()
+        };
+        #[cfg(target_os = "macos")]
+        if let Ok(bsd) = bsd_info(pid) {
+            let comm = bsd.pbi_comm.iter()
+                .take_while(|&&c| c != 0)
+                .map(|&c| c as u8)
+                .collect::<Vec<_>>();
+            info.ppid = Some(bsd.pbi_ppid);
+            info.name = Some(String::from_utf8_lossy(&comm).into());
+        }
+        #[cfg(windows)]
+        {
+            info.ppid = parent_pid(pid)?;
+        }
+        if pid == std::process::id() {
+            #[cfg(unix)]
+            {
+                info.ppid = Some(std::os::unix::process::parent_id());
+            }
+            info.name = env::current_exe().ok()
+                .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into()));
+            info.cmdline = Some(env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(" "));
+            info.cwd = env::current_dir().ok()
+                .map(|cwd| cwd.to_string_lossy().into());
+        }
+        Ok(info)
+    }
+}
+
+/// Info of all processes, processes exited during enumerate are skipped
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn list_processes() -> Result<Vec<ProcessInfo>, Error> {
+    Ok(list_pids()?.into_iter()
+        .filter_map(|pid| ProcessInfo::of(pid).ok())
+        .collect())
+}
+
+#[cfg(windows)]
+pub fn list_processes() -> Result<Vec<ProcessInfo>, Error> {
+    Ok(snapshot()?.into_iter()
+        .map(|(pid, ppid, name)| ProcessInfo {
+            pid,
+            ppid: Some(ppid),
+            name: Some(name),
+            start_time: pid_start_time(pid).ok(),
+            ..Default::default()
+        })
+        .collect())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn list_processes() -> Result<Vec<ProcessInfo>, Error> {
+    Err(Error::Unsupported("list_processes"))
+}
+
+/// Measure wall time and the CPU usage of reaped children
+pub struct Timing {
+    start: std::time::Instant,
+    #[cfg(unix)]
+    usage: Option<libc::rusage>,
+}
+
+#[cfg(unix)]
+fn children_usage() -> Option<libc::rusage> {
+    let mut usage = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) };
+    (res == 0).then_some(usage)
+}
+
+impl Timing {
+    pub fn start() -> Self {
+        Timing {
+            start: std::time::Instant::now(),
+            #[cfg(unix)]
+            usage: children_usage(),
+        }
+    }
+
+    /// `{wall_ms, user_ms, sys_ms, max_rss_kb}`, the unavailable are null.
+    ///
+    /// `max_rss_kb` is the largest of all reaped children
+    pub fn finish(self) -> serde_json::Value {
+        let wall_ms = self.start.elapsed().as_secs_f64() * 1e3;
+        #[allow(unused_mut)]
+        let mut result = serde_json::json!({
+            "wall_ms": wall_ms,
+            "user_ms": null,
+            "sys_ms": null,
+            "max_rss_kb": null,
+        });
+        #[cfg(unix)]
+        if let (Some(old), Some(new)) = (self.usage, children_usage()) {
+            let ms = |t: libc::timeval| t.tv_sec as f64 * 1e3 + t.tv_usec as f64 / 1e3;
+            // macos report bytes
+            let rss_kb = if cfg!(target_os = "macos") {
+                new.ru_maxrss / 1024
+            } else {
+                new.ru_maxrss
+            };
+            result["user_ms"] = (ms(new.ru_utime) - ms(old.ru_utime)).into();
+            result["sys_ms"] = (ms(new.ru_stime) - ms(old.ru_stime)).into();
+            result["max_rss_kb"] = rss_kb.into();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        process::{self, Command, Stdio},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    /// Poll `f` until it is true or 5 seconds are over
+    fn eventually(mut f: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kill_tree_leaves_no_sleeps() {
+        // a running sleep, not a zombie or a reused pid
+        let running = |pid: u32| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+            stat.contains("(sleep)") && stat_field(&stat, 0) != Some("Z")
+        };
+        let mut child = Command::new("sh").args(["-c", "sleep 60 & sleep 60 & wait"]).spawn().unwrap();
+        let mut sleeps = vec![];
+        assert!(eventually(|| {
+            sleeps = descendants(child.id()).unwrap();
+            sleeps.len() == 2 && sleeps.iter().all(|&pid| running(pid))
+        }));
+
+        let killed = kill_tree(child.id(), None).unwrap();
+        assert!(killed.errors.is_empty(), "{:?}", killed.errors);
+        assert_eq!(killed.signaled, [&sleeps[..], &[child.id()]].concat());
+        child.wait().unwrap();
+        assert!(eventually(|| !sleeps.iter().any(|&pid| running(pid))));
+    }
+
+    #[test]
+    fn alive() {
+        assert!(pid_alive(process::id()).unwrap());
+        // the test binary lists its tests and exits
+        let mut child = Command::new(env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(eventually(|| !pid_alive(pid).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_pids() {
+        // kill would probe the process group or every process
+        for pid in [0, 1 << 31, u32::MAX] {
+            assert!(matches!(pid_alive(pid), Err(Error::InvalidArgument(_))), "{pid}");
+            assert!(matches!(send_signal(pid, 0), Err(Error::InvalidArgument(_))), "{pid}");
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    #[test]
+    fn start_time() {
+        let start = pid_start_time(process::id()).unwrap();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
+        assert!(start > 0.0 && start <= now.as_secs_f64() + 1.0);
+        assert_eq!(pid_start_time(process::id()).unwrap(), start);
+    }
+
+    #[test]
+    fn self_info() {
+        let info = ProcessInfo::of(process::id()).unwrap();
+        assert_eq!(info.pid, process::id());
+        assert!(info.cmdline.is_some_and(|cmdline| !cmdline.is_empty()));
+        #[cfg(unix)]
+        assert_eq!(info.ppid, Some(std::os::unix::process::parent_id()));
+        #[cfg(windows)]
+        assert!(info.ppid.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timing() {
+        let timing = Timing::start();
+        let busy = "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done";
+        assert!(Command::new("sh").args(["-c", busy]).status().unwrap().success());
+        let timing = timing.finish();
+        // the usage of the children reaped by other tests is counted too
+        let (wall_ms, user_ms) = (timing["wall_ms"].as_f64().unwrap(), timing["user_ms"].as_f64().unwrap());
+        assert!((10.0..60_000.0).contains(&wall_ms), "{timing}");
+        assert!(user_ms >= 5.0, "{timing}");
+        assert!(timing["sys_ms"].as_f64().unwrap() >= 0.0, "{timing}");
+        assert!(timing["max_rss_kb"].as_i64().unwrap() > 0, "{timing}");
+    }
+}
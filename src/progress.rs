@@ -0,0 +1,191 @@
+//! Progress bar of the filter, drawn on stderr
+
+use std::time::{Duration, Instant};
+
+/// Least time between two redraws of a terminal bar
+pub const REDRAW: Duration = Duration::from_millis(100);
+/// Least time between two lines without a terminal
+pub const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+const WIDTH: usize = 30;
+
+#[derive(Debug)]
+pub struct Progress {
+    total: Option<u64>,
+    current: u64,
+    label: Option<String>,
+    started: Instant,
+    drawn: Option<Instant>,
+    /// Redrawn in place, otherwise a line is logged now and then
+    tty: bool,
+}
+
+impl Progress {
+    pub fn new(total: Option<u64>, label: Option<String>, tty: bool, now: Instant) -> Self {
+        Progress { total, current: 0, label, started: now, drawn: None, tty }
+    }
+
+    /// Set the count to `current`, or advance it by `delta`
+    pub fn update(&mut self, current: Option<u64>, delta: Option<u64>, label: Option<String>) {
+        if let Some(current) = current {
+            self.current = current;
+        }
+        self.current = self.current.saturating_add(delta.unwrap_or(0));
+        if label.is_some() {
+            self.label = label;
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.started)
+    }
+
+    /// What to write at `now`, `None` when drawn too recently unless `forced`
+    pub fn frame(&mut self, now: Instant, forced: bool) -> Option<String> {
+        let interval = if self.tty { REDRAW } else { LOG_INTERVAL };
+        let recent = self.drawn.is_some_and(|drawn| now.saturating_duration_since(drawn) < interval);
+        if recent && !forced {
+            return None;
+        }
+        self.drawn = Some(now);
+        let line = self.line(now);
+        Some(if self.tty { format!("\r{line}\x1b[K") } else { format!("{line}\n") })
+    }
+
+    /// The last frame, the bar is erased when `clear`
+    pub fn finish(&mut self, now: Instant, clear: bool) -> String {
+        match (self.tty, clear) {
+            (true, true) => "\r\x1b[K".into(),
+            (false, true) => String::new(),
+            (true, false) => self.frame(now, true).unwrap_or_default() + "\n",
+            (false, false) => self.frame(now, true).unwrap_or_default(),
+        }
+    }
+
+    /// `label [####    ] 42% 420/1000 35.0/s ETA 0:16`, without the bar and the ETA when
+    /// the total is unknown
+    fn line(&self, now: Instant) -> String {
+        let secs = self.elapsed(now).as_secs_f64();
+        let rate = if secs > 0.0 { self.current as f64 / secs } else { 0.0 };
+        let mut parts = vec![];
+        if let Some(label) = &self.label {
+            parts.push(label.clone());
+        }
+        match self.total {
+            Some(total) => {
+                let ratio = match total {
+                    0 => 1.0,
+                    _ => (self.current as f64 / total as f64).min(1.0),
+                };
+                let filled = (ratio * WIDTH as f64) as usize;
+                parts.push(format!("[{}{}]", "#".repeat(filled), " ".repeat(WIDTH - filled)));
+                parts.push(format!("{:.0}%", ratio * 100.0));
+                parts.push(format!("{}/{total}", self.current));
+                parts.push(format!("{rate:.1}/s"));
+                if rate > 0.0 && self.current < total {
+                    let left = (total - self.current) as f64 / rate;
+                    parts.push(format!("ETA {}", clock(left as u64)));
+                }
+            },
+            None => {
+                parts.push(self.current.to_string());
+                parts.push(format!("{rate:.1}/s"));
+            },
+        }
+        parts.join(" ")
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` past an hour
+fn clock(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 { format!("{h}:{m:02}:{s:02}") } else { format!("{m}:{s:02}") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn started(total: Option<u64>, tty: bool) -> (Progress, Instant) {
+        let start = Instant::now();
+        (Progress::new(total, Some("copy".into()), tty, start), start)
+    }
+
+    #[test]
+    fn tty_frames() {
+        let (mut bar, start) = started(Some(10), true);
+        let first = bar.frame(start, false).unwrap();
+        assert_eq!(first, format!("\rcopy [{}] 0% 0/10 0.0/s\x1b[K", " ".repeat(30)));
+
+        bar.update(Some(4), None, None);
+        assert_eq!(bar.frame(start + REDRAW / 2, false), None);
+        let later = bar.frame(start + 2 * SECOND, false).unwrap();
+        let filled = format!("[{}{}]", "#".repeat(12), " ".repeat(18));
+        assert_eq!(later, format!("\rcopy {filled} 40% 4/10 2.0/s ETA 0:03\x1b[K"));
+        assert!(bar.frame(start + 2 * SECOND, true).is_some());
+
+        bar.update(None, Some(6), Some("done".into()));
+        let last = bar.finish(start + 4 * SECOND, false);
+        assert_eq!(last, format!("\rdone [{}] 100% 10/10 2.5/s\x1b[K\n", "#".repeat(30)));
+        assert_eq!(bar.finish(start + 4 * SECOND, true), "\r\x1b[K");
+    }
+
+    #[test]
+    fn plain_frames() {
+        let (mut bar, start) = started(None, false);
+        assert_eq!(bar.frame(start, false).unwrap(), "copy 0 0.0/s\n");
+        bar.update(None, Some(3), None);
+        assert_eq!(bar.frame(start + REDRAW * 2, false), None);
+        assert_eq!(bar.frame(start + 3 * SECOND / 2, false).unwrap(), "copy 3 2.0/s\n");
+        bar.update(None, Some(u64::MAX), None);
+        assert_eq!(bar.current(), u64::MAX);
+        assert_eq!(bar.finish(start + 2 * SECOND, true), "");
+        assert!(bar.finish(start + 2 * SECOND, false).ends_with("/s\n"));
+        assert_eq!(bar.elapsed(start + 2 * SECOND), 2 * SECOND);
+    }
+
+    #[test]
+    fn edge_totals() {
+        let (mut bar, start) = started(Some(0), false);
+        assert!(bar.frame(start, false).unwrap().contains(" 100% 0/0 "));
+        let (mut bar, start) = started(Some(5), false);
+        bar.update(Some(9), None, None);
+        assert!(bar.frame(start + SECOND, false).unwrap().contains(" 100% 9/5 9.0/s\n"));
+        assert_eq!(clock(59), "0:59");
+        assert_eq!(clock(61), "1:01");
+        assert_eq!(clock(3 * 3600 + 5), "3:00:05");
+    }
+
+    #[test]
+    fn drawn_on_error_output() {
+        use serde_json::{Value, json};
+
+        use crate::{Command, Context, Output, output::Captured};
+
+        let stderr = Captured::default();
+        let mut ctx = Context::default();
+        ctx.set_error_output(Output::new(stderr.clone()));
+        let mut run = |line: Value| Command::parse(&line.to_string()).and_then(|cmd| cmd.run(&mut ctx));
+        let id = run(json!({"progress_start": {"total": 2, "label": "job"}})).unwrap();
+        run(json!({"progress_update": {"id": id, "delta": 2}})).unwrap();
+        let report = run(json!({"progress_finish": {"id": id}})).unwrap();
+        assert_eq!(report["current"], 2);
+        assert_eq!(report["total"], 2);
+        assert!(run(json!({"progress_update": {"id": id}})).is_err());
+
+        let text = stderr.text();
+        assert!(text.starts_with("\rjob [") || text.starts_with("job ["), "{text:?}");
+        assert!(text.contains(" 100% 2/2 "), "{text:?}");
+        assert!(text.ends_with('\n'), "{text:?}");
+    }
+}
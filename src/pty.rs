@@ -0,0 +1,144 @@
+//! Pseudo-terminal support
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process::{self, Child},
+    ptr::null_mut,
+};
+
+use crate::Error;
+
+pub struct Pty {
+    pub master: OwnedFd,
+    pub slave: OwnedFd,
+}
+
+impl Pty {
+    pub fn open(cols: u16, rows: u16) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let mut size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let res = unsafe {
+            libc::openpty(&mut master, &mut slave, null_mut(), null_mut(), &raw mut size)
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let pty = unsafe {
+            Pty {
+                master: OwnedFd::from_raw_fd(master),
+                slave: OwnedFd::from_raw_fd(slave),
+            }
+        };
+        for fd in [&pty.master, &pty.slave] {
+            if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(pty)
+    }
+
+    /// Spawn `cmd` with the slave as its stdio and controlling terminal,
+    /// the slave is closed in the current process
+    pub fn spawn(self, mut cmd: process::Command) -> Result<(File, Child), Error> {
+        let Pty { master, slave } = self;
+        cmd.stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1
+                || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+        Ok((master.into(), child))
+    }
+}
+
+/// Read until every slave is closed
+pub fn read_master(mut master: File) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        match master.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            // linux report EIO after the last slave closed
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(buf)
+}
+
+/// Remove CSI, OSC and other escape sequences
+pub fn strip_ansi(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&ch) {
+                        break;
+                    }
+                }
+            },
+            Some(']') => {
+                while let Some(ch) = chars.next() {
+                    if ch == '\x07' {
+                        break;
+                    }
+                    if ch == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::{Command, Context};
+
+    fn run(line: Value) -> Value {
+        Command::parse(&line.to_string())
+            .and_then(|cmd| cmd.run(&mut Context::default()))
+            .unwrap()
+    }
+
+    #[test]
+    fn stdout_is_tty() {
+        let builder = json!({"args": ["-c", "test -t 1"]});
+        let pty = run(json!({"command_pty": {"prog": "sh", "builder": builder}}));
+        assert_eq!(pty["status"], 0);
+        let piped = run(json!({"command": ["sh", builder]}));
+        assert_eq!(piped["status"], 1);
+    }
+}
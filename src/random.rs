@@ -0,0 +1,240 @@
+//! Random generation helpers
+
+use std::collections::HashSet;
+
+use rand::{rngs::StdRng, RngCore};
+use rand_distr::Distribution;
+use serde_json::Value;
+
+use crate::Error;
+
+/// Random generator of the context, seeded for reproducible runs
+#[derive(Debug, Default)]
+pub enum ContextRng {
+    /// The thread RNG of whichever thread runs the command
+    #[default]
+    Thread,
+    Seeded(Box<StdRng>),
+}
+
+impl RngCore for ContextRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            ContextRng::Thread => rand::rng().next_u32(),
+            ContextRng::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            ContextRng::Thread => rand::rng().next_u64(),
+            ContextRng::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        match self {
+            ContextRng::Thread => rand::rng().fill_bytes(dst),
+            ContextRng::Seeded(rng) => rng.fill_bytes(dst),
+        }
+    }
+}
+
+/// Limit of generated lengths, against a typo'd length
+pub const MAX_LEN: usize = 1 << 20;
+
+pub fn check_len(len: usize) -> Result<(), Error> {
+    if len > MAX_LEN {
+        return Err(Error::InvalidArgument(format!(
+            "length {len} exceeds the limit {MAX_LEN}",
+        )));
+    }
+    Ok(())
+}
+
+/// One value, or an array of `count` values
+pub fn repeat<F, T>(count: Option<usize>, mut f: F) -> Result<Value, Error>
+where F: FnMut() -> T,
+      T: Into<Value>,
+{
+    let Some(count) = count else { return Ok(f().into()) };
+    check_len(count)?;
+    Ok(std::iter::repeat_with(f).take(count).collect::<Vec<_>>().into())
+}
+
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Characters of a preset name, or the literal characters deduplicated
+pub fn charset(charset: &str) -> Result<Vec<char>, Error> {
+    let chars: Vec<char> = match charset {
+        "alnum" => ('A'..='Z').chain('a'..='z').chain('0'..='9').collect(),
+        "alpha" => ('A'..='Z').chain('a'..='z').collect(),
+        "digits" => ('0'..='9').collect(),
+        "hex" => ('0'..='9').chain('a'..='f').collect(),
+        "base58" => BASE58.chars().collect(),
+        "ascii_printable" => ('!'..='~').collect(),
+        _ => {
+            let mut seen = HashSet::new();
+            charset.chars().filter(|&ch| seen.insert(ch)).collect()
+        },
+    };
+    if chars.is_empty() {
+        return Err(Error::InvalidArgument("empty charset".into()));
+    }
+    Ok(chars)
+}
+
+pub fn array(value: &Value) -> Result<&Vec<Value>, Error> {
+    value.as_array()
+        .ok_or_else(|| Error::InvalidArgument("expected an array".into()))
+}
+
+fn uuid_string(uuid: u128) -> String {
+    let hex = crate::encoding::hex(&uuid.to_be_bytes());
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+pub fn uuid_v4(rng: &mut impl RngCore) -> String {
+    let mut bytes = [0; 16];
+    rng.fill_bytes(&mut bytes);
+    let uuid = u128::from_be_bytes(bytes)
+        & !(0xf << 76 | 0x3 << 62)
+        | 0x4 << 76 | 0x2 << 62;
+    uuid_string(uuid)
+}
+
+/// Generator of time-ordered UUIDv7, monotonic within the process
+///
+/// The 74 random bits act as a counter within the same millisecond
+/// (or when the clock goes backwards), seeded with the top bit clear
+#[derive(Debug, Default)]
+pub struct UuidV7 {
+    millis: u64,
+    counter: u128,
+}
+
+impl UuidV7 {
+    const COUNTER_BITS: u32 = 74;
+
+    pub fn next(&mut self, rng: &mut impl RngCore, now_millis: u64) -> String {
+        let max = (1 << Self::COUNTER_BITS) - 1;
+        if now_millis > self.millis || self.counter == max {
+            self.millis = now_millis.max(self.millis + u64::from(self.counter == max));
+            let mut bytes = [0; 16];
+            rng.fill_bytes(&mut bytes);
+            self.counter = u128::from_be_bytes(bytes) & (max >> 1);
+        } else {
+            self.counter += 1;
+        }
+        let rand_a = self.counter >> 62;
+        let rand_b = self.counter & ((1 << 62) - 1);
+        let uuid = u128::from(self.millis & ((1 << 48) - 1)) << 80
+            | 0x7 << 76 | rand_a << 64
+            | 0x2 << 62 | rand_b;
+        uuid_string(uuid)
+    }
+}
+
+/// Canonical form, version and variant of a UUID
+///
+/// Accepts hyphenated or plain hex in any case,
+/// optionally braced or prefixed with `urn:uuid:`
+pub fn uuid_parse(text: &str) -> Result<Value, Error> {
+    let invalid = || Error::InvalidArgument(format!("invalid uuid {text:?}"));
+    let s = text.strip_prefix("urn:uuid:").unwrap_or(text);
+    let s = s.strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s);
+    let digits: String = match s.len() {
+        32 => s.into(),
+        36 if [8, 13, 18, 23].iter().all(|&i| s.as_bytes()[i] == b'-') => {
+            s.chars().filter(|&ch| ch != '-').collect()
+        },
+        _ => return Err(invalid()),
+    };
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let uuid = u128::from_str_radix(&digits, 16).map_err(|_| invalid())?;
+    let variant = match (uuid >> 61) & 0x7 {
+        0b000..=0b011 => "ncs",
+        0b100 | 0b101 => "rfc4122",
+        0b110 => "microsoft",
+        _ => "future",
+    };
+    Ok(serde_json::json!({
+        "uuid": uuid_string(uuid),
+        "version": (uuid >> 76) & 0xf,
+        "variant": variant,
+    }))
+}
+
+/// Samples of a distribution, invalid parameters are errors
+pub fn sample<D>(
+    rng: &mut ContextRng,
+    dist: Result<D, impl std::fmt::Display>,
+    count: Option<usize>,
+) -> Result<Value, Error>
+where D: Distribution<f64>,
+{
+    let dist = dist.map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    repeat(count, || dist.sample(rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charset_presets() {
+        assert_eq!(charset("alnum").unwrap().len(), 62);
+        assert_eq!(charset("alpha").unwrap().len(), 52);
+        assert_eq!(charset("digits").unwrap().iter().collect::<String>(), "0123456789");
+        assert_eq!(charset("hex").unwrap().iter().collect::<String>(), "0123456789abcdef");
+        let base58 = charset("base58").unwrap();
+        assert_eq!(base58.len(), 58);
+        assert!(!base58.iter().any(|ch| "0OIl".contains(*ch)));
+        assert_eq!(charset("ascii_printable").unwrap().len(), 94);
+        assert_eq!(charset("abcab").unwrap(), ['a', 'b', 'c']);
+        assert_eq!(charset("äö").unwrap(), ['ä', 'ö']);
+        assert!(charset("").is_err());
+    }
+
+    fn uuid_shape(uuid: &str, version: char) {
+        let groups: Vec<_> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12], "{uuid}");
+        assert!(uuid.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f' | b'-')), "{uuid}");
+        assert_eq!(uuid.as_bytes()[14] as char, version, "{uuid}");
+        assert!("89ab".contains(uuid.as_bytes()[19] as char), "{uuid}");
+    }
+
+    #[test]
+    fn uuid_v4_bits() {
+        let mut rng = ContextRng::default();
+        for _ in 0..100 {
+            uuid_shape(&uuid_v4(&mut rng), '4');
+        }
+        assert_ne!(uuid_v4(&mut rng), uuid_v4(&mut rng));
+        let parsed = uuid_parse(&uuid_v4(&mut rng)).unwrap();
+        assert_eq!(parsed["version"], 4);
+        assert_eq!(parsed["variant"], "rfc4122");
+    }
+
+    #[test]
+    fn uuid_v7_monotonic() {
+        let mut rng = ContextRng::default();
+        let mut v7 = UuidV7::default();
+        // the same millisecond, then a clock going backwards
+        let uuids: Vec<_> = [1000; 50].into_iter().chain([999; 50])
+            .map(|millis| v7.next(&mut rng, millis))
+            .collect();
+        for uuid in &uuids {
+            uuid_shape(uuid, '7');
+            assert!(uuid.starts_with("00000000-03e8-"), "{uuid}");
+        }
+        assert!(uuids.windows(2).all(|pair| pair[0] < pair[1]), "{uuids:?}");
+        let later = v7.next(&mut rng, 1001);
+        assert!(later.starts_with("00000000-03e9-") && *uuids.last().unwrap() < later);
+        assert_eq!(uuid_parse(&later).unwrap()["version"], 7);
+    }
+}
@@ -0,0 +1,74 @@
+//! Queries of SQLite databases
+
+use std::path::Path;
+
+use rusqlite::{
+    params_from_iter,
+    types::{self, ValueRef},
+    Connection, OpenFlags,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{encoding, Error};
+
+/// Open the database, created when missing unless `readonly`
+pub fn open(path: &Path, readonly: bool) -> Result<Connection, Error> {
+    let flags = if readonly {
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+    } else {
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+    };
+    Ok(Connection::open_with_flags(path, flags | OpenFlags::SQLITE_OPEN_NO_MUTEX)?)
+}
+
+/// Rows of a statement returning columns as objects by column name,
+/// otherwise `{"rows_affected": n}`
+pub fn query(connection: &Connection, sql: &str, params: &[Value]) -> Result<Value, Error> {
+    let params = params.iter().map(param).collect::<Result<Vec<_>, _>>()?;
+    let mut statement = connection.prepare(sql)?;
+    if statement.column_count() == 0 {
+        let rows_affected = statement.execute(params_from_iter(params))?;
+        return Ok(json!({"rows_affected": rows_affected}));
+    }
+    let names: Vec<String> = statement.column_names().into_iter().map(Into::into).collect();
+    let mut rows = statement.query(params_from_iter(params))?;
+    let mut result = vec![];
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+        for (i, name) in names.iter().enumerate() {
+            object.insert(name.clone(), column(row.get_ref(i)?));
+        }
+        result.push(Value::Object(object));
+    }
+    Ok(result.into())
+}
+
+/// A parameter of a JSON value, a blob is `{"base64": ..}`
+fn param(value: &Value) -> Result<types::Value, Error> {
+    Ok(match value {
+        Value::Null => types::Value::Null,
+        Value::Bool(b) => types::Value::Integer((*b).into()),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => types::Value::Integer(n),
+            None => types::Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => types::Value::Text(s.clone()),
+        Value::Object(map) if map.len() == 1 && let Some(Value::String(data)) = map.get("base64") => {
+            types::Value::Blob(encoding::base64_decode(data)?)
+        },
+        _ => return Err(Error::InvalidArgument(format!(
+            "unsupported parameter {value}, expected a number, string, bool, null or {{\"base64\": string}}",
+        ))),
+    })
+}
+
+/// JSON of a column, a blob is `{"base64": ..}` and invalid UTF-8 text is replaced
+fn column(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(n) => n.into(),
+        ValueRef::Real(n) => n.into(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+        ValueRef::Blob(blob) => json!({"base64": encoding::base64(blob)}),
+    }
+}
@@ -0,0 +1,465 @@
+//! Platform specific system information
+
+use std::ffi::OsString;
+
+use crate::Error;
+
+#[cfg(unix)]
+pub fn hostname() -> Result<OsString, Error> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut buf = vec![0u8; 256];
+    loop {
+        let res = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if res == 0 {
+            if let Some(len) = buf.iter().position(|&b| b == 0) {
+                buf.truncate(len);
+                return Ok(OsString::from_vec(buf));
+            }
+        } else if std::io::Error::last_os_error().raw_os_error() != Some(libc::ENAMETOOLONG) {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // truncated
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+#[cfg(windows)]
+pub fn hostname() -> Result<OsString, Error> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows_sys::Win32::System::SystemInformation::{
+        ComputerNamePhysicalDnsHostname, GetComputerNameExW,
+    };
+
+    let mut size = 0;
+    unsafe { GetComputerNameExW(ComputerNamePhysicalDnsHostname, std::ptr::null_mut(), &mut size) };
+    let mut buf = vec![0u16; size as usize];
+    let ok = unsafe {
+        GetComputerNameExW(ComputerNamePhysicalDnsHostname, buf.as_mut_ptr(), &mut size)
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    buf.truncate(size as usize);
+    Ok(OsString::from_wide(&buf))
+}
+
+/// Canonical name of the host, `None` when it cannot be resolved
+#[cfg(all(feature = "net", unix))]
+pub fn fqdn() -> Result<Option<String>, Error> {
+    use std::{ffi::{CStr, CString}, os::unix::ffi::OsStringExt, ptr::null_mut};
+
+    let Ok(host) = CString::new(hostname()?.into_vec()) else { return Ok(None) };
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_flags = libc::AI_CANONNAME;
+    hints.ai_family = libc::AF_UNSPEC;
+    let mut info = null_mut();
+    if unsafe { libc::getaddrinfo(host.as_ptr(), std::ptr::null(), &hints, &mut info) } != 0 {
+        return Ok(None);
+    }
+    let name = unsafe { (*info).ai_canonname };
+    let fqdn = (!name.is_null()).then(|| {
+        unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+    });
+    unsafe { libc::freeaddrinfo(info) };
+    Ok(fqdn)
+}
+
+#[cfg(all(feature = "net", windows))]
+pub fn fqdn() -> Result<Option<String>, Error> {
+    use windows_sys::Win32::System::SystemInformation::{
+        ComputerNameDnsFullyQualified, GetComputerNameExW,
+    };
+
+    let mut size = 0;
+    unsafe { GetComputerNameExW(ComputerNameDnsFullyQualified, std::ptr::null_mut(), &mut size) };
+    let mut buf = vec![0u16; size as usize];
+    let ok = unsafe {
+        GetComputerNameExW(ComputerNameDnsFullyQualified, buf.as_mut_ptr(), &mut size)
+    };
+    if ok == 0 {
+        return Ok(None);
+    }
+    buf.truncate(size as usize);
+    Ok(Some(String::from_utf16_lossy(&buf)))
+}
+
+/// `release` field of uname
+#[cfg(unix)]
+pub fn kernel_version() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } == -1 {
+        return None;
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+pub fn kernel_version() -> Option<String> {
+    None
+}
+
+/// Parse the `KEY=value` lines of os-release(5)
+#[cfg(target_os = "linux")]
+pub fn parse_os_release(text: &str) -> Vec<(&str, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let value = match value.as_bytes() {
+                [q @ (b'"' | b'\''), .., end] if end == q => {
+                    let inner = &value[1..value.len()-1];
+                    if *q == b'"' {
+                        let mut s = String::with_capacity(inner.len());
+                        let mut chars = inner.chars();
+                        while let Some(ch) = chars.next() {
+                            match ch {
+                                '\\' => s.extend(chars.next()),
+                                _ => s.push(ch),
+                            }
+                        }
+                        s
+                    } else {
+                        inner.into()
+                    }
+                },
+                _ => value.into(),
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// `(distro id, version id)`
+#[cfg(target_os = "linux")]
+pub fn os_version() -> (Option<String>, Option<String>) {
+    let Ok(text) = std::fs::read_to_string("/etc/os-release")
+        .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+    else { return (None, None) };
+    let fields = parse_os_release(&text);
+    let get = |name| fields.iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.clone());
+    (get("ID"), get("VERSION_ID"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn os_version() -> (Option<String>, Option<String>) {
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    let res = unsafe {
+        libc::sysctlbyname(
+            c"kern.osproductversion".as_ptr(),
+            buf.as_mut_ptr().cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    let version = (res == 0).then(|| {
+        let end = buf[..len].iter().position(|&b| b == 0).unwrap_or(len);
+        String::from_utf8_lossy(&buf[..end]).into_owned()
+    });
+    (Some("macos".into()), version)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn os_version() -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+#[cfg(target_os = "macos")]
+fn sysctl_value<T: Copy>(name: &std::ffi::CStr) -> Option<T> {
+    let mut value = std::mem::MaybeUninit::<T>::uninit();
+    let mut len = size_of::<T>();
+    let res = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (res == 0 && len == size_of::<T>()).then(|| unsafe { value.assume_init() })
+}
+
+/// Count of physical cores
+#[cfg(target_os = "linux")]
+pub fn physical_cpus() -> Option<usize> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    let mut cores = std::collections::HashSet::new();
+    let mut physical_id = None;
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else { continue };
+        match key.trim() {
+            "physical id" => physical_id = Some(value.trim()),
+            "core id" => { cores.insert((physical_id, value.trim())); },
+            _ => (),
+        }
+    }
+    (!cores.is_empty()).then_some(cores.len())
+}
+
+#[cfg(target_os = "macos")]
+pub fn physical_cpus() -> Option<usize> {
+    sysctl_value::<libc::c_int>(c"hw.physicalcpu").map(|n| n as usize)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn physical_cpus() -> Option<usize> {
+    None
+}
+
+/// Bytes of total, available, swap total and swap free memory
+#[derive(Debug, Default, serde::Serialize)]
+pub struct MemoryInfo {
+    pub total: Option<u64>,
+    pub available: Option<u64>,
+    pub swap_total: Option<u64>,
+    pub swap_free: Option<u64>,
+}
+
+/// Bytes of the `Name: value kB` line of a /proc file
+#[cfg(target_os = "linux")]
+fn kb_field(text: &str, name: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim() != name {
+            return None;
+        }
+        let mut parts = value.split_whitespace();
+        let value: u64 = parts.next()?.parse().ok()?;
+        Some(match parts.next() {
+            Some("kB") => value * 1024,
+            _ => value,
+        })
+    })
+}
+
+/// Parse the `Name: value kB` lines of /proc/meminfo into bytes
+#[cfg(target_os = "linux")]
+pub fn parse_meminfo(text: &str) -> MemoryInfo {
+    let get = |name| kb_field(text, name);
+    MemoryInfo {
+        total: get("MemTotal"),
+        available: get("MemAvailable").or_else(|| get("MemFree")),
+        swap_total: get("SwapTotal"),
+        swap_free: get("SwapFree"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn memory_info() -> Result<MemoryInfo, Error> {
+    Ok(parse_meminfo(&std::fs::read_to_string("/proc/meminfo")?))
+}
+
+#[cfg(target_os = "macos")]
+pub fn memory_info() -> Result<MemoryInfo, Error> {
+    let swap = sysctl_value::<libc::xsw_usage>(c"vm.swapusage");
+    Ok(MemoryInfo {
+        total: sysctl_value(c"hw.memsize"),
+        available: None,
+        swap_total: swap.map(|swap| swap.xsu_total),
+        swap_free: swap.map(|swap| swap.xsu_avail),
+    })
+}
+
+#[cfg(windows)]
+pub fn memory_info() -> Result<MemoryInfo, Error> {
+    use windows_sys::Win32::System::SystemInformation::{
+        GlobalMemoryStatusEx, MEMORYSTATUSEX,
+    };
+
+    let mut status: MEMORYSTATUSEX = unsafe { std::mem::zeroed() };
+    status.dwLength = size_of::<MEMORYSTATUSEX>() as u32;
+    if unsafe { GlobalMemoryStatusEx(&mut status) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(MemoryInfo {
+        total: Some(status.ullTotalPhys),
+        available: Some(status.ullAvailPhys),
+        swap_total: None,
+        swap_free: None,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn memory_info() -> Result<MemoryInfo, Error> {
+    Ok(MemoryInfo::default())
+}
+
+/// Resident and peak resident bytes of the bridge itself
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SelfMemory {
+    pub rss: Option<u64>,
+    pub peak_rss: Option<u64>,
+}
+
+#[cfg(target_os = "linux")]
+pub fn self_memory() -> SelfMemory {
+    let Ok(text) = std::fs::read_to_string("/proc/self/status") else {
+        return SelfMemory::default();
+    };
+    SelfMemory {
+        rss: kb_field(&text, "VmRSS"),
+        peak_rss: kb_field(&text, "VmHWM"),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn self_memory() -> SelfMemory {
+    SelfMemory::default()
+}
+
+/// 1, 5 and 15 minutes load average
+#[cfg(unix)]
+pub fn load_average() -> Option<[f64; 3]> {
+    let mut loads = [0.0; 3];
+    let res = unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) };
+    (res == 3).then_some(loads)
+}
+
+#[cfg(not(unix))]
+pub fn load_average() -> Option<[f64; 3]> {
+    None
+}
+
+/// Parse the uptime seconds of /proc/uptime, the idle field is ignored
+#[cfg(target_os = "linux")]
+pub fn parse_uptime(text: &str) -> Option<f64> {
+    text.split_whitespace().next()?.parse().ok()
+}
+
+/// Seconds since boot
+#[cfg(target_os = "linux")]
+pub fn uptime() -> Result<f64, Error> {
+    let text = std::fs::read_to_string("/proc/uptime")?;
+    parse_uptime(&text).ok_or(Error::InvalidString(text))
+}
+
+#[cfg(target_os = "macos")]
+pub fn uptime() -> Result<f64, Error> {
+    let boot = sysctl_value::<libc::timeval>(c"kern.boottime")
+        .ok_or_else(std::io::Error::last_os_error)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    Ok(now - (boot.tv_sec as f64 + boot.tv_usec as f64 / 1e6))
+}
+
+#[cfg(windows)]
+pub fn uptime() -> Result<f64, Error> {
+    use windows_sys::Win32::System::SystemInformation::GetTickCount64;
+
+    Ok(unsafe { GetTickCount64() } as f64 / 1e3)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn uptime() -> Result<f64, Error> {
+    Err(Error::Unsupported("uptime"))
+}
+
+/// `(cols, rows)` of the terminal connected to stdout, stderr or stdin
+#[cfg(unix)]
+pub fn terminal_size() -> Option<(u16, u16)> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            let res = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+            (res == 0 && size.ws_col != 0).then_some((size.ws_col, size.ws_row))
+        })
+}
+
+#[cfg(windows)]
+pub fn terminal_size() -> Option<(u16, u16)> {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleScreenBufferInfo, GetStdHandle, CONSOLE_SCREEN_BUFFER_INFO,
+        STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE].into_iter().find_map(|std| {
+        let mut info: CONSOLE_SCREEN_BUFFER_INFO = unsafe { std::mem::zeroed() };
+        let handle = unsafe { GetStdHandle(std) };
+        if unsafe { GetConsoleScreenBufferInfo(handle, &mut info) } == 0 {
+            return None;
+        }
+        let window = info.srWindow;
+        let cols = window.Right - window.Left + 1;
+        let rows = window.Bottom - window.Top + 1;
+        Some((cols as u16, rows as u16))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn os_release() {
+        let text = "\
+# comment
+NAME=\"Debian GNU/Linux\"
+ID=debian
+
+VERSION_ID='12'
+PRETTY_NAME=\"Debian \\\"bookworm\\\"\"
+HOME_URL=https://www.debian.org/
+";
+        assert_eq!(parse_os_release(text), [
+            ("NAME", "Debian GNU/Linux".into()),
+            ("ID", "debian".into()),
+            ("VERSION_ID", "12".into()),
+            ("PRETTY_NAME", "Debian \"bookworm\"".into()),
+            ("HOME_URL", "https://www.debian.org/".into()),
+        ]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn meminfo() {
+        let text = "\
+MemTotal:       16318504 kB
+MemFree:         1024000 kB
+MemAvailable:    8159252 kB
+SwapTotal:             0 kB
+SwapFree:              0 kB
+HugePages_Total:       0
+";
+        let info = parse_meminfo(text);
+        assert_eq!(info.total, Some(16318504 * 1024));
+        assert_eq!(info.available, Some(8159252 * 1024));
+        assert_eq!(info.swap_total, Some(0));
+        assert_eq!(info.swap_free, Some(0));
+        assert_eq!(kb_field(text, "HugePages_Total"), Some(0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn meminfo_without_available() {
+        let info = parse_meminfo("MemTotal: 2048 kB\nMemFree: 1024 kB\n");
+        assert_eq!(info.available, Some(1024 * 1024));
+        assert_eq!(info.swap_total, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn uptime_fields() {
+        assert_eq!(parse_uptime("350735.47 234388.90\n"), Some(350735.47));
+        assert_eq!(parse_uptime("12.5"), Some(12.5));
+        assert_eq!(parse_uptime(""), None);
+        assert_eq!(parse_uptime("up 3 days"), None);
+    }
+
+    #[test]
+    fn hostname_is_trimmed() {
+        let name = hostname().unwrap().into_string().unwrap();
+        assert!(!name.is_empty());
+        assert!(!name.contains(char::is_whitespace), "{name:?}");
+    }
+}
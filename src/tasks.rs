@@ -0,0 +1,68 @@
+//! Commands running in the background on a worker thread
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{Command, Context, Error};
+
+#[derive(Debug)]
+pub struct Task {
+    cmd: Arc<Command>,
+    result: mpsc::Receiver<Result<Value, Error>>,
+    done: Option<Result<Value, Error>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Task {
+    /// Run `cmd` on `ctx`, a context detached from the state
+    pub fn spawn(cmd: Command, mut ctx: Context) -> Self {
+        let cmd = Arc::new(cmd);
+        let cancel = Arc::new(AtomicBool::new(false));
+        ctx.cancel = Some(cancel.clone());
+        let (send, result) = mpsc::channel();
+        let worker_cmd = cmd.clone();
+        thread::spawn(move || {
+            _ = send.send(worker_cmd.run(&mut ctx));
+        });
+        Task { cmd, result, done: None, cancel }
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.cmd
+    }
+
+    pub fn is_done(&mut self) -> bool {
+        if self.done.is_none() {
+            self.done = self.result.try_recv().ok();
+        }
+        self.done.is_some()
+    }
+
+    /// The result of the command, disconnected when the worker died without one
+    pub fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Result<Value, Error>, mpsc::RecvTimeoutError> {
+        if let Some(done) = self.done.take() {
+            return Ok(done);
+        }
+        match timeout {
+            Some(timeout) => self.result.recv_timeout(timeout),
+            None => self.result.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Ask the command to stop, seen by `sleep`, `sleep_until` and `download`,
+    /// any other command is abandoned and runs to its end
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,327 @@
+//! Interactive input of the bridge's own terminal
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+use rustyline::{
+    config::{Behavior, Config},
+    error::ReadlineError,
+    history::{FileHistory, History},
+    DefaultEditor,
+};
+
+use crate::{output::Output, Error};
+
+/// The controlling terminal, written even when stdio is redirected
+pub fn tty() -> Option<File> {
+    let path = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+    OpenOptions::new().write(true).open(path).ok()
+}
+
+/// Show `text` on the terminal, on `fallback` without one
+pub fn write_prompt(fallback: &Output, text: &str) -> io::Result<()> {
+    match tty() {
+        Some(mut tty) => {
+            tty.write_all(text.as_bytes())?;
+            tty.flush()
+        },
+        None => {
+            let mut out = fallback.lock();
+            out.write_all(text.as_bytes())?;
+            out.flush()
+        },
+    }
+}
+
+/// One line without its line ending, `None` at EOF,
+/// `trim` also strips the surrounding whitespace
+pub fn read_line(from: &mut dyn BufRead, trim: bool) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if from.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if trim {
+        return Ok(Some(line.trim().into()));
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+/// Ask `text` and read the answer of `from`, `None` at EOF
+pub fn prompt(fallback: &Output, from: &mut dyn BufRead, text: &str, trim: bool) -> io::Result<Option<String>> {
+    write_prompt(fallback, text)?;
+    read_line(from, trim)
+}
+
+/// Echo of the terminal on stdin disabled until dropped
+pub struct EchoOff {
+    #[cfg(unix)]
+    fd: libc::c_int,
+    #[cfg(unix)]
+    saved: libc::termios,
+    #[cfg(windows)]
+    saved: u32,
+}
+
+#[cfg(unix)]
+impl EchoOff {
+    pub fn new() -> io::Result<Self> {
+        Self::on(libc::STDIN_FILENO)
+    }
+
+    /// Echo of the terminal `fd` disabled until dropped
+    fn on(fd: libc::c_int) -> io::Result<Self> {
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut flags = saved;
+        flags.c_lflag &= !libc::ECHO;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EchoOff { fd, saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+    }
+}
+
+#[cfg(windows)]
+impl EchoOff {
+    pub fn new() -> io::Result<Self> {
+        use windows_sys::Win32::System::Console::{
+            GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_ECHO_INPUT, STD_INPUT_HANDLE,
+        };
+
+        let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        let mut saved = 0;
+        if unsafe { GetConsoleMode(handle, &mut saved) } == 0
+            || unsafe { SetConsoleMode(handle, saved & !ENABLE_ECHO_INPUT) } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EchoOff { saved })
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        use windows_sys::Win32::System::Console::{GetStdHandle, SetConsoleMode, STD_INPUT_HANDLE};
+
+        unsafe { SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.saved) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+impl EchoOff {
+    pub fn new() -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Password {
+    /// Read with the echo disabled
+    Hidden(String),
+    /// Read as is from a stdin that is not a terminal
+    Echoed(String),
+}
+
+/// A line of `from` with the echo of the terminal disabled when `is_tty`, `None` at EOF
+pub fn read_password(from: &mut dyn BufRead, is_tty: bool) -> io::Result<Option<Password>> {
+    if !is_tty {
+        return Ok(read_line(from, false)?.map(Password::Echoed));
+    }
+    let _echo_off = EchoOff::new()?;
+    Ok(read_line(from, false)?.map(Password::Hidden))
+}
+
+pub enum Readline {
+    Line(String),
+    Interrupted,
+    Eof,
+}
+
+fn readline_error(e: ReadlineError) -> io::Error {
+    match e {
+        ReadlineError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+/// A line edited on the terminal with `history`,
+/// read as a plain line when stdin is not a terminal
+pub fn readline(fallback: &Output, prompt: &str, history: &[String]) -> io::Result<Readline> {
+    if !io::stdin().is_terminal() {
+        write_prompt(fallback, prompt)?;
+        return Ok(match read_line(&mut io::stdin().lock(), false)? {
+            Some(line) => Readline::Line(line),
+            None => Readline::Eof,
+        });
+    }
+    // the terminal even when stdout is redirected
+    let config = Config::builder().behavior(Behavior::PreferTerm).build();
+    let mut editor = DefaultEditor::with_config(config).map_err(readline_error)?;
+    for entry in history {
+        editor.add_history_entry(entry).map_err(readline_error)?;
+    }
+    match editor.readline(prompt) {
+        Ok(line) => Ok(Readline::Line(line)),
+        Err(ReadlineError::Interrupted) => Ok(Readline::Interrupted),
+        Err(ReadlineError::Eof) => Ok(Readline::Eof),
+        Err(e) => Err(readline_error(e)),
+    }
+}
+
+/// Entries of a history file of rustyline, or one entry per line
+pub fn load_history(path: &Path) -> io::Result<Vec<String>> {
+    let mut history = FileHistory::new();
+    history.load(path).map_err(readline_error)?;
+    Ok(history.iter().cloned().collect())
+}
+
+#[cfg(feature = "fs-write")]
+pub fn save_history(path: &Path, entries: &[String]) -> io::Result<()> {
+    let mut history = FileHistory::new();
+    for entry in entries {
+        history.add(entry).map_err(readline_error)?;
+    }
+    history.save(path).map_err(readline_error)
+}
+
+/// Times an invalid answer of `confirm` is asked again
+pub const CONFIRM_ATTEMPTS: usize = 3;
+
+/// Ask `text` until answered y/yes/n/no, `default` on an empty answer or EOF
+pub fn confirm(
+    fallback: &Output,
+    from: &mut dyn BufRead,
+    text: &str,
+    default: Option<bool>,
+) -> Result<bool, Error> {
+    let choices = match default {
+        Some(true) => "[Y/n]",
+        Some(false) => "[y/N]",
+        None => "[y/n]",
+    };
+    let mut answer = String::new();
+    for _ in 0..CONFIRM_ATTEMPTS {
+        write_prompt(fallback, &format!("{text} {choices} "))?;
+        let Some(line) = read_line(from, true)? else {
+            return default.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "no answer to confirm").into()
+            });
+        };
+        answer = line.to_lowercase();
+        match &*answer {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => (),
+        }
+        if let ("", Some(default)) = (&*answer, default) {
+            return Ok(default);
+        }
+    }
+    Err(Error::InvalidArgument(format!("invalid answer {answer:?}, expected yes or no")))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::ptr::null_mut;
+
+    use super::*;
+
+    #[test]
+    fn password_without_tty() {
+        let mut input = "secret\r\nnext\n".as_bytes();
+        let password = read_password(&mut input, false).unwrap();
+        assert_eq!(password, Some(Password::Echoed("secret".into())));
+        assert_eq!(read_line(&mut input, false).unwrap().as_deref(), Some("next"));
+        assert_eq!(read_password(&mut input, false).unwrap(), None);
+    }
+
+    fn confirm_with(input: &str, default: Option<bool>) -> Result<bool, Error> {
+        confirm(&Output::new(io::sink()), &mut input.as_bytes(), "go on?", default)
+    }
+
+    #[test]
+    fn confirm_answers() {
+        assert!(confirm_with("Y\n", None).unwrap());
+        assert!(confirm_with(" yes \n", Some(false)).unwrap());
+        assert!(!confirm_with("No\n", Some(true)).unwrap());
+        assert!(confirm_with("nope\nyes\n", None).unwrap());
+    }
+
+    #[test]
+    fn confirm_default() {
+        assert!(confirm_with("\n", Some(true)).unwrap());
+        assert!(!confirm_with("", Some(false)).unwrap());
+        // without a default an empty answer is asked again
+        assert!(!confirm_with("\nn\n", None).unwrap());
+        assert_eq!(confirm_with("", None).unwrap_err().kind(), "unexpected_eof");
+    }
+
+    #[test]
+    fn confirm_gives_up() {
+        let e = confirm_with("a\nb\nc\nyes\n", None).unwrap_err();
+        assert_eq!(e.kind(), "invalid_argument");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn echo_off_restores() {
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, null_mut(), null_mut(), null_mut())
+        };
+        assert_eq!(opened, 0, "{}", io::Error::last_os_error());
+        let flags = || {
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            assert_eq!(unsafe { libc::tcgetattr(slave, &mut termios) }, 0);
+            termios.c_lflag
+        };
+        let before = flags();
+        assert_ne!(before & libc::ECHO, 0);
+        let echo_off = EchoOff::on(slave).unwrap();
+        assert_eq!(flags() & libc::ECHO, 0);
+        assert_eq!(flags() | libc::ECHO, before);
+        drop(echo_off);
+        assert_eq!(flags(), before);
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn prompt_answers() {
+        let captured = crate::output::Captured::default();
+        let fallback = Output::new(captured.clone());
+        let mut input = "  spaced  \r\n  kept  \r\nlast".as_bytes();
+        assert_eq!(prompt(&fallback, &mut input, "name? ", true).unwrap().as_deref(), Some("spaced"));
+        assert_eq!(prompt(&fallback, &mut input, "raw? ", false).unwrap().as_deref(), Some("  kept  "));
+        assert_eq!(prompt(&fallback, &mut input, "last? ", false).unwrap().as_deref(), Some("last"));
+        assert_eq!(prompt(&fallback, &mut input, "eof? ", true).unwrap(), None);
+        assert_eq!(read_line(&mut "\r\n".as_bytes(), false).unwrap().as_deref(), Some(""));
+        assert_eq!(read_line(&mut "lone\r".as_bytes(), false).unwrap().as_deref(), Some("lone\r"));
+        // on the terminal when there is one
+        if tty().is_none() {
+            assert_eq!(captured.text(), "name? raw? last? eof? ");
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Time formatting and parsing
+
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use time::{
+    format_description::{
+        self,
+        well_known::{Iso8601, Rfc2822, Rfc3339},
+        OwnedFormatItem,
+    },
+    parsing::Parsed,
+    OffsetDateTime, PrimitiveDateTime, UtcOffset,
+};
+
+use crate::Error;
+
+/// Seconds since the unix epoch,
+/// the string form keep the precision of nanoseconds
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Epoch {
+    Float(f64),
+    Text(String),
+}
+
+impl Epoch {
+    pub fn to_datetime(&self) -> Result<OffsetDateTime, Error> {
+        let nanos = match self {
+            &Epoch::Float(secs) => (secs * 1e9) as i128,
+            Epoch::Text(text) => {
+                let invalid = || Error::InvalidString(text.clone());
+                let (secs, frac) = text.split_once('.').unwrap_or((text, ""));
+                let negative = secs.starts_with('-');
+                let secs: i128 = secs.parse().map_err(|_| invalid())?;
+                if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                let frac: i128 = format!("{frac:0<9}").parse().map_err(|_| invalid())?;
+                secs.checked_mul(1_000_000_000)
+                    .and_then(|nanos| nanos.checked_add(if negative { -frac } else { frac }))
+                    .ok_or_else(|| Error::InvalidTime(format!("epoch {text} is out of range")))?
+            },
+        };
+        OffsetDateTime::from_unix_timestamp_nanos(nanos)
+            .map_err(|e| Error::InvalidTime(e.to_string()))
+    }
+}
+
+static LOCAL_OFFSET: OnceLock<Option<UtcOffset>> = OnceLock::new();
+
+/// Determine the local offset, call it before any thread is spawned
+///
+/// The offset cannot be soundly determined in a multi-threaded process on
+/// some platforms, so that it is computed once and cached
+pub fn init_local_offset() -> Option<UtcOffset> {
+    *LOCAL_OFFSET.get_or_init(|| UtcOffset::current_local_offset().ok())
+}
+
+/// Offset of the local timezone, UTC and `false` when it cannot be determined
+pub fn local_offset_checked() -> (UtcOffset, bool) {
+    match init_local_offset() {
+        Some(offset) => (offset, true),
+        None => (UtcOffset::UTC, false),
+    }
+}
+
+/// Offset of the local timezone, UTC when it cannot be determined
+pub fn local_offset() -> UtcOffset {
+    local_offset_checked().0
+}
+
+/// `+HH:MM` form of `offset`
+pub fn offset_string(offset: UtcOffset) -> String {
+    let (hours, minutes, _) = offset.as_hms();
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    format!("{sign}{:02}:{:02}", hours.abs(), minutes.abs())
+}
+
+/// IANA name of the local timezone from `TZ` or `/etc/localtime`
+pub fn timezone_name() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.strip_prefix(':').unwrap_or(&tz);
+        let tz = tz.split_once("zoneinfo/").map_or(tz, |(_, name)| name);
+        if !tz.is_empty() {
+            return Some(tz.into());
+        }
+    }
+    if let Ok(link) = std::fs::read_link("/etc/localtime") {
+        let link = link.to_string_lossy();
+        if let Some((_, name)) = link.split_once("zoneinfo/") {
+            return Some(name.into());
+        }
+    }
+    std::fs::read_to_string("/etc/timezone").ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+pub fn epoch_of(datetime: OffsetDateTime) -> f64 {
+    datetime.unix_timestamp_nanos() as f64 / 1e9
+}
+
+enum Format {
+    Rfc3339,
+    Rfc2822,
+    Iso8601,
+    Custom(OwnedFormatItem),
+}
+
+impl Format {
+    /// Well-known names `rfc3339`, `rfc2822` and `iso8601`,
+    /// or the format description of the time crate, e.g. `[year]-[month]-[day]`
+    fn new(format: &str) -> Result<Self, Error> {
+        Ok(match format {
+            "rfc3339" => Format::Rfc3339,
+            "rfc2822" => Format::Rfc2822,
+            "iso8601" => Format::Iso8601,
+            _ => Format::Custom(format_description::parse_owned::<2>(format)
+                .map_err(|e| Error::InvalidTime(e.to_string()))?),
+        })
+    }
+}
+
+pub fn format(datetime: OffsetDateTime, format: &str) -> Result<String, Error> {
+    Ok(match Format::new(format)? {
+        Format::Rfc3339 => datetime.format(&Rfc3339)?,
+        Format::Rfc2822 => datetime.format(&Rfc2822)?,
+        Format::Iso8601 => datetime.format(&Iso8601::DEFAULT)?,
+        Format::Custom(items) => datetime.format(&items)?,
+    })
+}
+
+/// Parse `text`, missing offset is UTC, missing time is midnight
+pub fn parse(text: &str, format: &str) -> Result<OffsetDateTime, Error> {
+    let invalid = |e: time::error::Parse| Error::InvalidTime(e.to_string());
+    let items = match Format::new(format)? {
+        Format::Rfc3339 => return OffsetDateTime::parse(text, &Rfc3339).map_err(invalid),
+        Format::Rfc2822 => return OffsetDateTime::parse(text, &Rfc2822).map_err(invalid),
+        Format::Iso8601 => return OffsetDateTime::parse(text, &Iso8601::DEFAULT).map_err(invalid),
+        Format::Custom(items) => items,
+    };
+    let mut parsed = Parsed::new();
+    let rest = parsed.parse_item(text.as_bytes(), &items)
+        .map_err(|e| invalid(e.into()))?;
+    if !rest.is_empty() {
+        let rest = String::from_utf8_lossy(rest);
+        return Err(Error::InvalidTime(format!("unexpected trailing characters: {rest:?}")));
+    }
+    let convert = |e: time::error::TryFromParsed| invalid(e.into());
+    if parsed.offset_hour().is_some() {
+        return OffsetDateTime::try_from(parsed).map_err(convert);
+    }
+    if parsed.hour_24().is_none() && parsed.hour_12().is_none() {
+        parsed.set_hour_24(0);
+    }
+    Ok(PrimitiveDateTime::try_from(parsed)
+        .map_err(convert)?
+        .assume_offset(UtcOffset::UTC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instant() -> OffsetDateTime {
+        Epoch::Text("1700000000.123456789".into()).to_datetime().unwrap()
+    }
+
+    #[test]
+    fn rfc3339_round_trip() {
+        let text = format(instant(), "rfc3339").unwrap();
+        assert_eq!(text, "2023-11-14T22:13:20.123456789Z");
+        assert_eq!(parse(&text, "rfc3339").unwrap(), instant());
+    }
+
+    #[test]
+    fn custom_round_trip() {
+        let pattern = "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:9]";
+        let text = format(instant(), pattern).unwrap();
+        assert_eq!(text, "2023-11-14 22:13:20.123456789");
+        assert_eq!(parse(&text, pattern).unwrap(), instant());
+        // a missing time is midnight
+        let day = parse("2023-11-14", "[year]-[month]-[day]").unwrap();
+        assert_eq!(day.unix_timestamp(), 1699920000);
+    }
+
+    #[test]
+    fn epoch_out_of_range() {
+        for epoch in ["170141183460469231731687303715884105727", "-170141183460469231731687303715884105727.5"] {
+            let e = Epoch::Text(epoch.into()).to_datetime().unwrap_err();
+            assert!(matches!(e, Error::InvalidTime(_)), "{e:?}");
+        }
+        assert!(matches!(Epoch::Float(1e300).to_datetime(), Err(Error::InvalidTime(_))));
+    }
+
+    #[test]
+    fn offset_format() {
+        let offset = |h, m| offset_string(UtcOffset::from_hms(h, m, 0).unwrap());
+        assert_eq!(offset(0, 0), "+00:00");
+        assert_eq!(offset(5, 30), "+05:30");
+        assert_eq!(offset(-3, 0), "-03:00");
+        assert_eq!(offset(0, -30), "-00:30");
+        let local = offset_string(local_offset());
+        let bytes = local.as_bytes();
+        assert!(matches!(bytes, [b'+' | b'-', h1, h2, b':', m1, m2]
+            if [h1, h2, m1, m2].iter().all(|b| b.is_ascii_digit())), "{local}");
+    }
+
+    /// Run by [`local_is_utc`] with `TZ=UTC`
+    #[cfg(unix)]
+    #[test]
+    #[ignore]
+    fn tz_utc() {
+        if std::env::var("TZ").as_deref() != Ok("UTC") {
+            return;
+        }
+        assert_eq!(init_local_offset(), Some(UtcOffset::UTC));
+        let now = OffsetDateTime::now_utc();
+        let local = now.to_offset(local_offset());
+        assert_eq!(format(local, "rfc3339").unwrap(), format(now, "rfc3339").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_is_utc() {
+        // the offset is determined once per process
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "times::tests::tz_utc", "--test-threads=1"])
+            .env("TZ", "UTC")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success() && stdout.contains(" 1 passed"), "{stdout}");
+    }
+}
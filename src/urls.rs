@@ -0,0 +1,147 @@
+//! Percent-encoding and URL parsing
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::Error;
+
+/// Everything except the unreserved characters of RFC 3986
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Like `encodeURI`, the reserved characters are kept as well
+const URI: &AsciiSet = &COMPONENT
+    .remove(b';').remove(b',').remove(b'/').remove(b'?').remove(b':')
+    .remove(b'@').remove(b'&').remove(b'=').remove(b'+').remove(b'$')
+    .remove(b'#').remove(b'!').remove(b'*').remove(b'\'').remove(b'(')
+    .remove(b')').remove(b'[').remove(b']');
+
+pub fn encode(text: &str, component: bool) -> String {
+    utf8_percent_encode(text, if component { COMPONENT } else { URI }).to_string()
+}
+
+/// Decode percent sequences, `+` is kept,
+/// errors report the byte offset of an invalid sequence
+pub fn decode(text: &str) -> Result<String, Error> {
+    let bytes = text.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            buf.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let byte = bytes.get(i+1..i+3)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok())
+            .ok_or_else(|| Error::InvalidArgument(format!(
+                "invalid percent sequence at offset {i}",
+            )))?;
+        buf.push(byte);
+        i += 3;
+    }
+    String::from_utf8(buf)
+        .map_err(|e| Error::InvalidArgument(format!("decoded text is not UTF-8: {e}")))
+}
+
+/// Components of a URL, `url_parse` output and `url_build` input
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UrlParts {
+    scheme: String,
+    username: Option<String>,
+    password: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    #[serde(default)]
+    path: String,
+    query: Option<String>,
+    query_pairs: Option<Vec<(String, String)>>,
+    fragment: Option<String>,
+}
+
+fn invalid(e: url::ParseError) -> Error {
+    Error::InvalidArgument(format!("invalid url: {e}"))
+}
+
+pub fn parse(text: &str) -> Result<UrlParts, Error> {
+    let url = Url::parse(text).map_err(invalid)?;
+    Ok(UrlParts {
+        scheme: url.scheme().into(),
+        username: Some(url.username()).filter(|name| !name.is_empty()).map(Into::into),
+        password: url.password().map(Into::into),
+        host: url.host_str().map(Into::into),
+        port: url.port(),
+        path: url.path().into(),
+        query: url.query().map(Into::into),
+        query_pairs: url.query().is_some().then(|| url.query_pairs().into_owned().collect()),
+        fragment: url.fragment().map(Into::into),
+    })
+}
+
+/// Build a URL, the raw `query` wins over `query_pairs`
+pub fn build(parts: &UrlParts) -> Result<String, Error> {
+    let base = match &parts.host {
+        Some(host) => format!("{}://{host}", parts.scheme),
+        None => format!("{}:", parts.scheme),
+    };
+    let mut url = Url::parse(&base).map_err(invalid)?;
+    let cannot = |what| Error::InvalidArgument(format!("cannot set {what} of {base:?}"));
+    if let Some(username) = &parts.username {
+        url.set_username(username).map_err(|_| cannot("username"))?;
+    }
+    if parts.password.is_some() {
+        url.set_password(parts.password.as_deref()).map_err(|_| cannot("password"))?;
+    }
+    if parts.port.is_some() {
+        url.set_port(parts.port).map_err(|_| cannot("port"))?;
+    }
+    url.set_path(&parts.path);
+    match (&parts.query, &parts.query_pairs) {
+        (Some(query), _) => url.set_query(Some(query)),
+        (None, Some(pairs)) => { url.query_pairs_mut().extend_pairs(pairs); },
+        (None, None) => (),
+    }
+    url.set_fragment(parts.fragment.as_deref());
+    Ok(url.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_keys_and_fragment() {
+        let text = "https://user@example.com:8080/p?a=1&a=2&b=x%20y+z#frag?q=1";
+        let parts = parse(text).unwrap();
+        assert_eq!(parts.host.as_deref(), Some("example.com"));
+        assert_eq!(parts.port, Some(8080));
+        assert_eq!(parts.username.as_deref(), Some("user"));
+        assert_eq!(parts.query.as_deref(), Some("a=1&a=2&b=x%20y+z"));
+        assert_eq!(parts.query_pairs.as_deref(), Some(&[
+            ("a".into(), "1".into()),
+            ("a".into(), "2".into()),
+            ("b".into(), "x y z".into()),
+        ][..]));
+        assert_eq!(parts.fragment.as_deref(), Some("frag?q=1"));
+        assert_eq!(build(&parts).unwrap(), text);
+
+        let pairs_only = UrlParts { query: None, fragment: None, ..parts };
+        assert_eq!(build(&pairs_only).unwrap(), "https://user@example.com:8080/p?a=1&a=2&b=x+y+z");
+    }
+
+    #[test]
+    fn space_and_plus() {
+        assert_eq!(decode("x%20y+z").unwrap(), "x y+z");
+        assert_eq!(encode("a b+c", true), "a%20b%2Bc");
+        assert_eq!(encode("a b+c", false), "a%20b+c");
+        assert_eq!(decode(&encode("?&= +ä", true)).unwrap(), "?&= +ä");
+        assert!(matches!(decode("100%"), Err(Error::InvalidArgument(msg))
+            if msg == "invalid percent sequence at offset 3"));
+        assert!(decode("%ff").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Users and groups database
+
+use serde::Serialize;
+
+use crate::Error;
+
+#[derive(Debug, Default, Serialize)]
+pub struct User {
+    pub name: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub home: Option<String>,
+    pub shell: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Group {
+    pub name: Option<String>,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupId {
+    pub gid: u32,
+    pub name: Option<String>,
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        ffi::{c_char, CStr, CString},
+        io,
+        mem::zeroed,
+        ptr::null_mut,
+    };
+
+    use super::{Group, User};
+    use crate::Error;
+
+    pub enum Key {
+        Id(u32),
+        Name(CString),
+    }
+
+    impl Key {
+        pub fn new(s: &str) -> Result<Self, Error> {
+            if let Ok(id) = s.parse() {
+                return Ok(Key::Id(id));
+            }
+            CString::new(s)
+                .map(Key::Name)
+                .map_err(|_| Error::InvalidString(s.into()))
+        }
+    }
+
+    unsafe fn string(s: *const c_char) -> Option<String> {
+        (!s.is_null()).then(|| {
+            unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+        })
+    }
+
+    /// Call a `get*_r` function, growing the buffer on ERANGE,
+    /// the not found errors are ignored
+    fn call_r<F>(mut f: F) -> Result<(), Error>
+    where F: FnMut(&mut [c_char]) -> libc::c_int,
+    {
+        let mut buf = vec![0 as c_char; 1024];
+        loop {
+            match f(&mut buf) {
+                0 | libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => {
+                    return Ok(());
+                },
+                libc::ERANGE if buf.len() < 1 << 20 => {
+                    buf.resize(buf.len() * 2, 0);
+                },
+                code => return Err(io::Error::from_raw_os_error(code).into()),
+            }
+        }
+    }
+
+    pub fn user(key: &Key) -> Result<Option<User>, Error> {
+        let mut pwd: libc::passwd = unsafe { zeroed() };
+        let mut user = None;
+        call_r(|buf| {
+            let mut result = null_mut();
+            let code = unsafe {
+                match key {
+                    Key::Id(uid) => libc::getpwuid_r(
+                        *uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result,
+                    ),
+                    Key::Name(name) => libc::getpwnam_r(
+                        name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result,
+                    ),
+                }
+            };
+            // strings point into buf, copy them out before it is reused
+            if code == 0 && !result.is_null() {
+                user = Some(unsafe {
+                    User {
+                        name: string(pwd.pw_name),
+                        uid: Some(pwd.pw_uid),
+                        gid: Some(pwd.pw_gid),
+                        home: string(pwd.pw_dir),
+                        shell: string(pwd.pw_shell),
+                    }
+                });
+            }
+            code
+        })?;
+        Ok(user)
+    }
+
+    pub fn group(key: &Key) -> Result<Option<Group>, Error> {
+        let mut grp: libc::group = unsafe { zeroed() };
+        let mut group = None;
+        call_r(|buf| {
+            let mut result = null_mut();
+            let code = unsafe {
+                match key {
+                    Key::Id(gid) => libc::getgrgid_r(
+                        *gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result,
+                    ),
+                    Key::Name(name) => libc::getgrnam_r(
+                        name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result,
+                    ),
+                }
+            };
+            if code == 0 && !result.is_null() {
+                let mut members = vec![];
+                let mut mem = grp.gr_mem;
+                while !mem.is_null() && unsafe { !(*mem).is_null() } {
+                    members.extend(unsafe { string(*mem) });
+                    mem = unsafe { mem.add(1) };
+                }
+                group = Some(Group {
+                    name: unsafe { string(grp.gr_name) },
+                    gid: grp.gr_gid,
+                    members,
+                });
+            }
+            code
+        })?;
+        Ok(group)
+    }
+}
+
+#[cfg(unix)]
+pub fn current_user() -> Result<User, Error> {
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+    let user = unix::user(&unix::Key::Id(uid))?.unwrap_or_default();
+    Ok(User { uid: Some(uid), gid: Some(gid), ..user })
+}
+
+#[cfg(windows)]
+pub fn current_user() -> Result<User, Error> {
+    use windows_sys::Win32::System::WindowsProgramming::GetUserNameW;
+
+    let mut buf = [0u16; 257];
+    let mut size = buf.len() as u32;
+    let ok = unsafe { GetUserNameW(buf.as_mut_ptr(), &mut size) };
+    let name = (ok != 0 && size > 0)
+        .then(|| String::from_utf16_lossy(&buf[..size as usize - 1]));
+    Ok(User {
+        name,
+        home: std::env::var("USERPROFILE").ok(),
+        ..Default::default()
+    })
+}
+
+/// Find user by name or uid
+#[cfg(unix)]
+pub fn lookup_user(key: &str) -> Result<Option<User>, Error> {
+    unix::user(&unix::Key::new(key)?)
+}
+
+/// Find group by name or gid
+#[cfg(unix)]
+pub fn lookup_group(key: &str) -> Result<Option<Group>, Error> {
+    unix::group(&unix::Key::new(key)?)
+}
+
+/// Primary group followed by the supplementary groups of the process
+#[cfg(unix)]
+pub fn groups() -> Result<Vec<GroupId>, Error> {
+    let primary = unsafe { libc::getgid() };
+    let mut gids = loop {
+        let len = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if len == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut gids = vec![0; len as usize];
+        let len = unsafe { libc::getgroups(len, gids.as_mut_ptr()) };
+        if len != -1 {
+            gids.truncate(len as usize);
+            break gids;
+        }
+        // the groups changed between the calls
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINVAL) {
+            return Err(e.into());
+        }
+    };
+    gids.retain(|&gid| gid != primary);
+    gids.sort_unstable();
+    gids.dedup();
+    gids.insert(0, primary);
+    gids.into_iter()
+        .map(|gid| Ok(GroupId {
+            gid,
+            name: unix::group(&unix::Key::Id(gid))?.and_then(|group| group.name),
+        }))
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn groups() -> Result<Vec<GroupId>, Error> {
+    Err(Error::Unsupported("groups"))
+}
+
+#[cfg(windows)]
+pub fn lookup_user(_key: &str) -> Result<Option<User>, Error> {
+    Err(Error::Unsupported("lookup_user"))
+}
+
+#[cfg(windows)]
+pub fn lookup_group(_key: &str) -> Result<Option<Group>, Error> {
+    Err(Error::Unsupported("lookup_group"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_round_trip() {
+        let user = current_user().unwrap();
+        let uid = user.uid.unwrap();
+        // a scratch container may have no passwd entry
+        let Some(name) = user.name else { return };
+        let by_name = lookup_user(&name).unwrap().unwrap();
+        assert_eq!(by_name.uid, Some(uid));
+        let by_uid = lookup_user(&uid.to_string()).unwrap().unwrap();
+        assert_eq!(by_uid.name.as_deref(), Some(&*name));
+        assert_eq!(by_uid.home, user.home);
+    }
+
+    #[test]
+    fn groups_start_with_primary() {
+        let groups = groups().unwrap();
+        let primary = unsafe { libc::getgid() };
+        assert_eq!(groups[0].gid, primary);
+        let mut gids: Vec<_> = groups.iter().map(|group| group.gid).collect();
+        gids.sort_unstable();
+        gids.dedup();
+        assert_eq!(gids.len(), groups.len());
+    }
+}
@@ -0,0 +1,444 @@
+//! The bridge binary against sh scripts standing in for jq
+#![cfg(unix)]
+
+use std::{
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+const BRIDGE: &str = env!("CARGO_BIN_EXE_jq-bridge");
+
+/// Empty scratch directory of the test `name`
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("jq-bridge-cli-{name}-{}", std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Executable sh script `body` in `dir`, given to the bridge as `--jq`
+fn fake_jq(dir: &Path, body: &str) -> PathBuf {
+    let path = dir.join("fake-jq");
+    fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Run the bridge with `args` and the `--jq` of `jq`, stdin is empty
+fn bridge(jq: &Path, args: &[&str]) -> Output {
+    Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(jq)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn text(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn jq_exits_without_reading() {
+    let dir = scratch("early-exit");
+    let jq = fake_jq(&dir, "printf '\"ping\"\\n{\"echo\": 1}\\n'\nexit 5\n");
+    let output = bridge(&jq, &[]);
+    let stderr = text(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert_eq!(output.status.code(), Some(5), "{stderr}");
+
+    // the answers are read and the exit code given through
+    let jq = fake_jq(&dir, "echo '{\"echo\": 1}'\nread -r answer\necho \"$answer\" >&2\n");
+    let output = bridge(&jq, &[]);
+    assert_eq!(text(&output.stderr), "{\"ok\":1}\n");
+    assert_eq!(output.status.code(), Some(0));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fifo_channel() {
+    let dir = scratch("fifo");
+    let fifos = dir.join("fifos");
+    // the answers are opened first, and stdout stays for the data
+    let jq = fake_jq(&dir, r#"
+exec 4<"$JQ_BRIDGE_OUT"
+exec 3>"$JQ_BRIDGE_IN"
+echo '{"echo": [1, "two"]}' >&3
+read -r answer <&4
+echo "answer $answer"
+echo '"ping"' >&3
+read -r answer <&4
+echo "$answer" >&2
+exec 3>&-
+"#);
+    let output = bridge(&jq, &["--channel-fifo", fifos.to_str().unwrap()]);
+    let stderr = text(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(text(&output.stdout), "answer {\"ok\":[1,\"two\"]}\n");
+    assert!(stderr.starts_with("{\"ok\":"), "{stderr}");
+    // the FIFOs are removed on exit
+    assert!(!fifos.join("in").exists() && !fifos.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Wait up to 5 s for `ready`
+fn eventually(mut ready: impl FnMut() -> bool) -> bool {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while !ready() {
+        if std::time::Instant::now() > deadline {
+            return false;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    true
+}
+
+#[test]
+fn listen() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let dir = scratch("listen");
+    let socket = dir.join("socket");
+    let mut server = Command::new(BRIDGE)
+        .arg("--listen")
+        .arg(&socket)
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+    assert!(eventually(|| UnixStream::connect(&socket).is_ok()));
+
+    // answers of `lines` on one connection
+    let session = |lines: &[String]| {
+        let stream = UnixStream::connect(&socket).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let reader = std::thread::spawn(move || {
+            BufReader::new(stream).lines().map(|line| line.unwrap()).collect::<Vec<_>>()
+        });
+        for line in lines {
+            writeln!(writer, "{line}").unwrap();
+        }
+        writer.shutdown(std::net::Shutdown::Write).unwrap();
+        reader.join().unwrap()
+    };
+    assert_eq!(session(&[r#"{"echo": 1}"#.into(), r#"{"timer_start": "t"}"#.into()]), [
+        r#"{"ok":1}"#,
+        r#"{"ok":null}"#,
+    ]);
+    // each connection has its own context
+    let second = session(&[r#"{"timer_elapsed": "t"}"#.into(), r#"{"echo": 2}"#.into()]);
+    assert!(second[0].starts_with(r#"{"err":"#), "{second:?}");
+    assert_eq!(second[1], r#"{"ok":2}"#);
+
+    // tagged answers written by their threads, of two clients at once
+    let client = |name: char| {
+        let lines: Vec<_> = (0..50)
+            .map(|i| format!(r#"{{"id": {i}, "cmd": {{"echo": "{}"}}}}"#, name.to_string().repeat(10_000 + i)))
+            .collect();
+        let answers = session(&lines);
+        assert_eq!(answers.len(), 50);
+        for answer in answers {
+            let answer = answer.strip_prefix(r#"{"id":"#).unwrap();
+            let (id, rest) = answer.split_once(r#","ok":""#).unwrap();
+            let id: usize = id.parse().unwrap();
+            assert_eq!(rest, format!("{}\"}}", name.to_string().repeat(10_000 + id)));
+        }
+    };
+    std::thread::scope(|scope| {
+        scope.spawn(|| client('a'));
+        scope.spawn(|| client('b'));
+    });
+
+    unsafe { libc::kill(server.id() as i32, libc::SIGTERM) };
+    let status = server.wait().unwrap();
+    assert!(!status.success());
+    assert!(!socket.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exit_flushes_buffered_prints() {
+    let dir = scratch("exit-flush");
+    let jq = fake_jq(&dir, r#"
+echo '{"set_buffering": {"enabled": true}}'
+echo '{"println": "buffered"}'
+echo '{"exit": 0}'
+cat >/dev/null
+"#);
+    let output = bridge(&jq, &[]);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert_eq!(text(&output.stdout), "buffered\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn prompt_default_on_eof() {
+    let dir = scratch("prompt");
+    let answers = dir.join("answers");
+    let jq = fake_jq(&dir, &format!(r#"
+echo '{{"prompt": {{"text": "name? ", "default": "anonymous"}}}}'
+read -r answer
+echo "$answer" >"{0}"
+echo '{{"prompt": {{"text": "name? "}}}}'
+read -r answer
+echo "$answer" >>"{0}"
+"#, answers.display()));
+    let output = bridge(&jq, &[]);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert_eq!(fs::read_to_string(&answers).unwrap(), "{\"ok\":\"anonymous\"}\n{\"ok\":null}\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn readline_without_tty() {
+    use std::io::Write;
+
+    let dir = scratch("readline");
+    let answers = dir.join("answers");
+    let jq = fake_jq(&dir, &format!(r#"
+for i in 1 2 3; do
+  echo '{{"readline": {{"prompt": "> ", "history_id": "h"}}}}'
+  read -r answer
+  echo "$answer" >>"{}"
+done
+"#, answers.display()));
+    let mut child = Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(&jq)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"  first line \r\nsecond\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    // read as is, null at EOF
+    let expected = "{\"ok\":\"  first line \"}\n{\"ok\":\"second\"}\n{\"ok\":null}\n";
+    assert_eq!(fs::read_to_string(&answers).unwrap(), expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn input_args() {
+    use std::io::Write;
+
+    let dir = scratch("input-args");
+    let argv = dir.join("argv");
+    let jq = fake_jq(&dir, &format!("for arg in \"$@\"; do printf '%s\\n' \"$arg\"; done >\"{}\"\n", argv.display()));
+    let json = dir.join("data.json");
+    fs::write(&json, "{\"a\": [1, 2]}\n").unwrap();
+    let raw = dir.join("notes.txt");
+    fs::write(&raw, "first \"line\"\n").unwrap();
+
+    let mut child = Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(&jq)
+        .arg("-i")
+        .arg(&json)
+        .arg("--raw-input-file")
+        .arg(&raw)
+        .args(["--input", "-", "-n", "-f", "prog.jq"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"[true, null]").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert_eq!(fs::read_to_string(&argv).unwrap(), [
+        "--argjson", "bridge_input0", r#"{"a":[1,2]}"#,
+        "--argjson", "bridge_input1", r#""first \"line\"\n""#,
+        "--argjson", "bridge_input2", "[true,null]",
+        "--argjson", "bridge_inputs", r#"[{"a":[1,2]},"first \"line\"\n",[true,null]]"#,
+        "-n", "-f", "prog.jq", "",
+    ].join("\n"));
+
+    // jq is not started without its inputs
+    fs::remove_file(&argv).unwrap();
+    fs::write(&json, "{not json").unwrap();
+    let output = bridge(&jq, &["-i", json.to_str().unwrap(), "-n"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(text(&output.stderr).contains("invalid JSON in input"), "{}", text(&output.stderr));
+    assert!(!argv.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Whether the process `pid` is gone, reaped by its parent
+#[cfg(feature = "proc")]
+fn gone(pid: i32) -> bool {
+    unsafe { libc::kill(pid, 0) != 0 }
+}
+
+#[cfg(feature = "proc")]
+#[test]
+fn sigterm_stops_children() {
+    let dir = scratch("sigterm");
+    let pids = dir.join("pids");
+    let jq = fake_jq(&dir, &format!(r#"
+echo '{{"spawn": ["sleep", {{"args": ["30"]}}]}}'
+read -r answer
+echo "$answer $$" >"{0}.tmp"
+mv "{0}.tmp" "{0}"
+exec sleep 30
+"#, pids.display()));
+    let mut server = Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(&jq)
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+    assert!(eventually(|| pids.exists()));
+    let written = fs::read_to_string(&pids).unwrap();
+    let (answer, jq_pid) = written.trim().split_once(' ').unwrap();
+    let child: i32 = answer.strip_prefix("{\"ok\":").unwrap().strip_suffix('}').unwrap().parse().unwrap();
+    let jq_pid: i32 = jq_pid.parse().unwrap();
+    assert!(!gone(child));
+
+    let started = std::time::Instant::now();
+    unsafe { libc::kill(server.id() as i32, libc::SIGTERM) };
+    let status = server.wait().unwrap();
+    // inside the grace period of 2 s, the sleep exits on its SIGTERM
+    assert!(started.elapsed() < std::time::Duration::from_secs(2), "{:?}", started.elapsed());
+    assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+    assert!(eventually(|| gone(child) && gone(jq_pid)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "proc")]
+#[test]
+fn exit_stops_jq_and_children() {
+    let dir = scratch("exit");
+    let pids = dir.join("pids");
+    // jq not exiting at the EOF of the answers
+    let jq = fake_jq(&dir, &format!(r#"
+echo '{{"spawn": ["sleep", {{"args": ["30"]}}]}}'
+read -r answer
+echo "$answer $$" >"{}"
+echo '{{"exit": 3}}'
+exec sleep 30
+"#, pids.display()));
+    let started = std::time::Instant::now();
+    let output = bridge(&jq, &[]);
+    let elapsed = started.elapsed();
+    assert_eq!(output.status.code(), Some(3), "{}", text(&output.stderr));
+    // killed after the grace period of 2 s
+    assert!(elapsed < std::time::Duration::from_secs(5), "{elapsed:?}");
+
+    let written = fs::read_to_string(&pids).unwrap();
+    let (answer, jq_pid) = written.trim().split_once(' ').unwrap();
+    let child: i32 = answer.strip_prefix("{\"ok\":").unwrap().strip_suffix('}').unwrap().parse().unwrap();
+    assert!(eventually(|| gone(child) && gone(jq_pid.parse().unwrap())));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "notify"))]
+fn notify_without_session_bus() {
+    let dir = scratch("notify");
+    let answers = dir.join("answers");
+    let jq = fake_jq(&dir, &format!(r#"
+echo '{{"notify": {{"summary": "hello", "urgency": "low", "timeout_ms": 100}}}}'
+read -r answer
+echo "$answer" >"{}"
+"#, answers.display()));
+    let missing = dir.join("no-bus");
+    let output = Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(&jq)
+        .env("DBUS_SESSION_BUS_ADDRESS", format!("unix:path={}", missing.display()))
+        .env_remove("DISPLAY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let answer: serde_json::Value = serde_json::from_str(&fs::read_to_string(&answers).unwrap()).unwrap();
+    assert_eq!(answer["err"]["kind"], "notify_unavailable", "{answer}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "clipboard"))]
+fn clipboard_without_display() {
+    let dir = scratch("clipboard");
+    let answers = dir.join("answers");
+    let jq = fake_jq(&dir, &format!(r#"
+echo '"clipboard_get"'
+read -r answer
+echo "$answer" >"{0}"
+echo '{{"clipboard_set": "text"}}'
+read -r answer
+echo "$answer" >>"{0}"
+echo '{{"clipboard_set": {{"text": "text", "wait": true}}}}'
+read -r answer
+echo "$answer" >>"{0}"
+"#, answers.display()));
+    let output = Command::new(BRIDGE)
+        .arg("--jq")
+        .arg(&jq)
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let answers = fs::read_to_string(&answers).unwrap();
+    assert_eq!(answers.lines().count(), 3, "{answers}");
+    for answer in answers.lines() {
+        let answer: serde_json::Value = serde_json::from_str(answer).unwrap();
+        assert_eq!(answer["err"]["kind"], "clipboard_unavailable", "{answer}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "open"))]
+fn open_with_handlers() {
+    let dir = scratch("open");
+    let (bin, empty, opened) = (dir.join("bin"), dir.join("empty"), dir.join("opened"));
+    fs::create_dir_all(&bin).unwrap();
+    fs::create_dir_all(&empty).unwrap();
+    for handler in ["xdg-open", "viewer"] {
+        let path = bin.join(handler);
+        fs::write(&path, format!("#!/bin/sh\necho \"{handler} $1\" >>\"{}\"\n", opened.display())).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let answers = dir.join("answers");
+    let jq = fake_jq(&dir, &format!(r#"
+echo '{{"open_url": {{"url": "HTTPS://Example.com"}}}}'
+read -r answer
+echo "$answer" >"{0}"
+echo '{{"open_path": {{"path": "{1}", "with": "viewer"}}}}'
+read -r answer
+echo "$answer" >>"{0}"
+"#, answers.display(), dir.display()));
+    let open = |path: &Path| {
+        Command::new(BRIDGE).arg("--jq").arg(&jq).env("PATH", path).stdin(Stdio::null()).output().unwrap()
+    };
+
+    let output = open(&bin);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert_eq!(fs::read_to_string(&answers).unwrap(), "{\"ok\":null}\n{\"ok\":null}\n");
+    // the handlers are not waited for
+    let expected = format!("viewer {}\nxdg-open https://example.com/\n", dir.display());
+    let mut lines = vec![];
+    assert!(eventually(|| {
+        lines = fs::read_to_string(&opened).unwrap_or_default().lines().map(String::from).collect();
+        lines.sort();
+        lines.len() == 2
+    }));
+    assert_eq!(lines.join("\n") + "\n", expected);
+
+    let output = open(&empty);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let answers = fs::read_to_string(&answers).unwrap();
+    for answer in answers.lines() {
+        let answer: serde_json::Value = serde_json::from_str(answer).unwrap();
+        assert_eq!(answer["err"]["kind"], "no_application", "{answer}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}